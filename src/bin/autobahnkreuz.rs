@@ -1,12 +1,78 @@
 extern crate autobahnkreuz;
 
-use autobahnkreuz::router::Router;
+use autobahnkreuz::router::{Router, ScramKdf, ScramPrincipal};
 extern crate env_logger;
 
+use argparse::{ArgumentParser, Store, StoreOption};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
 use std::net::ToSocketAddrs;
+use std::process;
+
+/// Prints the `wamp-scram` verifier of a principal whose password is read from stdin.
+fn scram_verifier(args: Vec<String>) {
+    let mut authid = String::new();
+    let mut role = "user".to_string();
+    let mut kdf = "argon2id13".to_string();
+    let mut iterations: Option<u32> = None;
+    let mut memory = 65536u32;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description(
+            "Generates the wamp-scram verifier of a principal. The password is read from stdin."
+        );
+        parser.refer(&mut authid)
+            .add_argument("authid", Store, "The authid of the principal")
+            .required();
+        parser.refer(&mut role)
+            .add_option(&["--role"], Store, "The authrole of the principal");
+        parser.refer(&mut kdf)
+            .add_option(&["--kdf"], Store, "The key derivation function (argon2id13 or pbkdf2)");
+        parser.refer(&mut iterations)
+            .add_option(&["--iterations"], StoreOption, "The iterations of the key derivation function");
+        parser.refer(&mut memory)
+            .add_option(&["--memory"], Store, "The memory cost of argon2id13 in KiB");
+        if let Err(code) = parser.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+
+    let kdf = match kdf.as_str() {
+        "argon2id13" => ScramKdf::Argon2id13,
+        "pbkdf2" => ScramKdf::Pbkdf2,
+        kdf => {
+            eprintln!("Unknown key derivation function {}", kdf);
+            process::exit(2);
+        }
+    };
+    let mut password = String::new();
+    if let Err(e) = io::stdin().lock().read_line(&mut password) {
+        eprintln!("Could not read the password: {}", e);
+        process::exit(1);
+    }
+    let password = password.trim_end_matches(|c| c == '\n' || c == '\r');
+    let iterations = iterations.unwrap_or_else(|| kdf.default_iterations());
+    match ScramPrincipal::new(password, &role, kdf, iterations, memory) {
+        Ok(principal) => {
+            let mut principals = HashMap::new();
+            principals.insert(authid, principal);
+            println!("{}", serde_json::to_string_pretty(&principals).unwrap());
+        }
+        Err(e) => {
+            eprintln!("Could not derive the verifier: {}", e);
+            process::exit(1);
+        }
+    }
+}
 
 #[runtime::main]
 async fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("scram-verifier") {
+        scram_verifier(args[1..].to_vec());
+        return;
+    }
+
     env_logger::init();
 
     let wamp_address = std::env::var("WAMP_ADDRESS").ok()
@@ -1,8 +1,10 @@
 use super::{ErrorType, Message, ID};
-use crate::messages::{self, Reason};
+use crate::messages::{self, ubjson, Reason};
 use rmp_serde::decode::Error as MsgPackError;
+use serde_cbor::Error as CBORError;
 use serde_json::Error as JSONError;
 use std::fmt;
+use std::io::Error as IOError;
 use std::sync::mpsc::SendError;
 use url::ParseError;
 use ws::Error as WSError;
@@ -23,9 +25,14 @@ pub enum ErrorKind {
     Closing(String),
     JSONError(JSONError),
     MsgPackError(MsgPackError),
+    CBORError(CBORError),
+    UBJSONError(ubjson::Error),
+    IOError(IOError),
     MalformedData,
     InvalidMessageType(Message),
     InvalidState(&'static str),
+    /// A peer sent a message the state of its session does not allow.
+    ProtocolViolation(String),
     Timeout,
     ErrorReason(ErrorType, ID, Reason),
 }
@@ -42,6 +49,14 @@ impl Error {
     pub fn get_kind(self) -> ErrorKind {
         self.kind
     }
+
+    /// Whether the error only refuses a request, so the session goes on.
+    pub fn is_refusal(&self) -> bool {
+        match self.kind {
+            ErrorKind::ErrorReason(..) => true,
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -59,8 +74,11 @@ impl ErrorKind {
             ErrorKind::ThreadError(ref e) => e.to_string(),
             ErrorKind::JSONError(ref e) => e.to_string(),
             ErrorKind::MsgPackError(ref e) => e.to_string(),
+            ErrorKind::CBORError(ref e) => e.to_string(),
+            ErrorKind::UBJSONError(ref e) => e.to_string(),
+            ErrorKind::IOError(ref e) => e.to_string(),
             ErrorKind::ErrorReason(_, _, ref s) => s.to_string(),
-            ErrorKind::Closing(ref s) => s.clone(),
+            ErrorKind::Closing(ref s) | ErrorKind::ProtocolViolation(ref s) => s.clone(),
             ErrorKind::UnexpectedMessage(s) | ErrorKind::InvalidState(s) => s.to_string(),
             ErrorKind::ConnectionLost => "Connection Lost".to_string(),
             ErrorKind::MalformedData => "Malformed Data".to_string(),
@@ -5,11 +5,11 @@ mod messages;
 pub mod router;
 mod utils;
 
-use self::error::*;
+pub use self::error::{Error, ErrorKind};
 
-pub use messages::{ArgDict, ArgList, CallError, Dict, InvocationPolicy, List, MatchingPolicy,
-                   Reason, Value, URI};
-use messages::{ErrorType, Message};
+pub use messages::{ArgDict, ArgList, BytesEncoding, CallError, Dict, InvocationPolicy, List,
+                   MatchingPolicy, Message, Reason, Value, URI};
+use messages::ErrorType;
 pub use router::Router;
 
 pub type CallResult<T> = Result<T, CallError>;
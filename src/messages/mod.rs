@@ -4,6 +4,7 @@ pub use crate::messages::types::*;
 use serde;
 use crate::ID;
 mod types;
+pub mod ubjson;
 
 macro_rules! try_or {
     ($e:expr, $msg:expr) => {
@@ -19,6 +20,8 @@ pub enum Message {
     Hello(URI, HelloDetails),
     Welcome(ID, WelcomeDetails),
     Abort(ErrorDetails, Reason),
+    Challenge(String, Dict),
+    Authenticate(String, Dict),
     Goodbye(ErrorDetails, Reason),
     Error(ErrorType, ID, Dict, Reason, Option<List>, Option<Dict>),
     Subscribe(ID, SubscribeOptions, URI),
@@ -68,6 +71,12 @@ impl serde::Serialize for Message {
                 (2, session, details).serialize(serializer)
             }
             Message::Abort(ref details, ref reason) => (3, details, reason).serialize(serializer),
+            Message::Challenge(ref authmethod, ref extra) => {
+                (4, authmethod, extra).serialize(serializer)
+            }
+            Message::Authenticate(ref signature, ref extra) => {
+                (5, signature, extra).serialize(serializer)
+            }
             Message::Goodbye(ref details, ref reason) => (6, details, reason).serialize(serializer),
             Message::Error(ref ty, id, ref details, ref reason, ref args, ref kwargs) => {
                 serialize_with_args!(args, kwargs, serializer, 8, ty, id, details, reason)
@@ -183,6 +192,36 @@ impl MessageVisitor {
         Ok(Message::Abort(details, reason))
     }
 
+    fn visit_challenge<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
+    where
+        V: serde::de::SeqAccess<'de>,
+    {
+        let authmethod = try_or!(
+            visitor.next_element(),
+            "Challenge message ended before auth method"
+        );
+        let extra = try_or!(
+            visitor.next_element(),
+            "Challenge message ended before extra dict"
+        );
+        Ok(Message::Challenge(authmethod, extra))
+    }
+
+    fn visit_authenticate<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
+    where
+        V: serde::de::SeqAccess<'de>,
+    {
+        let signature = try_or!(
+            visitor.next_element(),
+            "Authenticate message ended before signature"
+        );
+        let extra = try_or!(
+            visitor.next_element(),
+            "Authenticate message ended before extra dict"
+        );
+        Ok(Message::Authenticate(signature, extra))
+    }
+
     fn visit_goodbye<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
     where
         V: serde::de::SeqAccess<'de>,
@@ -514,6 +553,8 @@ impl<'de> serde::de::Visitor<'de> for MessageVisitor {
             1 => self.visit_hello(visitor),
             2 => self.visit_welcome(visitor),
             3 => self.visit_abort(visitor),
+            4 => self.visit_challenge(visitor),
+            5 => self.visit_authenticate(visitor),
             6 => self.visit_goodbye(visitor),
             8 => self.visit_error(visitor),
             32 => self.visit_subscribe(visitor),
@@ -538,11 +579,11 @@ impl<'de> serde::de::Visitor<'de> for MessageVisitor {
 
 #[cfg(test)]
 mod test {
-    use super::types::{CallOptions, ClientRoles, ErrorDetails, ErrorType, EventDetails,
-                       HelloDetails, InvocationDetails, PublishOptions, Reason, RegisterOptions,
-                       ResultDetails, RouterRoles, SubscribeOptions, Value, WelcomeDetails,
-                       YieldOptions, URI};
-    use super::Message;
+    use super::types::{ArgList, CallOptions, ClientRoles, ErrorDetails, ErrorType, EventDetails,
+                       HelloDetails, InvocationDetails, PublishOptions, Reason,
+                       RegisterOptions, ResultDetails, RouterRoles, SubscribeOptions, Value,
+                       WelcomeDetails, YieldOptions, URI};
+    use super::{ubjson, with_bytes_encoding, BytesEncoding, Message};
     use rmp_serde::Deserializer as RMPDeserializer;
     use rmp_serde::Serializer;
     use serde::{Deserialize, Serialize};
@@ -556,12 +597,16 @@ mod test {
             assert_eq!(serde_json::to_string(&message).unwrap(), $s);
             assert_eq!(serde_json::from_str::<Message>($s).unwrap(), message);
             let mut buf: Vec<u8> = Vec::new();
-            message
-                .serialize(&mut Serializer::with(&mut buf, StructMapWriter))
-                .unwrap();
+            with_bytes_encoding(BytesEncoding::Binary, || {
+                message.serialize(&mut Serializer::with(&mut buf, StructMapWriter))
+            }).unwrap();
             let mut de = RMPDeserializer::new(&buf[..]);
             let new_message: Message = Deserialize::deserialize(&mut de).unwrap();
             assert_eq!(new_message, message);
+            let buf = with_bytes_encoding(BytesEncoding::Binary, || serde_cbor::to_vec(&message)).unwrap();
+            assert_eq!(serde_cbor::from_slice::<Message>(&buf).unwrap(), message);
+            let buf = ubjson::to_vec(&message).unwrap();
+            assert_eq!(ubjson::from_slice::<Message>(&buf).unwrap(), message);
         }};
     }
 
@@ -577,6 +622,22 @@ mod test {
         )
     }
 
+    #[test]
+    fn deserialize_hello_roles() {
+        let message = serde_json::from_str::<Message>(
+            "[1,\"ca.dal.wamp.test\",{\"roles\":{\"subscriber\":{\"features\":{\"publisher_identification\":true}}}}]"
+        ).unwrap();
+        match message {
+            Message::Hello(_, details) => {
+                assert!(details.roles.publisher.is_none());
+                assert!(details.roles.subscriber.is_some());
+                assert!(details.roles.publisher_identification());
+                assert!(!details.roles.is_empty());
+            }
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
     #[test]
     fn serialize_welcome() {
         two_way_test!(
@@ -585,7 +646,7 @@ mod test {
         );
         two_way_test!(
             Message::Welcome(493782, WelcomeDetails::new_with_agent(RouterRoles::new(), "dal_wamp")),
-            "[2,493782,{\"agent\":\"dal_wamp\",\"roles\":{\"dealer\":{\"features\":{\"pattern_based_registration\":true}},\"broker\":{\"features\":{\"pattern_based_subscription\":true}}}}]"
+            "[2,493782,{\"agent\":\"dal_wamp\",\"roles\":{\"dealer\":{\"features\":{\"session_meta_api\":true}},\"broker\":{\"features\":{\"pattern_based_subscription\":true,\"publisher_identification\":true,\"session_meta_api\":true}}}}]"
         );
     }
 
@@ -604,6 +665,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn serialize_challenge() {
+        let mut extra = HashMap::new();
+        extra.insert("challenge".to_string(), Value::String("{\"nonce\":\"abc\"}".to_string()));
+        two_way_test!(
+            Message::Challenge("wampcra".to_string(), extra),
+            "[4,\"wampcra\",{\"challenge\":\"{\\\"nonce\\\":\\\"abc\\\"}\"}]"
+        );
+    }
+
+    #[test]
+    fn serialize_authenticate() {
+        two_way_test!(
+            Message::Authenticate("8xAPKbPsxfCFKz6H1roFoJf8DBlJFWc2j8HYr3YUB+Y=".to_string(), HashMap::new()),
+            "[5,\"8xAPKbPsxfCFKz6H1roFoJf8DBlJFWc2j8HYr3YUB+Y=\",{}]"
+        );
+    }
+
     #[test]
     fn serialize_goodbye() {
         two_way_test!(
@@ -670,6 +749,27 @@ mod test {
         )
     }
 
+    #[test]
+    fn serialize_durable_subscribe() {
+        let mut options = SubscribeOptions::new();
+        options.durable = true;
+        options.name = Some("commands".to_string());
+        two_way_test!(
+            Message::Subscribe(58944, options, URI::new("ca.dal.test.the_sub")),
+            "[32,58944,{\"durable\":true,\"name\":\"commands\"},\"ca.dal.test.the_sub\"]"
+        )
+    }
+
+    #[test]
+    fn deserialize_cbor_subscribe() {
+        // [32, 1, {}, "com.x"] as encoded by a CBOR library of a device
+        let payload = [0x84, 0x18, 0x20, 0x01, 0xA0, 0x65, b'c', b'o', b'm', b'.', b'x'];
+        assert_eq!(
+            serde_cbor::from_slice::<Message>(&payload).unwrap(),
+            Message::Subscribe(1, SubscribeOptions::new(), URI::new("com.x"))
+        );
+    }
+
     #[test]
     fn serialize_subscribed() {
         two_way_test!(Message::Subscribed(47853, 48975938), "[33,47853,48975938]")
@@ -722,6 +822,76 @@ mod test {
         )
     }
 
+    #[test]
+    fn serialize_publish_null_binary_and_integers() {
+        two_way_test!(
+            Message::Publish(
+                453453,
+                PublishOptions::new(false),
+                URI::new("ca.dal.test.topic1"),
+                Some(vec![
+                    Value::Null,
+                    Value::Bytes(vec![0, 1, 2, 255]),
+                    Value::UnsignedInteger(u64::max_value()),
+                    Value::Integer(i64::min_value()),
+                ]),
+                None
+            ),
+            "[16,453453,{},\"ca.dal.test.topic1\",[null,\"\\u0000AAEC/w==\",18446744073709551615,-9223372036854775808]]"
+        );
+        let bytes = Value::Bytes(vec![1, 2]);
+        with_bytes_encoding(BytesEncoding::Binary, || {
+            assert_eq!(serde_cbor::to_vec(&bytes).unwrap(), [0x42, 1, 2]);
+            assert_eq!(rmp_serde::to_vec(&bytes).unwrap(), [0xC4, 2, 1, 2]);
+        });
+        assert_eq!(rmp_serde::to_vec(&bytes).unwrap(), [0xA5, 0, b'A', b'Q', b'I', b'=']);
+        assert!(serde_json::from_str::<Value>("\"\\u0000not base64!\"").is_err());
+    }
+
+    #[test]
+    fn deserialize_msgpack_binary() {
+        // [16, 1, {}, "t", [bin(0x01 0x02), nil, int8(7)]]
+        let payload = [
+            0x95, 0x10, 0x01, 0x80, 0xA1, b't', 0x93, 0xC4, 0x02, 0x01, 0x02, 0xC0, 0xD0, 0x07,
+        ];
+        let mut de = RMPDeserializer::new(&payload[..]);
+        let message: Message = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(
+            message,
+            Message::Publish(
+                1,
+                PublishOptions::new(false),
+                URI::new("t"),
+                Some(vec![Value::Bytes(vec![1, 2]), Value::Null, Value::UnsignedInteger(7)]),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn deserialize_publish_disclose_me() {
+        let message = serde_json::from_str::<Message>("[16,1,{\"disclose_me\":true},\"t\"]").unwrap();
+        match message {
+            Message::Publish(_, options, _, _, _) => {
+                assert!(options.should_disclose());
+                assert!(!options.should_acknowledge());
+            }
+            message => panic!("unexpected message {:?}", message),
+        }
+        assert!(!PublishOptions::new(true).should_disclose());
+    }
+
+    #[test]
+    fn compare_integers() {
+        assert_eq!(Value::Integer(5), Value::UnsignedInteger(5));
+        assert_eq!(Value::UnsignedInteger(5), Value::Integer(5));
+        assert_ne!(Value::Integer(-1), Value::UnsignedInteger(u64::max_value()));
+        assert_ne!(Value::Integer(5), Value::Float(5.0));
+        let value: Value = serde_json::from_str("5").unwrap();
+        assert_eq!(value, Value::Integer(5));
+        assert_eq!(vec![value].get_int(0).unwrap(), Some(5));
+    }
+
     #[test]
     fn serialize_published() {
         two_way_test!(Message::Published(23443, 564564), "[17,23443,564564]")
@@ -10,20 +10,25 @@ pub enum Reason {
     ProcedureAlreadyExists,
     NoSuchRegistration,
     NoSuchSubscription,
+    NoSuchSession,
     InvalidArgument,
     SystemShutdown,
     CloseRealm,
     GoodbyeAndOut,
     NotAuthorized,
     AuthorizationFailed,
+    AuthenticationFailed,
+    NoAuthMethod,
     NoSuchRealm,
     NoSuchRole,
+    ProtocolViolation,
     Cancelled,
     OptionNotAllowed,
     NoEligibleCallee,
     OptionDisallowedDiscloseMe,
     NetworkFailure,
     NormalClose,
+    Killed,
     CustomReason(URI),
 }
 
@@ -87,20 +92,25 @@ impl Reason {
             Reason::ProcedureAlreadyExists => "wamp.error.procedure_already_exists",
             Reason::NoSuchRegistration => "wamp.error.no_such_registration",
             Reason::NoSuchSubscription => "wamp.error.no_such_subscription",
+            Reason::NoSuchSession => "wamp.error.no_such_session",
             Reason::InvalidArgument => "wamp.error.invalid_argument",
             Reason::SystemShutdown => "wamp.error.system_shutdown",
             Reason::CloseRealm => "wamp.error.close_realm",
             Reason::GoodbyeAndOut => "wamp.error.goodbye_and_out",
             Reason::NotAuthorized => "wamp.error.not_authorized",
             Reason::AuthorizationFailed => "wamp.error.authorization_failed",
+            Reason::AuthenticationFailed => "wamp.error.authentication_failed",
+            Reason::NoAuthMethod => "wamp.error.no_auth_method",
             Reason::NoSuchRealm => "wamp.error.no_such_realm",
             Reason::NoSuchRole => "wamp.error.no_such_role",
+            Reason::ProtocolViolation => "wamp.error.protocol_violation",
             Reason::Cancelled => "wamp.error.cancelled",
             Reason::OptionNotAllowed => "wamp.error.option_not_allowed",
             Reason::NoEligibleCallee => "wamp.error.no_eligible_callee",
             Reason::OptionDisallowedDiscloseMe => "wamp.error.option-disallowed.disclose_me",
             Reason::NetworkFailure => "wamp.error.network_failure",
             Reason::NormalClose => "wamp.close.normal",
+            Reason::Killed => "wamp.close.killed",
             Reason::CustomReason(ref reason) => &reason.uri,
         }
     }
@@ -152,20 +162,25 @@ impl<'de> serde::de::Visitor<'de> for ReasonVisitor {
             "wamp.error.procedure_already_exists" => Ok(Reason::ProcedureAlreadyExists),
             "wamp.error.no_such_registration" => Ok(Reason::NoSuchRegistration),
             "wamp.error.no_such_subscription" => Ok(Reason::NoSuchSubscription),
+            "wamp.error.no_such_session" => Ok(Reason::NoSuchSession),
             "wamp.error.invalid_argument" => Ok(Reason::InvalidArgument),
             "wamp.error.system_shutdown" => Ok(Reason::SystemShutdown),
             "wamp.error.close_realm" => Ok(Reason::CloseRealm),
             "wamp.error.goodbye_and_out" => Ok(Reason::GoodbyeAndOut),
             "wamp.error.not_authorized" => Ok(Reason::NotAuthorized),
             "wamp.error.authorization_failed" => Ok(Reason::AuthorizationFailed),
+            "wamp.error.authentication_failed" => Ok(Reason::AuthenticationFailed),
+            "wamp.error.no_auth_method" => Ok(Reason::NoAuthMethod),
             "wamp.error.no_such_realm" => Ok(Reason::NoSuchRealm),
             "wamp.error.no_such_role" => Ok(Reason::NoSuchRole),
+            "wamp.error.protocol_violation" => Ok(Reason::ProtocolViolation),
             "wamp.error.cancelled" => Ok(Reason::Cancelled),
             "wamp.error.option_not_allowed" => Ok(Reason::OptionNotAllowed),
             "wamp.error.no_eligible_callee" => Ok(Reason::NoEligibleCallee),
             "wamp.error.option-disallowed.disclose_me" => Ok(Reason::OptionDisallowedDiscloseMe),
             "wamp.error.network_failure" => Ok(Reason::NetworkFailure),
             "wamp.close.normal" => Ok(Reason::NormalClose),
+            "wamp.close.killed" => Ok(Reason::Killed),
             x => Ok(Reason::CustomReason(URI::new(x))),
         }
     }
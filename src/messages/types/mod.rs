@@ -57,6 +57,22 @@ impl MatchingPolicy {
     fn is_strict(&self) -> bool {
         self == &MatchingPolicy::Strict
     }
+
+    /// Whether a URI matches a pattern of this policy.  Empty segments of wildcard patterns
+    /// match any segment.
+    pub fn matches(&self, pattern: &str, uri: &str) -> bool {
+        match *self {
+            MatchingPolicy::Strict => uri == pattern,
+            MatchingPolicy::Prefix => uri.starts_with(pattern),
+            MatchingPolicy::Wildcard => {
+                let pattern = pattern.split('.').collect::<Vec<_>>();
+                let segments = uri.split('.').collect::<Vec<_>>();
+                pattern.len() == segments.len() && pattern.iter()
+                    .zip(segments)
+                    .all(|(pattern, segment)| pattern.is_empty() || *pattern == segment)
+            }
+        }
+    }
 }
 
 impl InvocationPolicy {
@@ -122,7 +138,7 @@ impl<'de> serde::de::Visitor<'de> for MatchingPolicyVisitor {
         match value {
             "prefix" => Ok(MatchingPolicy::Prefix),
             "wildcard" => Ok(MatchingPolicy::Wildcard),
-            "" => Ok(MatchingPolicy::Strict),
+            "" | "exact" => Ok(MatchingPolicy::Strict),
             x => Err(serde::de::Error::custom(format!(
                 "Invalid matching policy: {}",
                 x
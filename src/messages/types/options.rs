@@ -1,11 +1,22 @@
-use super::{is_not, ClientRoles, InvocationPolicy, MatchingPolicy, RouterRoles, URI};
+use super::{is_not, ClientRoles, Dict, InvocationPolicy, MatchingPolicy, RouterRoles, URI};
+use crate::ID;
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
 pub struct HelloDetails {
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    agent: Option<String>,
-    roles: ClientRoles,
+    pub agent: Option<String>,
+    pub roles: ClientRoles,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authmethods: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authextra: Option<Dict>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_session: Option<ID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
@@ -13,6 +24,20 @@ pub struct WelcomeDetails {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     agent: Option<String>,
     roles: RouterRoles,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authrole: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authmethod: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authprovider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authextra: Option<Dict>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub resumed: bool,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
@@ -25,12 +50,19 @@ pub struct ErrorDetails {
 pub struct SubscribeOptions {
     #[serde(default, rename = "match", skip_serializing_if = "MatchingPolicy::is_strict")]
     pub pattern_match: MatchingPolicy,
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub durable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
 pub struct PublishOptions {
     #[serde(default, skip_serializing_if = "is_not")]
     acknowledge: bool,
+
+    #[serde(default, skip_serializing_if = "is_not")]
+    disclose_me: bool,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
@@ -51,7 +83,13 @@ pub struct YieldOptions {}
 #[derive(Serialize, Clone, Deserialize, PartialEq, Debug, Default)]
 pub struct EventDetails {
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    publisher: Option<String>,
+    pub publisher: Option<ID>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher_authid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher_authrole: Option<String>,
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     trustlevel: Option<u64>,
@@ -74,6 +112,11 @@ impl HelloDetails {
         HelloDetails {
             roles: roles,
             agent: None,
+            authmethods: None,
+            authid: None,
+            authextra: None,
+            resume_session: None,
+            resume_token: None,
         }
     }
 
@@ -81,6 +124,11 @@ impl HelloDetails {
         HelloDetails {
             roles: roles,
             agent: Some(agent.to_string()),
+            authmethods: None,
+            authid: None,
+            authextra: None,
+            resume_session: None,
+            resume_token: None,
         }
     }
 }
@@ -90,6 +138,13 @@ impl WelcomeDetails {
         WelcomeDetails {
             roles: roles,
             agent: None,
+            authid: None,
+            authrole: None,
+            authmethod: None,
+            authprovider: None,
+            authextra: None,
+            resume_token: None,
+            resumed: false,
         }
     }
 
@@ -97,6 +152,13 @@ impl WelcomeDetails {
         WelcomeDetails {
             roles: roles,
             agent: Some(agent.to_string()),
+            authid: None,
+            authrole: None,
+            authmethod: None,
+            authprovider: None,
+            authextra: None,
+            resume_token: None,
+            resumed: false,
         }
     }
 }
@@ -117,6 +179,8 @@ impl SubscribeOptions {
     pub fn new() -> SubscribeOptions {
         SubscribeOptions {
             pattern_match: MatchingPolicy::Strict,
+            durable: false,
+            name: None,
         }
     }
 }
@@ -125,12 +189,18 @@ impl PublishOptions {
     pub fn new(acknowledge: bool) -> PublishOptions {
         PublishOptions {
             acknowledge: acknowledge,
+            disclose_me: false,
         }
     }
 
     pub fn should_acknowledge(&self) -> bool {
         self.acknowledge
     }
+
+    /// Whether the publisher asks to disclose its identity to the subscribers.
+    pub fn should_disclose(&self) -> bool {
+        self.disclose_me
+    }
 }
 
 impl RegisterOptions {
@@ -158,6 +228,8 @@ impl EventDetails {
     pub fn new() -> EventDetails {
         EventDetails {
             publisher: None,
+            publisher_authid: None,
+            publisher_authrole: None,
             trustlevel: None,
             topic: None,
         }
@@ -166,6 +238,8 @@ impl EventDetails {
     pub fn new_with_topic(topic: URI) -> EventDetails {
         EventDetails {
             publisher: None,
+            publisher_authid: None,
+            publisher_authrole: None,
             trustlevel: None,
             topic: Some(topic),
         }
@@ -2,12 +2,17 @@ use super::is_not;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 
+/// The roles a client announces.  Clients only announce the roles they implement.
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct ClientRoles {
-    pub publisher: PublisherRole,
-    pub subscriber: SubscriberRole,
-    pub caller: CallerRole,
-    pub callee: CalleeRole,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<PublisherRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscriber: Option<SubscriberRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<CallerRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callee: Option<CalleeRole>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -46,6 +51,8 @@ pub struct SubscriberRole {
 pub struct SubscriberFeatures {
     #[serde(skip_serializing_if = "is_not", default)]
     pattern_based_subscription: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    publisher_identification: bool,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -64,12 +71,18 @@ pub struct BrokerRole {
 pub struct DealerFeatures {
     #[serde(skip_serializing_if = "is_not", default)]
     pattern_based_registration: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    session_meta_api: bool,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct BrokerFeatures {
     #[serde(skip_serializing_if = "is_not", default)]
     pattern_based_subscription: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    publisher_identification: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    session_meta_api: bool,
 }
 
 /**************************
@@ -77,17 +90,21 @@ pub struct BrokerFeatures {
 **************************/
 
 impl RouterRoles {
+    /// The roles of this router with the features it implements.
     #[inline]
     pub fn new() -> RouterRoles {
         RouterRoles {
             broker: BrokerRole {
                 features: Some(BrokerFeatures {
                     pattern_based_subscription: true,
+                    publisher_identification: true,
+                    session_meta_api: true,
                 }),
             },
             dealer: DealerRole {
                 features: Some(DealerFeatures {
-                    pattern_based_registration: true,
+                    pattern_based_registration: false,
+                    session_meta_api: true,
                 }),
             },
         }
@@ -106,42 +123,60 @@ impl ClientRoles {
     #[inline]
     pub fn new() -> ClientRoles {
         ClientRoles {
-            publisher: PublisherRole {
+            publisher: Some(PublisherRole {
                 features: Some(HashMap::new()),
-            },
-            subscriber: SubscriberRole {
+            }),
+            subscriber: Some(SubscriberRole {
                 features: Some(SubscriberFeatures {
                     pattern_based_subscription: true,
+                    publisher_identification: false,
                 }),
-            },
-            caller: CallerRole {
+            }),
+            caller: Some(CallerRole {
                 features: Some(HashMap::new()),
-            },
-            callee: CalleeRole {
+            }),
+            callee: Some(CalleeRole {
                 features: Some(HashMap::new()),
-            },
+            }),
         }
     }
 
     #[inline]
     pub fn new_basic() -> ClientRoles {
         ClientRoles {
-            publisher: PublisherRole {
+            publisher: Some(PublisherRole {
                 features: Some(HashMap::new()),
-            },
-            subscriber: SubscriberRole {
+            }),
+            subscriber: Some(SubscriberRole {
                 features: Some(SubscriberFeatures {
                     pattern_based_subscription: false,
+                    publisher_identification: false,
                 }),
-            },
-            caller: CallerRole {
+            }),
+            caller: Some(CallerRole {
                 features: Some(HashMap::new()),
-            },
-            callee: CalleeRole {
+            }),
+            callee: Some(CalleeRole {
                 features: Some(HashMap::new()),
-            },
+            }),
         }
     }
+
+    /// Whether the client announced any role.
+    pub fn is_empty(&self) -> bool {
+        self.publisher.is_none()
+            && self.subscriber.is_none()
+            && self.caller.is_none()
+            && self.callee.is_none()
+    }
+
+    /// Whether the subscriber wants to receive the identity of publishers that disclose it.
+    pub fn publisher_identification(&self) -> bool {
+        self.subscriber
+            .as_ref()
+            .and_then(|subscriber| subscriber.features.as_ref())
+            .map_or(false, |features| features.publisher_identification)
+    }
 }
 
 impl Default for RouterRoles {
@@ -1,6 +1,8 @@
 use super::{CallError, Reason};
 use itertools::Itertools;
 use serde;
+use serde::de::Unexpected;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt;
 use crate::CallResult;
@@ -8,6 +10,37 @@ use crate::CallResult;
 pub type Dict = HashMap<String, Value>;
 pub type List = Vec<Value>;
 
+/// Starts a JSON string that carries base64 encoded binary data, as the WAMP specification
+/// requires for serializers without a binary type.
+const BINARY_PREFIX: char = '\0';
+
+/// How `Value::Bytes` is serialized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BytesEncoding {
+    /// As a string of the base64 encoded bytes that starts with `\0`, for serializers without a
+    /// binary type such as JSON.
+    Base64,
+    /// As the binary type of the serializer, such as a MessagePack `bin` or a CBOR byte string.
+    Binary,
+}
+
+thread_local! {
+    static BYTES_ENCODING: Cell<BytesEncoding> = Cell::new(BytesEncoding::Base64);
+}
+
+/// Serializes `Value::Bytes` with the given encoding while `serialize` runs.  Values are
+/// serialized as base64 strings otherwise.  The router encodes messages through
+/// `Serializer::encode`, which picks the encoding of the serializer.
+pub(crate) fn with_bytes_encoding<T, F>(encoding: BytesEncoding, serialize: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = BYTES_ENCODING.with(|current| current.replace(encoding));
+    let result = serialize();
+    BYTES_ENCODING.with(|current| current.set(previous));
+    result
+}
+
 // TODO properly implement Hash and Eq
 #[derive(Debug, PartialEq, Clone, Hash, Eq)]
 pub struct URI {
@@ -22,17 +55,22 @@ impl URI {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub enum Value {
     // The ID and URI types cannot be distinguished from string and integer types respectively.
     // So, we just ignore them here
     Dict(Dict),
+    /// A signed integer.  Integers that are not negative are always `UnsignedInteger`s once
+    /// they are deserialized, whichever encoding the serializer picked for them, and equal to
+    /// an `Integer` of the same number.
     Integer(i64),
     UnsignedInteger(u64),
     Float(f64),
     String(String),
     List(List),
     Boolean(bool),
+    Null,
+    Bytes(Vec<u8>),
 }
 
 struct URIVisitor;
@@ -49,12 +87,31 @@ pub trait ArgDict {
     fn get_string<'a>(&'a self, key: &str) -> CallResult<Option<&'a str>>;
 }
 
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Dict(a), Value::Dict(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::UnsignedInteger(a), Value::UnsignedInteger(b)) => a == b,
+            (Value::Integer(a), Value::UnsignedInteger(b))
+            | (Value::UnsignedInteger(b), Value::Integer(a)) => *a >= 0 && *a as u64 == *b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 impl ArgList for List {
     fn get_int(&self, index: usize) -> CallResult<Option<i64>> {
         let value = self.get(index);
         match value {
             Some(value) => {
-                if let Value::Integer(value) = *value {
+                if let Some(value) = value.as_i64() {
                     Ok(Some(value))
                 } else {
                     Err(CallError::new(
@@ -114,7 +171,7 @@ impl ArgDict for Dict {
         let value = self.get(key);
         match value {
             Some(value) => {
-                if let Value::Integer(value) = *value {
+                if let Some(value) = value.as_i64() {
                     Ok(Some(value))
                 } else {
                     Err(CallError::new(
@@ -153,6 +210,15 @@ impl ArgDict for Dict {
 }
 
 impl Value {
+    /// The number of an integer that fits an `i64`, whichever variant holds it.
+    fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Integer(i) => Some(i),
+            Value::UnsignedInteger(u) if u <= i64::max_value() as u64 => Some(u as i64),
+            _ => None,
+        }
+    }
+
     pub fn summarize(&self) -> String {
         match *self {
             Value::Dict(ref d) => {
@@ -186,6 +252,8 @@ impl Value {
                 result
             }
             Value::Boolean(b) => b.to_string(),
+            Value::Null => "null".to_string(),
+            Value::Bytes(ref b) => format!("<{} bytes>", b.len()),
         }
     }
 }
@@ -198,12 +266,34 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
         formatter.write_str("JSON value")
     }
 
-    #[inline]
     fn visit_str<E>(self, value: &str) -> Result<Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Value::String(value.to_string()))
+        if value.starts_with(BINARY_PREFIX) {
+            match base64::decode(&value[1..]) {
+                Ok(bytes) => Ok(Value::Bytes(bytes)),
+                Err(_) => Err(E::invalid_value(Unexpected::Str(value), &"base64 encoded binary data")),
+            }
+        } else {
+            Ok(Value::String(value.to_string()))
+        }
+    }
+
+    #[inline]
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Value::Bytes(value.to_vec()))
+    }
+
+    #[inline]
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Value::Bytes(value))
     }
 
     #[inline]
@@ -211,7 +301,11 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
     where
         E: serde::de::Error,
     {
-        Ok(Value::Integer(value))
+        if value < 0 {
+            Ok(Value::Integer(value))
+        } else {
+            Ok(Value::UnsignedInteger(value as u64))
+        }
     }
 
     #[inline]
@@ -237,6 +331,30 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
         Ok(Value::Boolean(value))
     }
 
+    #[inline]
+    fn visit_unit<E>(self) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn visit_none<E>(self) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        serde::Deserialize::deserialize(deserializer)
+    }
+
     #[inline]
     fn visit_map<Visitor>(self, mut visitor: Visitor) -> Result<Value, Visitor::Error>
     where
@@ -288,6 +406,15 @@ impl serde::Serialize for Value {
             Value::Float(f) => serializer.serialize_f64(f),
             Value::List(ref list) => list.serialize(serializer),
             Value::Boolean(b) => serializer.serialize_bool(b),
+            Value::Null => serializer.serialize_unit(),
+            Value::Bytes(ref bytes) => match BYTES_ENCODING.with(Cell::get) {
+                BytesEncoding::Base64 => {
+                    let mut s = BINARY_PREFIX.to_string();
+                    s.push_str(&base64::encode(bytes));
+                    serializer.serialize_str(&s)
+                }
+                BytesEncoding::Binary => serializer.serialize_bytes(bytes),
+            },
         }
     }
 }
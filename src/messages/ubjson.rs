@@ -0,0 +1,329 @@
+//! Encodes and decodes messages in the Universal Binary JSON format of the `wamp.2.ubjson`
+//! sub-protocol.
+//!
+//! UBJSON has the same data model as JSON, so messages pass through `serde_json::Value` on
+//! their way to and from the binary form.  The decoder understands optimized containers and
+//! no-op markers.  Unsigned integers beyond the range of an `int64` are written as
+//! high-precision numbers.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Number, Value as JSONValue};
+use std::fmt;
+
+/// How deep containers may be nested in a decoded message.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub struct Error {
+    message: String,
+}
+
+impl Error {
+    fn new<M: Into<String>>(message: M) -> Error {
+        Error { message: message.into() }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Error {
+        Error::new(error.to_string())
+    }
+}
+
+pub fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    write_value(&mut buf, &serde_json::to_value(value)?)?;
+    Ok(buf)
+}
+
+pub fn from_slice<T: DeserializeOwned>(payload: &[u8]) -> Result<T, Error> {
+    let mut reader = Reader { payload, position: 0 };
+    let value = reader.read_value(0)?;
+    if reader.position != payload.len() {
+        return Err(Error::new("trailing bytes after the value"));
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &JSONValue) -> Result<(), Error> {
+    match *value {
+        JSONValue::Null => buf.push(b'Z'),
+        JSONValue::Bool(true) => buf.push(b'T'),
+        JSONValue::Bool(false) => buf.push(b'F'),
+        JSONValue::Number(ref number) => write_number(buf, number)?,
+        JSONValue::String(ref string) => {
+            buf.push(b'S');
+            write_string(buf, string);
+        }
+        JSONValue::Array(ref values) => {
+            buf.push(b'[');
+            for value in values {
+                write_value(buf, value)?;
+            }
+            buf.push(b']');
+        }
+        JSONValue::Object(ref map) => {
+            buf.push(b'{');
+            for (key, value) in map {
+                write_string(buf, key);
+                write_value(buf, value)?;
+            }
+            buf.push(b'}');
+        }
+    }
+    Ok(())
+}
+
+fn write_number(buf: &mut Vec<u8>, number: &Number) -> Result<(), Error> {
+    if let Some(value) = number.as_i64() {
+        write_integer(buf, value);
+    } else if let Some(value) = number.as_u64() {
+        buf.push(b'H');
+        write_string(buf, &value.to_string());
+    } else if let Some(value) = number.as_f64() {
+        buf.push(b'D');
+        buf.extend_from_slice(&value.to_bits().to_be_bytes());
+    } else {
+        return Err(Error::new(format!("cannot encode the number {}", number)));
+    }
+    Ok(())
+}
+
+/// Writes an integer with the smallest type that holds it.
+fn write_integer(buf: &mut Vec<u8>, value: i64) {
+    if value >= 0 && value <= i64::from(u8::max_value()) {
+        buf.push(b'U');
+        buf.push(value as u8);
+    } else if value >= i64::from(i8::min_value()) && value <= i64::from(i8::max_value()) {
+        buf.push(b'i');
+        buf.push(value as i8 as u8);
+    } else if value >= i64::from(i16::min_value()) && value <= i64::from(i16::max_value()) {
+        buf.push(b'I');
+        buf.extend_from_slice(&(value as i16).to_be_bytes());
+    } else if value >= i64::from(i32::min_value()) && value <= i64::from(i32::max_value()) {
+        buf.push(b'l');
+        buf.extend_from_slice(&(value as i32).to_be_bytes());
+    } else {
+        buf.push(b'L');
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Writes the length and bytes of a string, without the `S` marker.
+fn write_string(buf: &mut Vec<u8>, string: &str) {
+    write_integer(buf, string.len() as i64);
+    buf.extend_from_slice(string.as_bytes());
+}
+
+struct Reader<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.payload.len() - self.position < length {
+            return Err(Error::new("unexpected end of input"));
+        }
+        let bytes = &self.payload[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> Result<u8, Error> {
+        self.payload.get(self.position).cloned().ok_or_else(|| Error::new("unexpected end of input"))
+    }
+
+    fn marker(&mut self) -> Result<u8, Error> {
+        loop {
+            let marker = self.take(1)?[0];
+            if marker != b'N' {
+                return Ok(marker);
+            }
+        }
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<JSONValue, Error> {
+        let marker = self.marker()?;
+        self.read_typed(marker, depth)
+    }
+
+    fn read_typed(&mut self, marker: u8, depth: usize) -> Result<JSONValue, Error> {
+        Ok(match marker {
+            b'Z' => JSONValue::Null,
+            b'T' => JSONValue::Bool(true),
+            b'F' => JSONValue::Bool(false),
+            b'i' | b'U' | b'I' | b'l' | b'L' => JSONValue::from(self.read_integer(marker)?),
+            b'd' => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(self.take(4)?);
+                float(f64::from(f32::from_bits(u32::from_be_bytes(bytes))))?
+            }
+            b'D' => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                float(f64::from_bits(u64::from_be_bytes(bytes)))?
+            }
+            b'H' => {
+                let digits = self.read_string()?;
+                match serde_json::from_str::<Number>(&digits) {
+                    Ok(number) => JSONValue::Number(number),
+                    Err(_) => return Err(Error::new(format!("invalid high-precision number {}", digits))),
+                }
+            }
+            b'C' => JSONValue::String((self.take(1)?[0] as char).to_string()),
+            b'S' => JSONValue::String(self.read_string()?),
+            b'[' | b'{' if depth >= MAX_DEPTH => return Err(Error::new("containers are nested too deeply")),
+            b'[' => JSONValue::Array(self.read_array(depth + 1)?),
+            b'{' => JSONValue::Object(self.read_object(depth + 1)?),
+            marker => return Err(Error::new(format!("unexpected marker {:?}", marker as char))),
+        })
+    }
+
+    fn read_integer(&mut self, marker: u8) -> Result<i64, Error> {
+        let bytes = match marker {
+            b'i' => return Ok(i64::from(self.take(1)?[0] as i8)),
+            b'U' => return Ok(i64::from(self.take(1)?[0])),
+            b'I' => self.take(2)?,
+            b'l' => self.take(4)?,
+            b'L' => self.take(8)?,
+            marker => return Err(Error::new(format!("expected an integer, got marker {:?}", marker as char))),
+        };
+        // Sign-extend the big-endian bytes.
+        let first = i64::from(bytes[0] as i8);
+        Ok(bytes[1..].iter().fold(first, |value, byte| value << 8 | i64::from(*byte)))
+    }
+
+    fn read_length(&mut self) -> Result<usize, Error> {
+        let marker = self.marker()?;
+        let length = self.read_integer(marker)?;
+        if length < 0 || length as usize > self.payload.len() - self.position {
+            return Err(Error::new(format!("invalid length {}", length)));
+        }
+        Ok(length as usize)
+    }
+
+    fn read_string(&mut self) -> Result<String, Error> {
+        let length = self.read_length()?;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| Error::new("a string is not valid UTF-8"))
+    }
+
+    /// Reads the `$` type and `#` count of an optimized container, if it has them.
+    fn read_header(&mut self) -> Result<(Option<u8>, Option<usize>), Error> {
+        let mut element_type = None;
+        if self.peek()? == b'$' {
+            self.position += 1;
+            element_type = Some(self.take(1)?[0]);
+            if self.peek()? != b'#' {
+                return Err(Error::new("a typed container has no count"));
+            }
+        }
+        let mut count = None;
+        if self.peek()? == b'#' {
+            self.position += 1;
+            count = Some(self.read_length()?);
+        }
+        Ok((element_type, count))
+    }
+
+    fn read_element(&mut self, element_type: Option<u8>, depth: usize) -> Result<JSONValue, Error> {
+        match element_type {
+            Some(marker) => self.read_typed(marker, depth),
+            None => self.read_value(depth),
+        }
+    }
+
+    fn read_array(&mut self, depth: usize) -> Result<Vec<JSONValue>, Error> {
+        let mut values = Vec::new();
+        match self.read_header()? {
+            (element_type, Some(count)) => {
+                for _ in 0..count {
+                    values.push(self.read_element(element_type, depth)?);
+                }
+            }
+            (_, None) => loop {
+                while self.peek()? == b'N' {
+                    self.position += 1;
+                }
+                if self.peek()? == b']' {
+                    self.position += 1;
+                    break;
+                }
+                values.push(self.read_value(depth)?);
+            },
+        }
+        Ok(values)
+    }
+
+    fn read_object(&mut self, depth: usize) -> Result<Map<String, JSONValue>, Error> {
+        let mut map = Map::new();
+        match self.read_header()? {
+            (element_type, Some(count)) => {
+                for _ in 0..count {
+                    let key = self.read_string()?;
+                    map.insert(key, self.read_element(element_type, depth)?);
+                }
+            }
+            (_, None) => loop {
+                while self.peek()? == b'N' {
+                    self.position += 1;
+                }
+                if self.peek()? == b'}' {
+                    self.position += 1;
+                    break;
+                }
+                let key = self.read_string()?;
+                map.insert(key, self.read_value(depth)?);
+            },
+        }
+        Ok(map)
+    }
+}
+
+fn float(value: f64) -> Result<JSONValue, Error> {
+    Number::from_f64(value).map(JSONValue::Number).ok_or_else(|| Error::new("NaN and infinity are not supported"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_slice, to_vec};
+    use serde_json::{json, Value as JSONValue};
+
+    #[test]
+    fn integers() {
+        assert_eq!(to_vec(&200u64).unwrap(), [b'U', 200]);
+        assert_eq!(to_vec(&-5i64).unwrap(), [b'i', 0xFB]);
+        assert_eq!(to_vec(&1000i64).unwrap(), [b'I', 0x03, 0xE8]);
+        assert_eq!(to_vec(&-70000i64).unwrap(), [b'l', 0xFF, 0xFE, 0xEE, 0x90]);
+        assert_eq!(from_slice::<i64>(&[b'l', 0xFF, 0xFE, 0xEE, 0x90]).unwrap(), -70000);
+        assert_eq!(from_slice::<u64>(&to_vec(&u64::max_value()).unwrap()).unwrap(), u64::max_value());
+        assert_eq!(from_slice::<i64>(&to_vec(&i64::min_value()).unwrap()).unwrap(), i64::min_value());
+    }
+
+    #[test]
+    fn containers() {
+        let value = json!({"args": [1, "two", 3.5, null, true], "kwargs": {}});
+        assert_eq!(from_slice::<JSONValue>(&to_vec(&value).unwrap()).unwrap(), value);
+
+        let optimized = b"[$U#U\x03\x01\x02\x03";
+        assert_eq!(from_slice::<JSONValue>(optimized).unwrap(), json!([1, 2, 3]));
+        let optimized = b"{#U\x01U\x01aSU\x01b";
+        assert_eq!(from_slice::<JSONValue>(optimized).unwrap(), json!({"a": "b"}));
+        assert_eq!(from_slice::<JSONValue>(b"[NU\x01NCx]").unwrap(), json!([1, "x"]));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(from_slice::<JSONValue>(b"[U").is_err());
+        assert!(from_slice::<JSONValue>(b"SU\x05ab").is_err());
+        assert!(from_slice::<JSONValue>(b"TT").is_err());
+        assert!(from_slice::<JSONValue>(&[b'['; 200]).is_err());
+        assert!(from_slice::<JSONValue>(b"[#L\x7F\xFF\xFF\xFF\xFF\xFF\xFF\xFFU").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Implements the `cryptosign` authentication.
+//!
+//! The client announces its Ed25519 public key in the `authextra` of its `HELLO` message and
+//! signs a random challenge with the corresponding private key.  If the client requests the
+//! `tls-unique` channel binding, the challenge is XORed with the SHA-256 hash of the TLS channel
+//! id, so the signature can not be relayed to another TLS connection.
+use super::{AuthIdentity, AuthStep, PendingAuthentication, TransportDetails};
+use crate::messages::{Dict, HelloDetails, Reason, Value};
+use crate::router::config::CryptosignConfig;
+use rand::{thread_rng, Rng};
+use ring::constant_time::verify_slices_are_equal;
+use ring::{digest, signature};
+
+/// A challenge that was sent to a session.
+#[derive(Debug)]
+pub struct Challenge {
+    identity: AuthIdentity,
+    pubkey: Vec<u8>,
+    message: Vec<u8>,
+}
+
+pub fn challenge(
+    config: &CryptosignConfig,
+    details: &HelloDetails,
+    transport: &TransportDetails,
+) -> Result<AuthStep, Reason> {
+    let authextra = details.authextra.clone().unwrap_or_default();
+    let pubkey = match authextra.get("pubkey") {
+        Some(Value::String(pubkey)) => pubkey.to_lowercase(),
+        _ => return Err(rejected("no public key was announced")),
+    };
+    let (authid, principal) = config.principals
+        .iter()
+        .filter(|(authid, _)| details.authid.as_ref().map_or(true, |id| id == *authid))
+        .find(|(_, principal)| {
+            principal.authorized_keys.iter().any(|key| key.eq_ignore_ascii_case(&pubkey))
+        })
+        .ok_or_else(|| rejected("the public key is not authorized"))?;
+
+    let mut extra = Dict::new();
+    let mut message = thread_rng().gen::<[u8; 32]>().to_vec();
+    extra.insert("challenge".to_string(), Value::String(hex::encode(&message)));
+    match authextra.get("channel_binding") {
+        None => {}
+        Some(Value::String(channel_binding)) if channel_binding == "tls-unique" => {
+            let tls_unique = transport.tls_unique
+                .as_ref()
+                .ok_or_else(|| rejected("tls-unique is not available on this transport"))?;
+            let channel_id = digest::digest(&digest::SHA256, tls_unique);
+            for (byte, id) in message.iter_mut().zip(channel_id.as_ref()) {
+                *byte ^= id;
+            }
+            extra.insert("channel_binding".to_string(), Value::String(channel_binding.clone()));
+        }
+        Some(_) => return Err(rejected("the channel binding is not supported")),
+    }
+
+    Ok(AuthStep::Challenge(
+        "cryptosign".to_string(),
+        extra,
+        PendingAuthentication::Cryptosign(Challenge {
+            identity: AuthIdentity {
+                authid: authid.clone(),
+                authrole: principal.role.clone(),
+                authmethod: "cryptosign".to_string(),
+                authprovider: "static".to_string(),
+                authextra: None,
+            },
+            pubkey: hex::decode(&pubkey).map_err(|_| rejected("the public key is not hex encoded"))?,
+            message,
+        }),
+    ))
+}
+
+fn rejected(reason: &str) -> Reason {
+    log::info!("Rejecting cryptosign authentication: {}", reason);
+    Reason::AuthenticationFailed
+}
+
+impl Challenge {
+    /// Verifies the hex encoded signature, which may be followed by the signed message.
+    pub fn verify(self, signature: &str) -> Result<AuthIdentity, Reason> {
+        let signed = hex::decode(signature).map_err(|_| rejected("the signature is not hex encoded"))?;
+        if signed.len() != 64 && signed.len() != 64 + self.message.len() {
+            return Err(rejected("the signature has an invalid length"));
+        }
+        if signed.len() > 64 && verify_slices_are_equal(&signed[64..], &self.message).is_err() {
+            return Err(rejected("a different message was signed"));
+        }
+        signature::UnparsedPublicKey::new(&signature::ED25519, &self.pubkey)
+            .verify(&self.message, &signed[..64])
+            .map_err(|_| rejected("the signature is invalid"))?;
+        Ok(self.identity)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::challenge;
+    use crate::messages::{ClientRoles, HelloDetails, Reason, Value};
+    use crate::router::auth::{AuthStep, PendingAuthentication, TransportDetails};
+    use crate::router::config::CryptosignConfig;
+
+    const PUBKEY: &str = "03a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b8";
+    const SIGNATURE: &str = "b6c8790330ad9053c84b389fa82359a9bcea86f90f6efdd6b798e57591cb1860d740744e07db8a39cc94b4faa6b540dd285f7cb2641b931965c47cd468f7ae040707070707070707070707070707070707070707070707070707070707070707";
+    const BOUND_SIGNATURE: &str = "2e144cd61dacbd9c77f14f8cc96c20b0da7d9a1276193307a8710b3e6e75a7de41b5ff7bec65770e181be54d004ddb3c92abb94f37c06c5b169b119071bd370f1474c795338a53b1b99fe0e314bb5d665ce34c6158bc7aab7aad41890d0496e2";
+
+    fn config() -> CryptosignConfig {
+        serde_json::from_str(&format!(
+            "{{\"principals\":{{\"device-1\":{{\"role\":\"device\",\"authorized_keys\":[\"{}\"]}}}}}}",
+            PUBKEY
+        )).unwrap()
+    }
+
+    fn hello(channel_binding: Option<&str>) -> HelloDetails {
+        let mut details = HelloDetails::new(ClientRoles::new());
+        let mut authextra = std::collections::HashMap::new();
+        authextra.insert("pubkey".to_string(), Value::String(PUBKEY.to_string()));
+        if let Some(channel_binding) = channel_binding {
+            authextra.insert("channel_binding".to_string(), Value::String(channel_binding.to_string()));
+        }
+        details.authmethods = Some(vec!["cryptosign".to_string()]);
+        details.authextra = Some(authextra);
+        details
+    }
+
+    fn verify(details: &HelloDetails, transport: &TransportDetails, signature: &str) -> Result<String, Reason> {
+        match challenge(&config(), details, transport)? {
+            AuthStep::Challenge(_, _, PendingAuthentication::Cryptosign(mut challenge)) => {
+                // replace the random challenge with the one the signatures were created for
+                challenge.message = vec![7; 32];
+                if let Some(ref tls_unique) = transport.tls_unique {
+                    let channel_id = ring::digest::digest(&ring::digest::SHA256, tls_unique);
+                    for (byte, id) in challenge.message.iter_mut().zip(channel_id.as_ref()) {
+                        *byte ^= id;
+                    }
+                }
+                challenge.verify(signature).map(|identity| identity.authid)
+            }
+            _ => panic!("expected a cryptosign challenge"),
+        }
+    }
+
+    #[test]
+    fn signed_challenge() {
+        let transport = TransportDetails::default();
+        assert_eq!(verify(&hello(None), &transport, SIGNATURE), Ok("device-1".to_string()));
+        assert_eq!(verify(&hello(None), &transport, &SIGNATURE[..128]), Ok("device-1".to_string()));
+        assert_eq!(verify(&hello(None), &transport, BOUND_SIGNATURE), Err(Reason::AuthenticationFailed));
+    }
+
+    #[test]
+    fn channel_binding() {
+        let transport = TransportDetails {
+            tls_unique: Some(b"tls-finished".to_vec()),
+            ..TransportDetails::default()
+        };
+        assert_eq!(
+            verify(&hello(Some("tls-unique")), &transport, BOUND_SIGNATURE),
+            Ok("device-1".to_string())
+        );
+        assert_eq!(
+            verify(&hello(Some("tls-unique")), &transport, SIGNATURE),
+            Err(Reason::AuthenticationFailed)
+        );
+        assert!(challenge(&config(), &hello(Some("tls-unique")), &TransportDetails::default()).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Implements authentication by a procedure that a backend session registers.
+//!
+//! The router invokes the procedure with `(realm, authid, details)`.  The procedure returns the
+//! authrole of the session, or a dictionary with the `role` and optionally the `authid` and
+//! `extra` of the session.  If it raises an error, is not registered or does not return in time,
+//! the session is rejected.
+use super::AuthIdentity;
+use crate::messages::{Dict, List, Reason, Value};
+use crate::router::config::AuthenticatorConfig;
+use crate::router::rpc::InvocationResult;
+
+/// An authentication that waits for the result of the authenticator.
+#[derive(Debug)]
+pub struct Authentication {
+    config: AuthenticatorConfig,
+    realm: String,
+    authmethod: String,
+    authid: Option<String>,
+    details: Dict,
+}
+
+impl Authentication {
+    pub fn new(
+        config: &AuthenticatorConfig,
+        realm: &str,
+        authmethod: &str,
+        authid: Option<String>,
+        details: Dict,
+    ) -> Authentication {
+        Authentication {
+            config: config.clone(),
+            realm: realm.to_string(),
+            authmethod: authmethod.to_string(),
+            authid,
+            details,
+        }
+    }
+
+    /// The realm the authenticator is registered in.
+    pub fn authenticator_realm(&self) -> &str {
+        self.config.realm.as_ref().unwrap_or(&self.realm)
+    }
+
+    pub fn procedure(&self) -> &str {
+        &self.config.procedure
+    }
+
+    /// The milliseconds to wait for the result of the authenticator.
+    pub fn timeout(&self) -> u64 {
+        self.config.timeout
+    }
+
+    pub fn arguments(&self) -> List {
+        let mut details = self.details.clone();
+        details.insert("authmethod".to_string(), Value::String(self.authmethod.clone()));
+        vec![
+            Value::String(self.realm.clone()),
+            Value::String(self.authid.clone().unwrap_or_default()),
+            Value::Dict(details),
+        ]
+    }
+
+    pub fn verify(self, result: InvocationResult) -> Result<AuthIdentity, Reason> {
+        let args = match result {
+            InvocationResult::Yield(args, _kwargs) => args.unwrap_or_default(),
+            InvocationResult::Error(reason, _args, _kwargs) => {
+                log::info!("Authenticator rejected {:?}: {}", self.authid, reason);
+                return Err(reason);
+            }
+        };
+        let (authid, authrole, authextra) = match args.into_iter().next() {
+            Some(Value::String(authrole)) => (self.authid.clone(), authrole, None),
+            Some(Value::Dict(mut result)) => {
+                let authid = match result.remove("authid") {
+                    Some(Value::String(authid)) => Some(authid),
+                    _ => self.authid.clone(),
+                };
+                let authrole = match result.remove("role") {
+                    Some(Value::String(authrole)) => authrole,
+                    _ => return Err(invalid_result("it contains no role")),
+                };
+                let authextra = match result.remove("extra") {
+                    Some(Value::Dict(extra)) => Some(extra),
+                    _ => None,
+                };
+                (authid, authrole, authextra)
+            }
+            _ => return Err(invalid_result("it is neither a role nor a dictionary")),
+        };
+        let authid = authid.ok_or_else(|| invalid_result("the session has no authid"))?;
+        Ok(AuthIdentity {
+            authid,
+            authrole,
+            authmethod: self.authmethod,
+            authprovider: "dynamic".to_string(),
+            authextra,
+        })
+    }
+}
+
+fn invalid_result(reason: &str) -> Reason {
+    log::warn!("Rejecting session, as the result of the authenticator is invalid: {}", reason);
+    Reason::AuthenticationFailed
+}
+
+#[cfg(test)]
+mod test {
+    use super::Authentication;
+    use crate::messages::{Reason, URI, Value};
+    use crate::router::config::AuthenticatorConfig;
+    use crate::router::rpc::InvocationResult;
+    use std::collections::HashMap;
+
+    fn authentication(authid: Option<&str>) -> Authentication {
+        let config = AuthenticatorConfig {
+            procedure: "com.example.authenticate".to_string(),
+            realm: Some("internal".to_string()),
+            authrole: "authenticator".to_string(),
+            timeout: 5000,
+        };
+        Authentication::new(&config, "default", "ticket", authid.map(str::to_string), HashMap::new())
+    }
+
+    #[test]
+    fn role() {
+        let auth = authentication(Some("joe"));
+        assert_eq!(auth.authenticator_realm(), "internal");
+        assert_eq!(auth.arguments()[0], Value::String("default".to_string()));
+        let identity = auth
+            .verify(InvocationResult::Yield(Some(vec![Value::String("user".to_string())]), None))
+            .unwrap();
+        assert_eq!(identity.authid, "joe");
+        assert_eq!(identity.authrole, "user");
+        assert_eq!(identity.authprovider, "dynamic");
+
+        assert_eq!(
+            authentication(None)
+                .verify(InvocationResult::Yield(Some(vec![Value::String("user".to_string())]), None)),
+            Err(Reason::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn dict() {
+        let mut result = HashMap::new();
+        result.insert("authid".to_string(), Value::String("alice".to_string()));
+        result.insert("role".to_string(), Value::String("admin".to_string()));
+        let identity = authentication(None)
+            .verify(InvocationResult::Yield(Some(vec![Value::Dict(result)]), None))
+            .unwrap();
+        assert_eq!(identity.authid, "alice");
+        assert_eq!(identity.authrole, "admin");
+    }
+
+    #[test]
+    fn error() {
+        let reason = Reason::CustomReason(URI::new("com.example.invalid_ticket"));
+        assert_eq!(
+            authentication(Some("joe")).verify(InvocationResult::Error(reason.clone(), None, None)),
+            Err(reason)
+        );
+        assert_eq!(
+            authentication(Some("joe")).verify(InvocationResult::Yield(None, None)),
+            Err(Reason::AuthenticationFailed)
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Contains the authentication methods a session can use to join a realm.
+//!
+//! Authentication happens between the `HELLO` and the `WELCOME` message.  A method either
+//! welcomes the session right away, or sends a `CHALLENGE` that the session has to answer with
+//! an `AUTHENTICATE` message.  Methods may also ask a procedure to authenticate the session.
+mod cryptosign;
+mod dynamic;
+mod jwt;
+mod scram;
+mod ticket;
+mod tls;
+mod unix;
+mod wampcra;
+
+pub use crate::router::auth::dynamic::Authentication as DynamicAuthentication;
+pub use crate::router::auth::ticket::{Principal, TicketValidator};
+
+use crate::messages::{Dict, HelloDetails, Reason, WelcomeDetails};
+use crate::router::config::RealmConfig;
+use crate::ID;
+
+/// The identity a session is welcomed with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthIdentity {
+    pub authid: String,
+    pub authrole: String,
+    pub authmethod: String,
+    pub authprovider: String,
+    pub authextra: Option<Dict>,
+}
+
+/// Properties of the transport of a session that methods can use to authenticate it.
+#[derive(Debug, Clone, Default)]
+pub struct TransportDetails {
+    /// The kind of transport, `websocket` or `rawsocket`.
+    pub kind: &'static str,
+    /// The `tls-unique` channel binding of a TLS 1.2 connection.
+    pub tls_unique: Option<Vec<u8>>,
+    /// The subject common names and alternative names of the verified client certificate.
+    pub client_names: Vec<String>,
+    /// The address of the peer.
+    pub peer: Option<String>,
+    /// The user and group of the peer of a Unix domain socket.
+    pub peer_credentials: Option<PeerCredentials>,
+}
+
+/// The user and group of the process on the other end of a Unix domain socket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// The state of an authentication that waits for the `AUTHENTICATE` message of the session.
+#[derive(Debug)]
+pub enum PendingAuthentication {
+    WampCra(wampcra::Challenge),
+    Ticket(ticket::Challenge),
+    Cryptosign(cryptosign::Challenge),
+    Scram(scram::Challenge),
+}
+
+/// The outcome of the `HELLO` or `AUTHENTICATE` message of a session.
+#[derive(Debug)]
+pub enum AuthStep {
+    Welcome(AuthIdentity),
+    Challenge(String, Dict, PendingAuthentication),
+    Invoke(DynamicAuthentication),
+}
+
+impl AuthIdentity {
+    fn anonymous(session_id: ID, role: &str) -> AuthIdentity {
+        AuthIdentity {
+            authid: session_id.to_string(),
+            authrole: role.to_string(),
+            authmethod: "anonymous".to_string(),
+            authprovider: "static".to_string(),
+            authextra: None,
+        }
+    }
+
+    /// Adds this identity to the details of a `WELCOME` message.
+    pub fn welcome_details(&self, mut details: WelcomeDetails) -> WelcomeDetails {
+        details.authid = Some(self.authid.clone());
+        details.authrole = Some(self.authrole.clone());
+        details.authmethod = Some(self.authmethod.clone());
+        details.authprovider = Some(self.authprovider.clone());
+        details.authextra = self.authextra.clone();
+        details
+    }
+}
+
+/// Picks the first authentication method announced by the session that the realm supports.
+///
+/// Sessions that do not announce any methods are treated as anonymous.
+pub fn hello(
+    realm: &RealmConfig,
+    session_id: ID,
+    details: &HelloDetails,
+    transport: &TransportDetails,
+) -> Result<AuthStep, Reason> {
+    let config = &realm.auth;
+    let authmethods = match details.authmethods {
+        Some(ref authmethods) if !authmethods.is_empty() => authmethods.clone(),
+        _ => vec!["anonymous".to_string()],
+    };
+    for authmethod in authmethods {
+        match authmethod.as_str() {
+            "anonymous" => if let Some(ref role) = config.anonymous {
+                return Ok(AuthStep::Welcome(AuthIdentity::anonymous(session_id, role)));
+            },
+            "wampcra" => if let Some(ref wampcra) = config.wampcra {
+                return wampcra::challenge(wampcra, session_id, details);
+            },
+            "ticket" => if let Some(ref ticket) = config.ticket {
+                return ticket::challenge(ticket, &realm.name, session_id, details);
+            },
+            "cryptosign" => if let Some(ref cryptosign) = config.cryptosign {
+                return cryptosign::challenge(cryptosign, details, transport);
+            },
+            "wamp-scram" => if let Some(ref scram) = config.scram {
+                return scram::challenge(scram, details, transport);
+            },
+            "tls" => if let Some(ref tls) = config.tls {
+                return tls::authenticate(tls, details, transport).map(AuthStep::Welcome);
+            },
+            "unix" => if let Some(ref unix) = config.unix {
+                return unix::authenticate(unix, details, transport).map(AuthStep::Welcome);
+            },
+            _ => {}
+        }
+    }
+    Err(Reason::NoAuthMethod)
+}
+
+/// Verifies the `AUTHENTICATE` message of a session against the challenge it was sent.
+pub fn authenticate(
+    pending: PendingAuthentication,
+    signature: &str,
+    extra: &Dict,
+) -> Result<AuthStep, Reason> {
+    match pending {
+        PendingAuthentication::WampCra(challenge) => challenge.verify(signature).map(AuthStep::Welcome),
+        PendingAuthentication::Ticket(challenge) => challenge.verify(signature),
+        PendingAuthentication::Cryptosign(challenge) => challenge.verify(signature).map(AuthStep::Welcome),
+        PendingAuthentication::Scram(challenge) => challenge.verify(signature, extra).map(AuthStep::Welcome),
+    }
+}
@@ -0,0 +1,304 @@
+//! Implements the `wamp-scram` authentication.
+//!
+//! Principals are stored as SCRAM verifiers.  A verifier contains the salt and the parameters of
+//! the key derivation function together with the stored and server keys derived from the
+//! password, so the router never learns the password itself.
+use super::{AuthIdentity, AuthStep, PendingAuthentication, TransportDetails};
+use crate::messages::{Dict, HelloDetails, Reason, Value};
+use crate::router::config::{ScramConfig, ScramKdf, ScramPrincipal};
+use rand::{thread_rng, Rng};
+use ring::constant_time::verify_slices_are_equal;
+use ring::{digest, hmac, pbkdf2};
+use std::num::NonZeroU32;
+
+/// A challenge that was sent to a session.
+#[derive(Debug)]
+pub struct Challenge {
+    identity: AuthIdentity,
+    nonce: String,
+    auth_message: String,
+    stored_key: Vec<u8>,
+    server_key: Vec<u8>,
+}
+
+pub fn challenge(
+    config: &ScramConfig,
+    details: &HelloDetails,
+    transport: &TransportDetails,
+) -> Result<AuthStep, Reason> {
+    let authid = details.authid.as_ref().ok_or_else(|| rejected("no authid was announced"))?;
+    let principal = config.principals.get(authid).ok_or_else(|| rejected("the authid is unknown"))?;
+    let authextra = details.authextra.clone().unwrap_or_default();
+    let client_nonce = match authextra.get("nonce") {
+        Some(Value::String(nonce)) => nonce.clone(),
+        _ => return Err(rejected("no client nonce was sent")),
+    };
+    let mut extra = Dict::new();
+    let channel_binding = match authextra.get("channel_binding") {
+        None => String::new(),
+        Some(Value::String(channel_binding)) if channel_binding == "tls-unique" => {
+            let tls_unique = transport.tls_unique
+                .as_ref()
+                .ok_or_else(|| rejected("tls-unique is not available on this transport"))?;
+            extra.insert("channel_binding".to_string(), Value::String(channel_binding.clone()));
+            base64::encode(tls_unique)
+        }
+        Some(_) => return Err(rejected("the channel binding is not supported")),
+    };
+    if base64::decode(&principal.salt).is_err() {
+        return Err(rejected("the salt of the principal is not base64 encoded"));
+    }
+
+    let nonce = format!("{}{}", client_nonce, base64::encode(&thread_rng().gen::<[u8; 16]>()));
+    let auth_message = format!(
+        "n={},r={},r={},s={},i={},c={},r={}",
+        authid, client_nonce, nonce, principal.salt, principal.iterations, channel_binding, nonce,
+    );
+    extra.insert("nonce".to_string(), Value::String(nonce.clone()));
+    extra.insert("salt".to_string(), Value::String(principal.salt.clone()));
+    extra.insert("kdf".to_string(), Value::String(principal.kdf.name().to_string()));
+    extra.insert("iterations".to_string(), Value::UnsignedInteger(principal.iterations as u64));
+    if principal.kdf == ScramKdf::Argon2id13 {
+        extra.insert("memory".to_string(), Value::UnsignedInteger(principal.memory as u64));
+    }
+
+    Ok(AuthStep::Challenge(
+        "wamp-scram".to_string(),
+        extra,
+        PendingAuthentication::Scram(Challenge {
+            identity: AuthIdentity {
+                authid: authid.clone(),
+                authrole: principal.role.clone(),
+                authmethod: "wamp-scram".to_string(),
+                authprovider: "static".to_string(),
+                authextra: None,
+            },
+            nonce,
+            auth_message,
+            stored_key: base64::decode(&principal.stored_key)
+                .map_err(|_| rejected("the stored key of the principal is not base64 encoded"))?,
+            server_key: base64::decode(&principal.server_key)
+                .map_err(|_| rejected("the server key of the principal is not base64 encoded"))?,
+        }),
+    ))
+}
+
+fn rejected(reason: &str) -> Reason {
+    log::info!("Rejecting wamp-scram authentication: {}", reason);
+    Reason::AuthenticationFailed
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data).as_ref().to_vec()
+}
+
+impl Challenge {
+    /// Verifies the base64 encoded client proof.  The server signature is passed back to the
+    /// client in the `authextra` of the `WELCOME` message.
+    pub fn verify(self, proof: &str, extra: &Dict) -> Result<AuthIdentity, Reason> {
+        match extra.get("nonce") {
+            Some(Value::String(nonce)) if *nonce == self.nonce => {}
+            Some(_) => return Err(rejected("the nonce does not match")),
+            None => return Err(rejected("no nonce was sent")),
+        }
+        let proof = base64::decode(proof).map_err(|_| rejected("the proof is not base64 encoded"))?;
+        let client_signature = hmac_sha256(&self.stored_key, self.auth_message.as_bytes());
+        if proof.len() != client_signature.len() {
+            return Err(rejected("the proof has an invalid length"));
+        }
+        let client_key = proof.iter()
+            .zip(&client_signature)
+            .map(|(proof, signature)| proof ^ signature)
+            .collect::<Vec<_>>();
+        verify_slices_are_equal(digest::digest(&digest::SHA256, &client_key).as_ref(), &self.stored_key)
+            .map_err(|_| rejected("the proof is invalid"))?;
+
+        let server_signature = hmac_sha256(&self.server_key, self.auth_message.as_bytes());
+        let mut authextra = Dict::new();
+        authextra.insert(
+            "scram_server_signature".to_string(),
+            Value::String(base64::encode(&server_signature)),
+        );
+        let mut identity = self.identity;
+        identity.authextra = Some(authextra);
+        Ok(identity)
+    }
+}
+
+impl ScramKdf {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ScramKdf::Argon2id13 => "argon2id13",
+            ScramKdf::Pbkdf2 => "pbkdf2",
+        }
+    }
+
+    /// The iterations used for new verifiers if none are given.
+    pub fn default_iterations(&self) -> u32 {
+        match *self {
+            ScramKdf::Argon2id13 => 3,
+            ScramKdf::Pbkdf2 => 100_000,
+        }
+    }
+}
+
+/// Derives the salted password from the password and the salt.
+fn salted_password(
+    password: &str,
+    salt: &[u8],
+    kdf: ScramKdf,
+    iterations: u32,
+    memory: u32,
+) -> Result<Vec<u8>, argon2::Error> {
+    match kdf {
+        ScramKdf::Argon2id13 => argon2::hash_raw(password.as_bytes(), salt, &argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: memory,
+            time_cost: iterations,
+            lanes: 1,
+            thread_mode: argon2::ThreadMode::Sequential,
+            secret: &[],
+            ad: &[],
+            hash_length: 32,
+        }),
+        ScramKdf::Pbkdf2 => {
+            let mut salted_password = vec![0u8; 32];
+            pbkdf2::derive(
+                pbkdf2::PBKDF2_HMAC_SHA256,
+                NonZeroU32::new(iterations).unwrap_or_else(|| NonZeroU32::new(1).unwrap()),
+                salt,
+                password.as_bytes(),
+                &mut salted_password,
+            );
+            Ok(salted_password)
+        }
+    }
+}
+
+impl ScramPrincipal {
+    /// Creates the verifier of a principal from its password using a random salt.
+    pub fn new(
+        password: &str,
+        role: &str,
+        kdf: ScramKdf,
+        iterations: u32,
+        memory: u32,
+    ) -> Result<ScramPrincipal, argon2::Error> {
+        ScramPrincipal::with_salt(password, role, kdf, &thread_rng().gen::<[u8; 16]>(), iterations, memory)
+    }
+
+    fn with_salt(
+        password: &str,
+        role: &str,
+        kdf: ScramKdf,
+        salt: &[u8],
+        iterations: u32,
+        memory: u32,
+    ) -> Result<ScramPrincipal, argon2::Error> {
+        let salted_password = salted_password(password, salt, kdf, iterations, memory)?;
+        let client_key = hmac_sha256(&salted_password, b"Client Key");
+        let server_key = hmac_sha256(&salted_password, b"Server Key");
+        Ok(ScramPrincipal {
+            role: role.to_string(),
+            kdf,
+            salt: base64::encode(salt),
+            iterations,
+            memory,
+            stored_key: base64::encode(digest::digest(&digest::SHA256, &client_key).as_ref()),
+            server_key: base64::encode(&server_key),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{challenge, hmac_sha256, salted_password};
+    use crate::messages::{ClientRoles, Dict, HelloDetails, Reason, Value};
+    use crate::router::auth::{AuthStep, PendingAuthentication, TransportDetails};
+    use crate::router::config::{ScramConfig, ScramKdf, ScramPrincipal};
+    use ring::digest;
+    use std::collections::HashMap;
+
+    /// Computes the client proof for a challenge like a client would.
+    fn client_proof(password: &str, extra: &Dict) -> (String, String) {
+        let get = |key: &str| match extra.get(key) {
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::UnsignedInteger(value)) => value.to_string(),
+            _ => panic!("challenge is missing {}", key),
+        };
+        let kdf = if get("kdf") == "pbkdf2" { ScramKdf::Pbkdf2 } else { ScramKdf::Argon2id13 };
+        let memory = if kdf == ScramKdf::Pbkdf2 { 0 } else { get("memory").parse().unwrap() };
+        let nonce = get("nonce");
+        let salt = base64::decode(&get("salt")).unwrap();
+        let salted = salted_password(password, &salt, kdf, get("iterations").parse().unwrap(), memory).unwrap();
+        let client_key = hmac_sha256(&salted, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, &client_key);
+        let auth_message = format!(
+            "n=joe,r=client-nonce,r={},s={},i={},c=,r={}",
+            nonce, get("salt"), get("iterations"), nonce,
+        );
+        let signature = hmac_sha256(stored_key.as_ref(), auth_message.as_bytes());
+        let proof = client_key.iter().zip(&signature).map(|(a, b)| a ^ b).collect::<Vec<_>>();
+        (base64::encode(&proof), nonce)
+    }
+
+    fn authenticate(principal: ScramPrincipal, password: &str) -> Result<Dict, Reason> {
+        authenticate_with(principal, password, true)
+    }
+
+    fn authenticate_with(principal: ScramPrincipal, password: &str, send_nonce: bool) -> Result<Dict, Reason> {
+        let mut principals = HashMap::new();
+        principals.insert("joe".to_string(), principal);
+        let config = ScramConfig { principals };
+        let mut details = HelloDetails::new(ClientRoles::new());
+        let mut authextra = HashMap::new();
+        authextra.insert("nonce".to_string(), Value::String("client-nonce".to_string()));
+        details.authid = Some("joe".to_string());
+        details.authmethods = Some(vec!["wamp-scram".to_string()]);
+        details.authextra = Some(authextra);
+        match challenge(&config, &details, &TransportDetails::default())? {
+            AuthStep::Challenge(_, extra, PendingAuthentication::Scram(challenge)) => {
+                let (proof, nonce) = client_proof(password, &extra);
+                let mut extra = HashMap::new();
+                if send_nonce {
+                    extra.insert("nonce".to_string(), Value::String(nonce));
+                }
+                challenge.verify(&proof, &extra).map(|identity| identity.authextra.unwrap())
+            }
+            _ => panic!("expected a wamp-scram challenge"),
+        }
+    }
+
+    #[test]
+    fn pbkdf2_verifier() {
+        let principal = ScramPrincipal::with_salt(
+            "pencil", "user", ScramKdf::Pbkdf2, b"saltsaltsaltsalt", 4096, 0,
+        ).unwrap();
+        assert_eq!(principal.stored_key, "Y7KMtnzzL+zDqi0RtBNdAPJekq+PCg4eCcAMKDtLILQ=");
+        assert_eq!(principal.server_key, "c1MMj1kLUJb96FIM7IjzQUXiW2pIi8LDbQl55vL1oRo=");
+
+        assert!(authenticate(principal.clone(), "pencil").unwrap().contains_key("scram_server_signature"));
+        assert_eq!(authenticate(principal, "crayon"), Err(Reason::AuthenticationFailed));
+    }
+
+    #[test]
+    fn missing_nonce() {
+        let principal = ScramPrincipal::new("pencil", "user", ScramKdf::Pbkdf2, 1, 0).unwrap();
+        assert_eq!(authenticate_with(principal, "pencil", false), Err(Reason::AuthenticationFailed));
+    }
+
+    #[test]
+    fn invalid_salt() {
+        let mut principal = ScramPrincipal::new("pencil", "user", ScramKdf::Pbkdf2, 1, 0).unwrap();
+        principal.salt = "not base64!".to_string();
+        assert_eq!(authenticate(principal, "pencil"), Err(Reason::AuthenticationFailed));
+    }
+
+    #[test]
+    fn argon2id13_verifier() {
+        let principal = ScramPrincipal::new("pencil", "user", ScramKdf::Argon2id13, 1, 1024).unwrap();
+        assert!(authenticate(principal.clone(), "pencil").is_ok());
+        assert_eq!(authenticate(principal, "crayon"), Err(Reason::AuthenticationFailed));
+    }
+}
@@ -0,0 +1,209 @@
+//! Implements the `ticket` authentication.
+//!
+//! The router answers the `HELLO` with an empty challenge and the client sends its ticket in the
+//! `AUTHENTICATE` message.  Tickets are checked against the static principals of the realm first,
+//! then verified as JSON web tokens, handed to the `TicketValidator` of the realm and finally
+//! passed to the authenticator procedure of the realm.
+use super::{jwt, AuthIdentity, AuthStep, DynamicAuthentication, PendingAuthentication};
+use crate::messages::{Dict, HelloDetails, Reason, Value};
+use crate::ID;
+use crate::router::config::TicketConfig;
+use ring::constant_time::verify_slices_are_equal;
+
+/// The identity a ticket authenticates a session as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub authid: String,
+    pub authrole: String,
+    pub authextra: Option<Dict>,
+}
+
+/// Validates tickets of sessions joining a realm.
+///
+/// Embedders of the router can implement this trait to check tickets against their own session
+/// store, e.g. to reuse a session token of a web application as the WAMP ticket.
+pub trait TicketValidator: Send + Sync {
+    /// Returns the principal the ticket belongs to, or `None` if the ticket is not valid.
+    ///
+    /// The `authid` is the one the client announced in its `HELLO` message, if any.
+    fn validate(&self, realm: &str, authid: Option<&str>, ticket: &str) -> Option<Principal>;
+}
+
+/// A ticket challenge that was sent to a session.
+#[derive(Debug)]
+pub struct Challenge {
+    realm: String,
+    session_id: ID,
+    authid: Option<String>,
+    config: TicketConfig,
+}
+
+pub fn challenge(
+    config: &TicketConfig,
+    realm: &str,
+    session_id: ID,
+    details: &HelloDetails,
+) -> Result<AuthStep, Reason> {
+    Ok(AuthStep::Challenge(
+        "ticket".to_string(),
+        Dict::new(),
+        PendingAuthentication::Ticket(Challenge {
+            realm: realm.to_string(),
+            session_id,
+            authid: details.authid.clone(),
+            config: config.clone(),
+        }),
+    ))
+}
+
+impl Challenge {
+    pub fn verify(self, ticket: &str) -> Result<AuthStep, Reason> {
+        if let Some(ref authid) = self.authid {
+            if let Some(principal) = self.config.principals.get(authid) {
+                return match verify_slices_are_equal(principal.ticket.as_bytes(), ticket.as_bytes()) {
+                    Ok(()) => Ok(AuthStep::Welcome(AuthIdentity {
+                        authid: authid.clone(),
+                        authrole: principal.role.clone(),
+                        authmethod: "ticket".to_string(),
+                        authprovider: "static".to_string(),
+                        authextra: None,
+                    })),
+                    Err(_) => {
+                        log::info!("Invalid ticket for {}", authid);
+                        Err(Reason::AuthenticationFailed)
+                    }
+                };
+            }
+        }
+
+        if let Some(ref config) = self.config.jwt {
+            match jwt::verify(config, ticket) {
+                Ok(principal) => return Ok(AuthStep::Welcome(AuthIdentity::ticket(principal, "jwt"))),
+                Err(reason) => if self.config.validator.is_none() && self.config.authenticator.is_none() {
+                    return Err(reason);
+                },
+            }
+        }
+
+        if let Some(ref validator) = self.config.validator {
+            match validator.validate(&self.realm, self.authid.as_ref().map(String::as_str), ticket) {
+                Some(principal) => return Ok(AuthStep::Welcome(AuthIdentity::ticket(principal, "dynamic"))),
+                None => log::info!("Ticket validator rejected {:?}", self.authid),
+            }
+        }
+
+        match self.config.authenticator {
+            Some(ref authenticator) => {
+                let mut details = Dict::new();
+                details.insert("ticket".to_string(), Value::String(ticket.to_string()));
+                details.insert("session".to_string(), Value::UnsignedInteger(self.session_id));
+                Ok(AuthStep::Invoke(DynamicAuthentication::new(
+                    authenticator,
+                    &self.realm,
+                    "ticket",
+                    self.authid,
+                    details,
+                )))
+            }
+            None => Err(Reason::AuthenticationFailed),
+        }
+    }
+}
+
+impl AuthIdentity {
+    fn ticket(principal: Principal, authprovider: &str) -> AuthIdentity {
+        AuthIdentity {
+            authid: principal.authid,
+            authrole: principal.authrole,
+            authmethod: "ticket".to_string(),
+            authprovider: authprovider.to_string(),
+            authextra: principal.authextra,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Challenge, Principal, TicketValidator};
+    use crate::messages::Reason;
+    use crate::router::auth::{AuthIdentity, AuthStep};
+    use crate::router::config::{AuthenticatorConfig, TicketConfig, TicketPrincipal};
+    use std::sync::Arc;
+
+    struct TokenValidator;
+
+    impl TicketValidator for TokenValidator {
+        fn validate(&self, _realm: &str, _authid: Option<&str>, ticket: &str) -> Option<Principal> {
+            if ticket == "session-token" {
+                Some(Principal {
+                    authid: "alice".to_string(),
+                    authrole: "user".to_string(),
+                    authextra: None,
+                })
+            } else {
+                None
+            }
+        }
+    }
+
+    fn challenge(authid: Option<&str>) -> Challenge {
+        let mut config = TicketConfig::default();
+        config.principals.insert("backend".to_string(), TicketPrincipal {
+            ticket: "secret".to_string(),
+            role: "backend".to_string(),
+        });
+        config.validator = Some(Arc::new(TokenValidator));
+        Challenge {
+            realm: "default".to_string(),
+            session_id: 1,
+            authid: authid.map(|authid| authid.to_string()),
+            config,
+        }
+    }
+
+    fn welcome(step: Result<AuthStep, Reason>) -> Result<AuthIdentity, Reason> {
+        match step? {
+            AuthStep::Welcome(identity) => Ok(identity),
+            step => panic!("expected a welcome, got {:?}", step),
+        }
+    }
+
+    #[test]
+    fn static_principals() {
+        let identity = welcome(challenge(Some("backend")).verify("secret")).unwrap();
+        assert_eq!(identity.authid, "backend");
+        assert_eq!(identity.authrole, "backend");
+        assert_eq!(identity.authprovider, "static");
+        assert_eq!(
+            welcome(challenge(Some("backend")).verify("session-token")),
+            Err(Reason::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn validator() {
+        let identity = welcome(challenge(None).verify("session-token")).unwrap();
+        assert_eq!(identity.authid, "alice");
+        assert_eq!(identity.authrole, "user");
+        assert_eq!(identity.authprovider, "dynamic");
+        assert_eq!(welcome(challenge(None).verify("secret")), Err(Reason::AuthenticationFailed));
+    }
+
+    #[test]
+    fn authenticator() {
+        let mut challenge = challenge(Some("joe"));
+        challenge.config.authenticator = Some(AuthenticatorConfig {
+            procedure: "com.example.authenticate".to_string(),
+            realm: None,
+            authrole: "authenticator".to_string(),
+            timeout: 5000,
+        });
+        match challenge.verify("unknown") {
+            Ok(AuthStep::Invoke(authentication)) => {
+                assert_eq!(authentication.procedure(), "com.example.authenticate");
+                assert_eq!(authentication.authenticator_realm(), "default");
+            }
+            step => panic!("expected an invocation, got {:?}", step),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Implements the `tls` authentication with client certificates.
+//!
+//! The certificate is verified during the TLS handshake, so the session is welcomed right away
+//! if one of the names of its certificate belongs to a principal.
+use super::{AuthIdentity, TransportDetails};
+use crate::messages::{HelloDetails, Reason};
+use crate::router::config::TlsAuthConfig;
+
+pub fn authenticate(
+    config: &TlsAuthConfig,
+    details: &HelloDetails,
+    transport: &TransportDetails,
+) -> Result<AuthIdentity, Reason> {
+    let principal = transport.client_names
+        .iter()
+        .filter_map(|name| config.principals.get(name).map(|principal| (name, principal)))
+        .map(|(name, principal)| (principal.authid.clone().unwrap_or_else(|| name.clone()), principal))
+        .find(|(authid, _)| details.authid.as_ref().map_or(true, |announced| announced == authid));
+    match principal {
+        Some((authid, principal)) => Ok(AuthIdentity {
+            authid,
+            authrole: principal.role.clone(),
+            authmethod: "tls".to_string(),
+            authprovider: "static".to_string(),
+            authextra: None,
+        }),
+        None => {
+            log::info!("No principal matches the client certificate {:?}", transport.client_names);
+            Err(Reason::AuthenticationFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::authenticate;
+    use crate::messages::{ClientRoles, HelloDetails, Reason};
+    use crate::router::auth::TransportDetails;
+    use crate::router::config::{TlsAuthConfig, TlsPrincipal};
+    use std::collections::HashMap;
+
+    fn config() -> TlsAuthConfig {
+        let mut principals = HashMap::new();
+        principals.insert("billing.internal".to_string(), TlsPrincipal {
+            authid: Some("billing".to_string()),
+            role: "backend".to_string(),
+        });
+        principals.insert("audit.internal".to_string(), TlsPrincipal {
+            authid: None,
+            role: "auditor".to_string(),
+        });
+        TlsAuthConfig { principals }
+    }
+
+    fn transport(names: &[&str]) -> TransportDetails {
+        TransportDetails {
+            client_names: names.iter().map(|name| name.to_string()).collect(),
+            ..TransportDetails::default()
+        }
+    }
+
+    #[test]
+    fn principals() {
+        let details = HelloDetails::new(ClientRoles::new());
+        let identity = authenticate(&config(), &details, &transport(&["billing.internal"])).unwrap();
+        assert_eq!(identity.authid, "billing");
+        assert_eq!(identity.authrole, "backend");
+        assert_eq!(identity.authmethod, "tls");
+
+        let identity = authenticate(&config(), &details, &transport(&["Audit", "audit.internal"])).unwrap();
+        assert_eq!(identity.authid, "audit.internal");
+
+        assert_eq!(
+            authenticate(&config(), &details, &transport(&[])),
+            Err(Reason::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn announced_authid() {
+        let mut details = HelloDetails::new(ClientRoles::new());
+        details.authid = Some("someone-else".to_string());
+        assert_eq!(
+            authenticate(&config(), &details, &transport(&["billing.internal"])),
+            Err(Reason::AuthenticationFailed)
+        );
+    }
+}
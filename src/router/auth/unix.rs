@@ -0,0 +1,96 @@
+//! Implements the `unix` authentication with the credentials of a Unix domain socket peer.
+//!
+//! The kernel reports the user and group of the process that connected to the socket, so the
+//! session is welcomed right away if they belong to a principal.
+use super::{AuthIdentity, TransportDetails};
+use crate::messages::{HelloDetails, Reason};
+use crate::router::config::UnixAuthConfig;
+
+pub fn authenticate(
+    config: &UnixAuthConfig,
+    details: &HelloDetails,
+    transport: &TransportDetails,
+) -> Result<AuthIdentity, Reason> {
+    let credentials = match transport.peer_credentials {
+        Some(ref credentials) => credentials,
+        None => {
+            log::info!("The transport of the session does not report peer credentials");
+            return Err(Reason::AuthenticationFailed);
+        }
+    };
+    let principal = config.principals
+        .iter()
+        .filter(|principal| principal.restricts_peer())
+        .filter(|principal| principal.uid.map_or(true, |uid| uid == credentials.uid))
+        .filter(|principal| principal.gid.map_or(true, |gid| gid == credentials.gid))
+        .map(|principal| (principal.authid.clone().unwrap_or_else(|| credentials.uid.to_string()), principal))
+        .find(|(authid, _)| details.authid.as_ref().map_or(true, |announced| announced == authid));
+    match principal {
+        Some((authid, principal)) => Ok(AuthIdentity {
+            authid,
+            authrole: principal.role.clone(),
+            authmethod: "unix".to_string(),
+            authprovider: "static".to_string(),
+            authextra: None,
+        }),
+        None => {
+            log::info!("No principal matches the peer credentials {:?}", credentials);
+            Err(Reason::AuthenticationFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::authenticate;
+    use crate::messages::{ClientRoles, HelloDetails, Reason};
+    use crate::router::auth::{PeerCredentials, TransportDetails};
+    use crate::router::config::{UnixAuthConfig, UnixPrincipal};
+
+    fn config() -> UnixAuthConfig {
+        UnixAuthConfig {
+            principals: vec![
+                UnixPrincipal { uid: Some(1000), gid: None, authid: Some("sidecar".to_string()), role: "backend".to_string() },
+                UnixPrincipal { uid: None, gid: Some(2000), authid: None, role: "monitor".to_string() },
+            ],
+        }
+    }
+
+    fn transport(uid: u32, gid: u32) -> TransportDetails {
+        TransportDetails {
+            peer_credentials: Some(PeerCredentials { uid, gid }),
+            ..TransportDetails::default()
+        }
+    }
+
+    #[test]
+    fn principals() {
+        let mut details = HelloDetails::new(ClientRoles::new());
+        let identity = authenticate(&config(), &details, &transport(1000, 1000)).unwrap();
+        assert_eq!(identity.authid, "sidecar");
+        assert_eq!(identity.authrole, "backend");
+        assert_eq!(identity.authmethod, "unix");
+
+        let identity = authenticate(&config(), &details, &transport(1001, 2000)).unwrap();
+        assert_eq!(identity.authid, "1001");
+        assert_eq!(identity.authrole, "monitor");
+
+        assert_eq!(authenticate(&config(), &details, &transport(1001, 1001)), Err(Reason::AuthenticationFailed));
+        assert_eq!(
+            authenticate(&config(), &details, &TransportDetails::default()),
+            Err(Reason::AuthenticationFailed)
+        );
+
+        details.authid = Some("1000".to_string());
+        assert_eq!(authenticate(&config(), &details, &transport(1000, 1000)), Err(Reason::AuthenticationFailed));
+    }
+
+    #[test]
+    fn unrestricted_principal() {
+        let config = UnixAuthConfig {
+            principals: vec![UnixPrincipal { uid: None, gid: None, authid: None, role: "admin".to_string() }],
+        };
+        let details = HelloDetails::new(ClientRoles::new());
+        assert_eq!(authenticate(&config, &details, &transport(1001, 1001)), Err(Reason::AuthenticationFailed));
+    }
+}
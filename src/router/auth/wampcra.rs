@@ -0,0 +1,145 @@
+//! Implements the `wampcra` challenge-response authentication.
+//!
+//! The router sends a JSON encoded challenge, which the client signs with HMAC-SHA256 using its
+//! secret.  Salted secrets are derived with PBKDF2-HMAC-SHA256 first, as done by Autobahn.
+use super::{AuthIdentity, AuthStep, PendingAuthentication};
+use crate::messages::{Dict, HelloDetails, Reason, Value};
+use crate::router::config::WampCraConfig;
+use crate::ID;
+use rand::{thread_rng, Rng};
+use ring::{hmac, pbkdf2};
+use serde_json::json;
+use std::num::NonZeroU32;
+
+/// A challenge that was sent to a session.
+#[derive(Debug)]
+pub struct Challenge {
+    identity: AuthIdentity,
+    challenge: String,
+    key: Vec<u8>,
+}
+
+pub fn challenge(config: &WampCraConfig, session_id: ID, details: &HelloDetails) -> Result<AuthStep, Reason> {
+    let authid = details.authid.as_ref().ok_or(Reason::AuthenticationFailed)?;
+    let principal = config.principals.get(authid).ok_or_else(|| {
+        log::info!("Unknown wampcra principal {}", authid);
+        Reason::AuthenticationFailed
+    })?;
+    let identity = AuthIdentity {
+        authid: authid.clone(),
+        authrole: principal.role.clone(),
+        authmethod: "wampcra".to_string(),
+        authprovider: "static".to_string(),
+        authextra: None,
+    };
+    let nonce = base64::encode(&thread_rng().gen::<[u8; 16]>());
+    let challenge = json!({
+        "authid": identity.authid,
+        "authrole": identity.authrole,
+        "authmethod": identity.authmethod,
+        "authprovider": identity.authprovider,
+        "nonce": nonce,
+        "session": session_id,
+    }).to_string();
+
+    let mut extra = Dict::new();
+    extra.insert("challenge".to_string(), Value::String(challenge.clone()));
+    let key = match principal.salt {
+        Some(ref salt) => {
+            extra.insert("salt".to_string(), Value::String(salt.clone()));
+            extra.insert("iterations".to_string(), Value::UnsignedInteger(principal.iterations as u64));
+            extra.insert("keylen".to_string(), Value::UnsignedInteger(principal.keylen as u64));
+            derive_key(&principal.secret, salt, principal.iterations, principal.keylen as usize)
+        }
+        None => principal.secret.as_bytes().to_vec(),
+    };
+
+    Ok(AuthStep::Challenge(
+        "wampcra".to_string(),
+        extra,
+        PendingAuthentication::WampCra(Challenge { identity, challenge, key }),
+    ))
+}
+
+/// Derives the key a client signs the challenge with from a salted secret.
+pub fn derive_key(secret: &str, salt: &str, iterations: u32, keylen: usize) -> Vec<u8> {
+    let mut key = vec![0u8; keylen];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(iterations).unwrap_or_else(|| NonZeroU32::new(1).unwrap()),
+        salt.as_bytes(),
+        secret.as_bytes(),
+        &mut key,
+    );
+    base64::encode(&key).into_bytes()
+}
+
+impl Challenge {
+    pub fn verify(self, signature: &str) -> Result<AuthIdentity, Reason> {
+        let signature = base64::decode(signature).map_err(|_| Reason::AuthenticationFailed)?;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.key);
+        match hmac::verify(&key, self.challenge.as_bytes(), &signature) {
+            Ok(()) => Ok(self.identity),
+            Err(_) => {
+                log::info!("Invalid wampcra signature for {}", self.identity.authid);
+                Err(Reason::AuthenticationFailed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{derive_key, Challenge};
+    use crate::router::auth::AuthIdentity;
+    use crate::messages::Reason;
+    use ring::hmac;
+
+    fn compute_signature(key: &[u8], challenge: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        base64::encode(hmac::sign(&key, challenge.as_bytes()).as_ref())
+    }
+
+    fn identity() -> AuthIdentity {
+        AuthIdentity {
+            authid: "joe".to_string(),
+            authrole: "frontend".to_string(),
+            authmethod: "wampcra".to_string(),
+            authprovider: "static".to_string(),
+            authextra: None,
+        }
+    }
+
+    #[test]
+    fn salted_key() {
+        let key = derive_key("secret123", "salt123", 1000, 32);
+        assert_eq!(key, b"Eu7CQLfR+/Ffb+275A4s9/6H/RGKYxM4s6IMrsNKzC8=".to_vec());
+        assert_eq!(
+            compute_signature(&key, "{\"nonce\":\"abc\"}"),
+            "8xAPKbPsxfCFKz6H1roFoJf8DBlJFWc2j8HYr3YUB+Y="
+        );
+    }
+
+    #[test]
+    fn verify_signature() {
+        let challenge = Challenge {
+            identity: identity(),
+            challenge: "{\"nonce\":\"abc\"}".to_string(),
+            key: b"secret123".to_vec(),
+        };
+        assert_eq!(
+            challenge.verify("lYIB7ZG/9J/87flwH78c09VppbgU99xcrOLMMilJsrw="),
+            Ok(identity())
+        );
+
+        let challenge = Challenge {
+            identity: identity(),
+            challenge: "{\"nonce\":\"abc\"}".to_string(),
+            key: b"secret123".to_vec(),
+        };
+        assert_eq!(
+            challenge.verify("8xAPKbPsxfCFKz6H1roFoJf8DBlJFWc2j8HYr3YUB+Y="),
+            Err(Reason::AuthenticationFailed)
+        );
+    }
+}
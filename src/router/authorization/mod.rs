@@ -0,0 +1,498 @@
+//! Decides whether a session may publish, subscribe, call or register a URI.
+//!
+//! The permissions are configured per authrole of a realm.  Realms without roles allow every
+//! action.  Actions that no permission mentions can be decided by an authorizer procedure.  While
+//! the authorizer decides, all later messages of the session wait, so they are handled in
+//! order.  Decisions are remembered by the handler of the session, so they are forgotten when
+//! the session leaves.
+//!
+//! The authenticator and authorizer procedures of the realms decide who may join and what
+//! sessions may do, so only sessions with the trusted authrole of such a procedure may register
+//! it, whatever the permissions say.
+use super::ConnectionHandler;
+
+use crate::messages::{Dict, Message, Value};
+use crate::router::config::{Action, AuthorizerConfig, Permission, RealmConfig, RouterConfig};
+use crate::router::rpc::{InvocationResult, INVOCATION_TIMEOUT};
+use crate::{MatchingPolicy, WampResult, ID};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Whether a session may perform an action.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    /// The action is allowed.  If `disclose` is set, the identity of the session is disclosed to
+    /// the receivers of its publications.
+    Allow { disclose: bool },
+    Deny,
+}
+
+/// The decisions of the authorizer for a session.
+#[derive(Debug, Default)]
+pub struct Authorizations {
+    cache: HashMap<(String, Action), (Decision, Instant)>,
+    pending: Option<PendingAuthorization>,
+    parked: VecDeque<Message>,
+}
+
+/// A message that waits for the decision of the authorizer.
+#[derive(Debug)]
+struct PendingAuthorization {
+    invocation_id: ID,
+    deadline: Instant,
+    cache_ttl: Duration,
+    uri: String,
+    action: Action,
+    message: Message,
+}
+
+/// The outcome of the static permissions.
+enum Check<'a> {
+    Decided(Decision),
+    Ask(&'a AuthorizerConfig),
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Publish => "publish",
+            Action::Subscribe => "subscribe",
+            Action::Call => "call",
+            Action::Register => "register",
+        }
+    }
+}
+
+impl Permission {
+    fn matches(&self, uri: &str) -> bool {
+        self.matching_policy.matches(&self.uri, uri)
+    }
+
+    /// Orders permissions from wildcards over prefixes to exact patterns.  Longer prefixes and
+    /// wildcards with more fixed segments are more specific.
+    fn specificity(&self) -> (u8, usize) {
+        match self.matching_policy {
+            MatchingPolicy::Strict => (2, self.uri.len()),
+            MatchingPolicy::Prefix => (1, self.uri.len()),
+            MatchingPolicy::Wildcard => {
+                (0, self.uri.split('.').filter(|segment| !segment.is_empty()).count())
+            }
+        }
+    }
+}
+
+/// Checks the action against the permissions of the authrole.
+///
+/// If permissions with the same specificity disagree, the action is denied.
+fn check<'a>(realm: &'a RealmConfig, authrole: &str, uri: &str, action: Action) -> Check<'a> {
+    let roles = match realm.roles {
+        Some(ref roles) => roles,
+        None => return Check::Decided(Decision::Allow { disclose: false }),
+    };
+    let role = match roles.get(authrole) {
+        Some(role) => role,
+        None => return Check::Decided(Decision::Deny),
+    };
+    let allowed = role.permissions.iter()
+        .filter(|permission| permission.allow.contains(&action) || permission.deny.contains(&action))
+        .filter(|permission| permission.matches(uri))
+        .max_by_key(|permission| (permission.specificity(), permission.deny.contains(&action)))
+        .map(|permission| !permission.deny.contains(&action));
+    match (allowed, &role.authorizer) {
+        (Some(true), _) => Check::Decided(Decision::Allow { disclose: false }),
+        (Some(false), _) | (None, None) => Check::Decided(Decision::Deny),
+        (None, Some(authorizer)) => Check::Ask(authorizer),
+    }
+}
+
+/// Whether a permission of the authrole allows the action.  Realms without roles and decisions
+/// of the authorizer grant nothing.
+pub fn granted(realm: &RealmConfig, authrole: &str, uri: &str, action: Action) -> bool {
+    realm.roles.is_some() && match check(realm, authrole, uri, action) {
+        Check::Decided(decision) => decision != Decision::Deny,
+        Check::Ask(_) => false,
+    }
+}
+
+/// Whether sessions of the authrole may set `disclose_me` on their publications.  Realms without
+/// roles allow it.
+pub fn may_disclose(realm: &RealmConfig, authrole: &str) -> bool {
+    match realm.roles {
+        Some(ref roles) => roles.get(authrole).map_or(false, |role| role.disclose_me),
+        None => true,
+    }
+}
+
+/// Returns the realm, the URI and the trusted authrole of the authenticator and authorizer
+/// procedures of every realm.
+fn trusted_procedures(config: &RouterConfig) -> Vec<(&str, &str, &str)> {
+    let mut procedures = Vec::new();
+    for realm in &config.realms {
+        let authenticator = realm.auth.ticket.as_ref().and_then(|ticket| ticket.authenticator.as_ref());
+        if let Some(authenticator) = authenticator {
+            let authenticator_realm = authenticator.realm.as_ref().unwrap_or(&realm.name);
+            procedures.push((
+                authenticator_realm.as_str(),
+                authenticator.procedure.as_str(),
+                authenticator.authrole.as_str(),
+            ));
+        }
+        let authorizers = realm.roles.iter()
+            .flat_map(|roles| roles.values())
+            .filter_map(|role| role.authorizer.as_ref());
+        for authorizer in authorizers {
+            let authorizer_realm = authorizer.realm.as_ref().unwrap_or(&realm.name);
+            procedures.push((
+                authorizer_realm.as_str(),
+                authorizer.procedure.as_str(),
+                authorizer.authrole.as_str(),
+            ));
+        }
+    }
+    procedures
+}
+
+/// Denies registering the authenticator or authorizer procedure of a realm to sessions without
+/// the trusted authrole of the procedure.
+fn guard(config: &RouterConfig, realm: &str, authrole: &str, uri: &str, action: Action) -> Option<Decision> {
+    if action != Action::Register {
+        return None;
+    }
+    let trusted = trusted_procedures(config)
+        .into_iter()
+        .filter(|(procedure_realm, procedure, _)| *procedure == uri && *procedure_realm == realm)
+        .map(|(_, _, trusted_authrole)| trusted_authrole == authrole)
+        .collect::<Vec<_>>();
+    if trusted.is_empty() || trusted.iter().all(|trusted| *trusted) {
+        None
+    } else {
+        log::warn!("Refusing to let a session with authrole {} register the procedure {}", authrole, uri);
+        Some(Decision::Deny)
+    }
+}
+
+/// Returns the URI, the action and the options of a message that needs to be authorized.
+fn action_of(message: &Message) -> Option<(&str, Action, Value)> {
+    match message {
+        Message::Publish(_, options, topic, _, _) => {
+            Some((topic.uri.as_str(), Action::Publish, to_value(options)))
+        }
+        Message::Subscribe(_, options, topic) => {
+            Some((topic.uri.as_str(), Action::Subscribe, to_value(options)))
+        }
+        Message::Register(_, options, procedure) => {
+            Some((procedure.uri.as_str(), Action::Register, to_value(options)))
+        }
+        Message::Call(_, options, procedure, _, _) => {
+            Some((procedure.uri.as_str(), Action::Call, to_value(options)))
+        }
+        _ => None,
+    }
+}
+
+fn to_value<T: Serialize>(options: &T) -> Value {
+    serde_json::to_value(options)
+        .ok()
+        .and_then(|options| serde_json::from_value(options).ok())
+        .unwrap_or_else(|| Value::Dict(Dict::new()))
+}
+
+/// Reads the decision of the authorizer and whether it may be cached.
+///
+/// The authorizer returns a boolean, or a dictionary with `allow` and optionally `disclose` and
+/// `cache`.
+fn decision_of(result: InvocationResult) -> (Decision, bool) {
+    let args = match result {
+        InvocationResult::Yield(args, _kwargs) => args.unwrap_or_default(),
+        InvocationResult::Error(reason, _args, _kwargs) => {
+            log::info!("Authorizer failed: {}", reason);
+            return (Decision::Deny, false);
+        }
+    };
+    let flag = |result: &Dict, key: &str, default: bool| match result.get(key) {
+        Some(Value::Boolean(flag)) => *flag,
+        _ => default,
+    };
+    match args.into_iter().next() {
+        Some(Value::Boolean(true)) => (Decision::Allow { disclose: false }, true),
+        Some(Value::Boolean(false)) => (Decision::Deny, true),
+        Some(Value::Dict(result)) => {
+            let decision = if flag(&result, "allow", false) {
+                Decision::Allow { disclose: flag(&result, "disclose", false) }
+            } else {
+                Decision::Deny
+            };
+            (decision, flag(&result, "cache", true))
+        }
+        _ => {
+            log::warn!("Denying action, as the result of the authorizer is invalid");
+            (Decision::Deny, false)
+        }
+    }
+}
+
+impl Authorizations {
+    /// The invocation of the authorizer that is running, if any.
+    pub fn pending_invocation(&self) -> Option<ID> {
+        self.pending.as_ref().map(|pending| pending.invocation_id)
+    }
+
+    fn cached(&mut self, uri: &str, action: Action) -> Option<Decision> {
+        let key = (uri.to_string(), action);
+        match self.cache.get(&key).cloned() {
+            Some((decision, expires)) if Instant::now() < expires => Some(decision),
+            Some(_) => {
+                self.cache.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Returns the next message that waited for an earlier decision.
+    pub fn next_parked(&mut self) -> Option<Message> {
+        if self.pending.is_none() {
+            self.parked.pop_front()
+        } else {
+            None
+        }
+    }
+}
+
+impl ConnectionHandler {
+    /// Whether this session may set `disclose_me` on its publications.
+    pub fn may_disclose(&self) -> bool {
+        match (&self.realm, &self.identity) {
+            (Some(realm), Some(identity)) => may_disclose(realm, &identity.authrole),
+            _ => false,
+        }
+    }
+
+    /// Decides whether the message may be handled.  Returns `None` if the message has to wait for
+    /// the decision of the authorizer.  While the authorizer decides, every message waits, so
+    /// that no message overtakes an earlier one.  Messages that do not need authorization are
+    /// allowed.
+    pub fn authorize(&mut self, message: &Message) -> WampResult<Option<Decision>> {
+        if self.authorizations.pending.is_some() {
+            self.authorizations.parked.push_back(message.clone());
+            return Ok(None);
+        }
+        let (uri, action, options) = match action_of(message) {
+            Some(action) => action,
+            None => return Ok(Some(Decision::Allow { disclose: false })),
+        };
+        let (realm, identity) = match (&self.realm, &self.identity) {
+            (Some(realm), Some(identity)) => (realm, identity),
+            _ => return Ok(Some(Decision::Deny)),
+        };
+        if let Some(decision) = guard(&self.router.config, &realm.name, &identity.authrole, uri, action) {
+            return Ok(Some(decision));
+        }
+        let config = match check(realm, &identity.authrole, uri, action) {
+            Check::Decided(decision) => return Ok(Some(decision)),
+            Check::Ask(config) => config.clone(),
+        };
+        if let Some(decision) = self.authorizations.cached(uri, action) {
+            return Ok(Some(decision));
+        }
+
+        let registration = self.router.registration(
+            config.realm.clone().unwrap_or_else(|| realm.name.clone()),
+            config.procedure.clone(),
+        );
+        let registration = match registration {
+            Some(registration) => registration,
+            None => {
+                log::warn!("The authorizer {} is not registered", config.procedure);
+                return Ok(Some(Decision::Deny));
+            }
+        };
+        let mut session = Dict::new();
+        session.insert("session".to_string(), Value::UnsignedInteger(self.info_id));
+        session.insert("realm".to_string(), Value::String(realm.name.clone()));
+        session.insert("authid".to_string(), Value::String(identity.authid.clone()));
+        session.insert("authrole".to_string(), Value::String(identity.authrole.clone()));
+        session.insert("authmethod".to_string(), Value::String(identity.authmethod.clone()));
+        session.insert("authprovider".to_string(), Value::String(identity.authprovider.clone()));
+        let args = vec![
+            Value::Dict(session),
+            Value::String(uri.to_string()),
+            Value::String(action.name().to_string()),
+            options,
+        ];
+        log::debug!("Asking authorizer {} whether {} may {} {}", config.procedure, self.info_id, action.name(), uri);
+        let invocation_id = self.invoke(registration, args);
+        self.schedule(config.timeout, INVOCATION_TIMEOUT)?;
+        self.authorizations.pending = Some(PendingAuthorization {
+            invocation_id,
+            deadline: Instant::now() + Duration::from_millis(config.timeout),
+            cache_ttl: Duration::from_secs(config.cache_ttl),
+            uri: uri.to_string(),
+            action,
+            message: message.clone(),
+        });
+        Ok(None)
+    }
+
+    /// Handles the message that waited for the authorizer once its decision arrived.
+    pub fn handle_authorizer_result(&mut self) -> WampResult<()> {
+        let invocation_id = match self.authorizations.pending_invocation() {
+            Some(invocation_id) => invocation_id,
+            None => return Ok(()),
+        };
+        let result = match self.router.take_invocation_result(invocation_id) {
+            Some(result) => result,
+            None => return Ok(()),
+        };
+        let pending = self.authorizations.pending.take().unwrap();
+        let (decision, cache) = decision_of(result);
+        if cache && pending.cache_ttl > Duration::from_secs(0) {
+            self.authorizations.cache.insert(
+                (pending.uri, pending.action),
+                (decision, Instant::now() + pending.cache_ttl),
+            );
+        }
+        self.dispatch(pending.message, decision)
+    }
+
+    /// Denies the message that waits for the authorizer if its decision is overdue.
+    pub fn handle_authorizer_timeout(&mut self) -> WampResult<()> {
+        let deadline = match self.authorizations.pending {
+            Some(ref pending) => pending.deadline,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        if now < deadline {
+            let remaining = deadline - now;
+            return self.schedule(remaining.as_millis() as u64 + 1, INVOCATION_TIMEOUT);
+        }
+        let pending = self.authorizations.pending.take().unwrap();
+        log::warn!("The authorizer did not decide whether {} may {} {} in time",
+                   self.info_id, pending.action.name(), pending.uri);
+        self.router.cancel_invocation(pending.invocation_id);
+        self.dispatch(pending.message, Decision::Deny)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, decision_of, granted, guard, may_disclose, Check, Decision};
+    use crate::messages::Value;
+    use crate::router::config::{Action, RouterConfig};
+    use crate::router::rpc::InvocationResult;
+    use std::collections::HashMap;
+
+    fn is_authorized(config: &RouterConfig, authrole: &str, uri: &str, action: Action) -> bool {
+        match check(config.realm("default").unwrap(), authrole, uri, action) {
+            Check::Decided(decision) => decision == Decision::Allow { disclose: false },
+            Check::Ask(_) => panic!("expected a static decision"),
+        }
+    }
+
+    #[test]
+    fn permissions() {
+        let config: RouterConfig = serde_json::from_str(r#"{"realms":[{"name":"default","roles":{
+            "frontend":{"permissions":[
+                {"uri":"com.example.","match":"prefix","allow":["subscribe","call"]},
+                {"uri":"com.example.admin.","match":"prefix","deny":["call"]},
+                {"uri":"com.example.admin.status","allow":["call"]},
+                {"uri":"com.example..chat","match":"wildcard","allow":["publish"]}
+            ]}
+        }}]}"#).unwrap();
+
+        assert!(is_authorized(&config, "frontend", "com.example.news", Action::Subscribe));
+        assert!(is_authorized(&config, "frontend", "com.example.users.get", Action::Call));
+        assert!(!is_authorized(&config, "frontend", "com.example.admin.kill", Action::Call));
+        assert!(is_authorized(&config, "frontend", "com.example.admin.status", Action::Call));
+        assert!(is_authorized(&config, "frontend", "com.example.lobby.chat", Action::Publish));
+        assert!(!is_authorized(&config, "frontend", "com.example.news", Action::Publish));
+        assert!(!is_authorized(&config, "frontend", "com.example.news", Action::Register));
+        assert!(!is_authorized(&config, "backend", "com.example.news", Action::Subscribe));
+    }
+
+    #[test]
+    fn without_roles() {
+        let config = RouterConfig::default();
+        assert!(is_authorized(&config, "anonymous", "com.example.news", Action::Publish));
+        assert!(!granted(config.realm("default").unwrap(), "anonymous", "com.example.news", Action::Publish));
+    }
+
+    #[test]
+    fn authorizer() {
+        let config: RouterConfig = serde_json::from_str(r#"{"realms":[{"name":"default","roles":{
+            "user":{
+                "permissions":[{"uri":"com.example.public.","match":"prefix","allow":["subscribe"]}],
+                "authorizer":{"procedure":"com.example.authorize","authrole":"authorizer"}
+            }
+        }}]}"#).unwrap();
+        let realm = config.realm("default").unwrap();
+        assert!(is_authorized(&config, "user", "com.example.public.news", Action::Subscribe));
+        match check(realm, "user", "com.example.documents.42", Action::Subscribe) {
+            Check::Ask(authorizer) => {
+                assert_eq!(authorizer.procedure, "com.example.authorize");
+                assert_eq!(authorizer.cache_ttl, 60);
+            }
+            Check::Decided(decision) => panic!("expected the authorizer to decide, got {:?}", decision),
+        }
+    }
+
+    #[test]
+    fn authenticator_registration() {
+        let config: RouterConfig = serde_json::from_str(r#"{"realms":[
+            {"name":"default","auth":{"ticket":{"authenticator":{
+                "procedure":"com.example.authenticate","realm":"internal","authrole":"authenticator"
+            }}}},
+            {"name":"internal"}
+        ]}"#).unwrap();
+        let procedure = "com.example.authenticate";
+        assert_eq!(guard(&config, "internal", "anonymous", procedure, Action::Register), Some(Decision::Deny));
+        assert_eq!(guard(&config, "internal", "authenticator", procedure, Action::Register), None);
+        assert_eq!(guard(&config, "internal", "anonymous", procedure, Action::Call), None);
+        assert_eq!(guard(&config, "internal", "anonymous", "com.example.other", Action::Register), None);
+        assert_eq!(guard(&config, "default", "anonymous", procedure, Action::Register), None);
+    }
+
+    #[test]
+    fn authorizer_registration() {
+        let config: RouterConfig = serde_json::from_str(r#"{"realms":[
+            {"name":"default","roles":{
+                "user":{"permissions":[],"authorizer":{"procedure":"com.example.authorize","authrole":"authorizer"}},
+                "authorizer":{"permissions":[{"uri":"com.example.","match":"prefix","allow":["register"]}]}
+            }}
+        ]}"#).unwrap();
+        let procedure = "com.example.authorize";
+        assert_eq!(guard(&config, "default", "user", procedure, Action::Register), Some(Decision::Deny));
+        assert_eq!(guard(&config, "default", "authorizer", procedure, Action::Register), None);
+        assert_eq!(guard(&config, "default", "user", procedure, Action::Call), None);
+        assert_eq!(guard(&config, "other", "user", procedure, Action::Register), None);
+    }
+
+    #[test]
+    fn disclose_me() {
+        let config: RouterConfig = serde_json::from_str(r#"{"realms":[{"name":"default","roles":{
+            "backend":{"disclose_me":true},
+            "frontend":{}
+        }}]}"#).unwrap();
+        let realm = config.realm("default").unwrap();
+        assert!(may_disclose(realm, "backend"));
+        assert!(!may_disclose(realm, "frontend"));
+        assert!(!may_disclose(realm, "anonymous"));
+        assert!(may_disclose(RouterConfig::default().realm("default").unwrap(), "anonymous"));
+    }
+
+    #[test]
+    fn decisions() {
+        let decide = |value: Value| decision_of(InvocationResult::Yield(Some(vec![value]), None));
+        assert_eq!(decide(Value::Boolean(true)), (Decision::Allow { disclose: false }, true));
+        assert_eq!(decide(Value::Boolean(false)), (Decision::Deny, true));
+
+        let mut result = HashMap::new();
+        result.insert("allow".to_string(), Value::Boolean(true));
+        result.insert("disclose".to_string(), Value::Boolean(true));
+        result.insert("cache".to_string(), Value::Boolean(false));
+        assert_eq!(decide(Value::Dict(result)), (Decision::Allow { disclose: true }, false));
+
+        assert_eq!(decide(Value::String("yes".to_string())), (Decision::Deny, false));
+    }
+}
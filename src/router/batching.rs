@@ -0,0 +1,211 @@
+//! Implements the batched WebSocket sub-protocols.
+//!
+//! With `wamp.2.json.batched`, a frame holds several JSON messages that are each terminated by
+//! `\x1e`.  With `wamp.2.msgpack.batched`, every MessagePack message in a frame is prefixed by
+//! its length as a 32-bit big-endian integer.
+//!
+//! Events for a batched connection wait in its `Batch` for the flush window, so events that are
+//! published in quick succession share a frame.  Any other message flushes the batch right away
+//! and travels in the same frame, so the order of the messages is kept.  The serializer of the
+//! connection puts the messages of a batch into a frame.
+use super::ConnectionHandler;
+
+use crate::router::serializer::Serializer;
+use crate::WampResult;
+use crate::{Error, ErrorKind};
+use std::fmt;
+use std::sync::Arc;
+use ws::util::Token;
+use ws::Message as WSMessage;
+
+/// Wakes a handler whose batch has to be flushed.
+pub const FLUSH: Token = Token(7);
+
+/// Terminates each message of a JSON batch.
+const SEPARATOR: char = '\u{1e}';
+
+/// Batches are flushed before they grow beyond this many bytes.
+const MAX_BATCH_SIZE: usize = 1 << 16;
+
+/// The messages that wait to be sent to a batched connection.
+pub struct Batch {
+    serializer: Arc<dyn Serializer>,
+    messages: Vec<WSMessage>,
+    size: usize,
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Batch {{ protocol: {}, messages: {}, size: {} }}",
+               self.serializer.protocol(), self.messages.len(), self.size)
+    }
+}
+
+impl Batch {
+    pub fn new(serializer: Arc<dyn Serializer>) -> Batch {
+        Batch {
+            serializer,
+            messages: Vec::new(),
+            size: 0,
+        }
+    }
+
+    /// Adds a message to the batch.  Returns whether the batch was empty before.
+    pub fn push(&mut self, message: WSMessage) -> bool {
+        self.size += message.len();
+        self.messages.push(message);
+        self.messages.len() == 1
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.size >= MAX_BATCH_SIZE
+    }
+
+    /// Takes the messages of the batch as a single frame.
+    pub fn take(&mut self) -> Option<WSMessage> {
+        self.size = 0;
+        if self.messages.is_empty() {
+            return None;
+        }
+        let payloads = self.messages.drain(..).map(WSMessage::into_data).collect();
+        let frame = self.serializer.join(payloads);
+        if self.serializer.binary() {
+            Some(WSMessage::Binary(frame))
+        } else {
+            match String::from_utf8(frame) {
+                Ok(frame) => Some(WSMessage::Text(frame)),
+                Err(_) => {
+                    log::warn!("Dropping a batch of {}, which is not valid UTF-8", self.serializer.protocol());
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Puts the payloads of messages into one frame.  Text payloads make a JSON batch, binary
+/// payloads a MessagePack batch.
+pub fn join(binary: bool, payloads: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut frame = Vec::new();
+    for payload in payloads {
+        if binary {
+            frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&payload);
+        } else {
+            frame.extend_from_slice(&payload);
+            frame.push(SEPARATOR as u8);
+        }
+    }
+    frame
+}
+
+/// Splits the frame of a JSON batch into its messages.
+pub fn split_json(frame: &[u8]) -> Vec<&[u8]> {
+    frame.split(|byte| *byte == SEPARATOR as u8)
+        .filter(|message| message.iter().any(|byte| !byte.is_ascii_whitespace()))
+        .collect()
+}
+
+/// Splits the frame of a MessagePack batch into its messages.
+pub fn split_msgpack(mut frame: &[u8]) -> WampResult<Vec<&[u8]>> {
+    let mut messages = Vec::new();
+    while !frame.is_empty() {
+        if frame.len() < 4 {
+            return Err(Error::new(ErrorKind::MalformedData));
+        }
+        let length = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+        if frame.len() - 4 < length {
+            return Err(Error::new(ErrorKind::MalformedData));
+        }
+        messages.push(&frame[4..4 + length]);
+        frame = &frame[4 + length..];
+    }
+    Ok(messages)
+}
+
+impl ConnectionHandler {
+    pub fn handle_flush(&self) -> WampResult<()> {
+        match self.router.senders.lock().unwrap().get(&self.info_id) {
+            Some(sender) => sender.flush().map_err(|e| Error::new(ErrorKind::WSError(e))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_json, split_msgpack, Batch};
+    use crate::messages::Message;
+    use crate::router::serializer::{Serializer, Serializers};
+    use crate::router::{WAMP_JSON_BATCHED, WAMP_MSGPACK_BATCHED};
+    use crate::{Error, ErrorKind, WampResult};
+    use std::sync::Arc;
+    use ws::Message as WSMessage;
+
+    /// Frames a batch as a JSON array of its messages.
+    struct JsonArray;
+
+    impl Serializer for JsonArray {
+        fn protocol(&self) -> &str {
+            "wamp.2.json.array"
+        }
+
+        fn binary(&self) -> bool {
+            false
+        }
+
+        fn batched(&self) -> bool {
+            true
+        }
+
+        fn serialize(&self, _message: &Message) -> WampResult<Vec<u8>> {
+            Err(Error::new(ErrorKind::MalformedData))
+        }
+
+        fn deserialize(&self, _payload: &[u8]) -> WampResult<Message> {
+            Err(Error::new(ErrorKind::MalformedData))
+        }
+
+        fn join(&self, payloads: Vec<Vec<u8>>) -> Vec<u8> {
+            let mut frame = b"[".to_vec();
+            frame.extend_from_slice(&payloads.join(&b','));
+            frame.push(b']');
+            frame
+        }
+    }
+
+    fn batch(protocol: &str) -> Batch {
+        Batch::new(Serializers::default().get(protocol).unwrap())
+    }
+
+    #[test]
+    fn json() {
+        let mut batch = batch(WAMP_JSON_BATCHED);
+        assert!(batch.push(WSMessage::Text("[36,1,2,{}]".to_string())));
+        assert!(!batch.push(WSMessage::Text("[36,1,3,{}]".to_string())));
+        let frame = batch.take().unwrap();
+        assert_eq!(frame, WSMessage::Text("[36,1,2,{}]\u{1e}[36,1,3,{}]\u{1e}".to_string()));
+        assert_eq!(batch.take(), None);
+        assert_eq!(split_json(&frame.into_data()), vec![&b"[36,1,2,{}]"[..], &b"[36,1,3,{}]"[..]]);
+    }
+
+    #[test]
+    fn msgpack() {
+        let mut batch = batch(WAMP_MSGPACK_BATCHED);
+        batch.push(WSMessage::Binary(vec![0x93, 0x01]));
+        batch.push(WSMessage::Binary(vec![0x91]));
+        let frame = batch.take().unwrap().into_data();
+        assert_eq!(frame, vec![0, 0, 0, 2, 0x93, 0x01, 0, 0, 0, 1, 0x91]);
+        assert_eq!(split_msgpack(&frame).unwrap(), vec![&[0x93, 0x01][..], &[0x91][..]]);
+        assert!(split_msgpack(&frame[..9]).is_err());
+        assert!(split_msgpack(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn custom() {
+        let mut batch = Batch::new(Arc::new(JsonArray));
+        batch.push(WSMessage::Text("[36,1,2,{}]".to_string()));
+        batch.push(WSMessage::Text("[36,1,3,{}]".to_string()));
+        assert_eq!(batch.take().unwrap(), WSMessage::Text("[[36,1,2,{}],[36,1,3,{}]]".to_string()));
+    }
+}
@@ -0,0 +1,727 @@
+//! Contains the `RouterConfig` struct, which describes the realms a router serves.
+//!
+//! The configuration is read from a JSON file.  Every node of a cluster has to be started
+//! with the same configuration, as the replicated router state is derived from it.
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use crate::router::auth::TicketValidator;
+use crate::router::serializer::{Serializer, Serializers};
+use crate::{Error, ErrorKind, MatchingPolicy, WampResult};
+
+/// The configuration of a router.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouterConfig {
+    /// The realms that are available on this router.
+    #[serde(default = "default_realms")]
+    pub realms: Vec<RealmConfig>,
+    /// Whether a realm that is not configured should be created when a client joins it.
+    #[serde(default)]
+    pub auto_create_realms: bool,
+    /// Serves WebSocket connections over TLS.  Requires the `ssl` feature.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Serves WAMP RawSocket connections next to the WebSocket ones.
+    #[serde(default)]
+    pub rawsocket: Option<RawSocketConfig>,
+    /// Lets sessions that lost their connection resume on any node.  If this is `null`,
+    /// sessions end with their connection.
+    #[serde(default)]
+    pub resumption: Option<ResumptionConfig>,
+    /// Limits the events that are queued for durable subscriptions while their owner is offline.
+    #[serde(default)]
+    pub durable_subscriptions: DurableConfig,
+    /// The milliseconds the router waits for the reply to its `GOODBYE` before it closes the
+    /// connection.
+    #[serde(default = "default_close_timeout")]
+    pub close_timeout: u64,
+    /// The milliseconds a client has to complete its handshake after connecting.
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout: u64,
+    /// The milliseconds between WebSocket pings.  Peers that did not answer the previous ping
+    /// are dropped.  If this is `null`, no pings are sent.
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval: Option<u64>,
+    /// The milliseconds events for connections with a batched sub-protocol wait for other
+    /// events, so they are sent in one frame.
+    #[serde(default = "default_batch_window")]
+    pub batch_window: u64,
+    /// The serializers clients can pick.  Custom serializers have to be added with
+    /// `RouterConfig::register_serializer`.
+    #[serde(skip)]
+    pub serializers: Serializers,
+}
+
+/// How many events are kept for each durable subscription.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DurableConfig {
+    /// The number of events that are kept.  Older events are dropped.
+    #[serde(default = "default_max_events")]
+    pub max_events: usize,
+    /// The seconds an event is kept.
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+}
+
+/// How long the router keeps the sessions that lost their connection.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResumptionConfig {
+    /// The milliseconds a session can be resumed after it lost its connection.
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+    /// The number of messages that are kept for a session until it resumes.  Older messages
+    /// are dropped.
+    #[serde(default = "default_max_buffered")]
+    pub max_buffered: usize,
+}
+
+/// Where the router accepts RawSocket connections.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawSocketConfig {
+    /// The TCP address to listen on, such as `127.0.0.1:8091`.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// The path of a Unix domain socket to listen on.  An existing socket file is replaced.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// The octal permissions of the Unix domain socket, such as `"0660"`.
+    #[serde(default)]
+    pub permissions: Option<String>,
+    /// The length in bytes of the longest message the router accepts.  The router announces
+    /// the largest power of two that is not longer, at least 512 bytes and at most 16 MiB.
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: u32,
+}
+
+/// The certificate and private key used for TLS connections.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// The path to the PEM encoded certificate chain.
+    pub certificate: String,
+    /// The path to the PEM encoded private key.
+    pub key: String,
+    /// The path to the PEM encoded certificates of the authorities that issue client
+    /// certificates.  If this is set, clients are asked for a certificate.
+    #[serde(default)]
+    pub client_ca: Option<String>,
+    /// Rejects clients that do not present a certificate.
+    #[serde(default)]
+    pub require_client_certificate: bool,
+}
+
+/// The configuration of a single realm.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RealmConfig {
+    /// The URI of the realm that clients send in their `HELLO` message.
+    pub name: String,
+    /// The authentication methods sessions may use to join the realm.
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// The permissions of each authrole.  If this is `null`, every session may perform any
+    /// action.
+    #[serde(default)]
+    pub roles: Option<HashMap<String, RoleConfig>>,
+    /// The milliseconds after which a session that sent no message is closed.  If this is
+    /// `null`, sessions may stay idle.
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+}
+
+/// The permissions of the sessions that joined a realm with an authrole.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RoleConfig {
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    /// Decides actions that no permission mentions.
+    #[serde(default)]
+    pub authorizer: Option<AuthorizerConfig>,
+    /// Whether sessions may disclose their identity to subscribers with the `disclose_me`
+    /// option of their publications.
+    #[serde(default)]
+    pub disclose_me: bool,
+}
+
+/// A procedure that authorizes the actions of sessions, registered by a backend session.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorizerConfig {
+    /// The URI of the procedure.
+    pub procedure: String,
+    /// The realm the procedure is registered in.  Defaults to the realm of the session.
+    #[serde(default)]
+    pub realm: Option<String>,
+    /// The authrole of the sessions that may register the procedure.  Sessions with any other
+    /// authrole are not authorized to register it.
+    pub authrole: String,
+    /// The milliseconds to wait for a decision before the action is denied.
+    #[serde(default = "default_authenticator_timeout")]
+    pub timeout: u64,
+    /// The seconds a session remembers a decision for.
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+}
+
+/// Allows or denies actions on the URIs that match a pattern.
+///
+/// If several permissions of a role mention an action, the most specific pattern decides.  Exact
+/// patterns are more specific than prefixes, which are more specific than wildcards.  Actions that
+/// no permission mentions are denied.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Permission {
+    pub uri: String,
+    #[serde(rename = "match", default)]
+    pub matching_policy: MatchingPolicy,
+    #[serde(default)]
+    pub allow: Vec<Action>,
+    #[serde(default)]
+    pub deny: Vec<Action>,
+}
+
+/// An action a session performs on a URI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Publish,
+    Subscribe,
+    Call,
+    Register,
+}
+
+/// The authentication methods of a realm.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthConfig {
+    /// The role of sessions that join without authenticating.  If this is `null`, anonymous
+    /// sessions are rejected.
+    #[serde(default = "default_anonymous")]
+    pub anonymous: Option<String>,
+    /// Enables the `wampcra` challenge-response authentication.
+    #[serde(default)]
+    pub wampcra: Option<WampCraConfig>,
+    /// Enables the `ticket` authentication.
+    #[serde(default)]
+    pub ticket: Option<TicketConfig>,
+    /// Enables the `cryptosign` authentication.
+    #[serde(default)]
+    pub cryptosign: Option<CryptosignConfig>,
+    /// Enables the `wamp-scram` authentication.
+    #[serde(default)]
+    pub scram: Option<ScramConfig>,
+    /// Enables the `tls` authentication with client certificates.
+    #[serde(default)]
+    pub tls: Option<TlsAuthConfig>,
+    /// Enables the `unix` authentication with the credentials of Unix domain socket peers.
+    #[serde(default)]
+    pub unix: Option<UnixAuthConfig>,
+}
+
+/// The principals that may authenticate via `wampcra`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WampCraConfig {
+    pub principals: HashMap<String, WampCraPrincipal>,
+}
+
+/// A principal that authenticates via `wampcra`.
+///
+/// If a salt is given, the secret the client has to sign the challenge with is derived using
+/// PBKDF2 with the given iterations and key length.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WampCraPrincipal {
+    pub secret: String,
+    pub role: String,
+    #[serde(default)]
+    pub salt: Option<String>,
+    #[serde(default = "default_iterations")]
+    pub iterations: u32,
+    #[serde(default = "default_keylen")]
+    pub keylen: u32,
+}
+
+/// The principals and validator used by the `ticket` authentication.
+///
+/// The validator can not be read from the configuration file and has to be set with
+/// `RouterConfig::set_ticket_validator`.
+#[derive(Clone, Default, Deserialize)]
+pub struct TicketConfig {
+    #[serde(default)]
+    pub principals: HashMap<String, TicketPrincipal>,
+    /// Accepts JSON web tokens as tickets.
+    #[serde(default)]
+    pub jwt: Option<JwtConfig>,
+    #[serde(skip)]
+    pub validator: Option<Arc<dyn TicketValidator>>,
+    /// Passes tickets that no other check accepted to a procedure.
+    #[serde(default)]
+    pub authenticator: Option<AuthenticatorConfig>,
+}
+
+/// A procedure that authenticates sessions, registered by a backend session.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthenticatorConfig {
+    /// The URI of the procedure.
+    pub procedure: String,
+    /// The realm the procedure is registered in.  Defaults to the realm the session joins.
+    #[serde(default)]
+    pub realm: Option<String>,
+    /// The authrole of the sessions that may register the procedure.  Sessions with any other
+    /// authrole are not authorized to register it.
+    pub authrole: String,
+    /// The milliseconds to wait for the result before the session is rejected.
+    #[serde(default = "default_authenticator_timeout")]
+    pub timeout: u64,
+}
+
+/// A principal that authenticates with a static ticket.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TicketPrincipal {
+    pub ticket: String,
+    pub role: String,
+}
+
+/// The principals that may authenticate via `cryptosign`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CryptosignConfig {
+    pub principals: HashMap<String, CryptosignPrincipal>,
+}
+
+/// A principal that authenticates via `cryptosign`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CryptosignPrincipal {
+    pub role: String,
+    /// The hex encoded Ed25519 public keys of the principal.
+    pub authorized_keys: Vec<String>,
+}
+
+/// The principals that may authenticate via `wamp-scram`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScramConfig {
+    pub principals: HashMap<String, ScramPrincipal>,
+}
+
+/// The SCRAM verifier of a principal that authenticates via `wamp-scram`.
+///
+/// Verifiers can be generated with `autobahnkreuz scram-verifier`.  The salt and both keys are
+/// base64 encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScramPrincipal {
+    pub role: String,
+    pub kdf: ScramKdf,
+    pub salt: String,
+    pub iterations: u32,
+    /// The memory cost of `argon2id13` in KiB.
+    #[serde(default)]
+    pub memory: u32,
+    pub stored_key: String,
+    pub server_key: String,
+}
+
+/// The key derivation function used to salt the password of a `wamp-scram` principal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScramKdf {
+    #[serde(rename = "argon2id13")]
+    Argon2id13,
+    #[serde(rename = "pbkdf2")]
+    Pbkdf2,
+}
+
+/// The client certificates that may authenticate via `tls`.
+///
+/// The principals are keyed by the subject common name or a subject alternative name of the
+/// certificate.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsAuthConfig {
+    pub principals: HashMap<String, TlsPrincipal>,
+}
+
+/// A principal that authenticates with a client certificate.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsPrincipal {
+    /// The authid of the session.  Defaults to the name of the certificate.
+    #[serde(default)]
+    pub authid: Option<String>,
+    pub role: String,
+}
+
+/// The principals that may authenticate via `unix`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnixAuthConfig {
+    /// The first principal that matches the peer decides its identity.  Every principal has to
+    /// set a `uid` or a `gid`.
+    #[serde(deserialize_with = "deserialize_unix_principals")]
+    pub principals: Vec<UnixPrincipal>,
+}
+
+/// A principal that authenticates with the user and group of its process.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnixPrincipal {
+    /// The user ID of the peer.  Any user matches if this is `null`.
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// The group ID of the peer.  Any group matches if this is `null`.
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// The authid of the session.  Defaults to the user ID of the peer.
+    #[serde(default)]
+    pub authid: Option<String>,
+    pub role: String,
+}
+
+/// The keys and claims used to verify tickets that are JSON web tokens.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtConfig {
+    /// The keys a token may be signed with.
+    pub keys: Vec<JwtKey>,
+    /// The audience a token has to be issued for.
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// The seconds `exp` and `nbf` may be off to account for clock skew.
+    #[serde(default)]
+    pub leeway: u64,
+    /// Whether tokens without an `exp` claim are rejected.  Tokens that never expire are only
+    /// accepted if this is turned off.
+    #[serde(default = "default_require_exp")]
+    pub require_exp: bool,
+    /// The claim that contains the authid of the session.
+    #[serde(default = "default_authid_claim")]
+    pub authid_claim: String,
+    /// The claim that contains the authrole of the session.
+    #[serde(default = "default_authrole_claim")]
+    pub authrole_claim: String,
+    /// The authrole of sessions whose token does not contain the authrole claim.
+    #[serde(default)]
+    pub default_role: Option<String>,
+    /// The claims that are passed to the session as `authextra`.
+    #[serde(default)]
+    pub authextra_claims: Vec<String>,
+}
+
+/// A key in the JSON web key format.
+///
+/// Symmetric `oct` keys verify HS256, `RSA` keys verify RS256 and `OKP` keys on the `Ed25519`
+/// curve verify EdDSA signatures.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kty")]
+pub enum JwtKey {
+    #[serde(rename = "oct")]
+    Oct {
+        #[serde(default)]
+        kid: Option<String>,
+        k: String,
+    },
+    #[serde(rename = "RSA")]
+    Rsa {
+        #[serde(default)]
+        kid: Option<String>,
+        n: String,
+        e: String,
+    },
+    #[serde(rename = "OKP")]
+    Okp {
+        #[serde(default)]
+        kid: Option<String>,
+        crv: String,
+        x: String,
+    },
+}
+
+fn default_realms() -> Vec<RealmConfig> {
+    vec![RealmConfig::new("default")]
+}
+
+fn default_anonymous() -> Option<String> {
+    Some("anonymous".to_string())
+}
+
+fn default_authid_claim() -> String {
+    "sub".to_string()
+}
+
+fn default_authrole_claim() -> String {
+    "role".to_string()
+}
+
+fn default_require_exp() -> bool {
+    true
+}
+
+fn default_authenticator_timeout() -> u64 {
+    5000
+}
+
+fn default_cache_ttl() -> u64 {
+    60
+}
+
+fn default_grace_period() -> u64 {
+    30000
+}
+
+fn default_max_buffered() -> usize {
+    1000
+}
+
+fn default_close_timeout() -> u64 {
+    5000
+}
+
+fn default_handshake_timeout() -> u64 {
+    10000
+}
+
+fn default_ping_interval() -> Option<u64> {
+    Some(30000)
+}
+
+fn default_batch_window() -> u64 {
+    5
+}
+
+fn default_max_events() -> usize {
+    1000
+}
+
+fn default_max_age() -> u64 {
+    3600
+}
+
+fn default_max_message_size() -> u32 {
+    1 << 24
+}
+
+/// Rejects principals without a `uid` and a `gid`, which would match every peer.
+fn deserialize_unix_principals<'de, D>(deserializer: D) -> Result<Vec<UnixPrincipal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let principals = Vec::<UnixPrincipal>::deserialize(deserializer)?;
+    if principals.iter().any(|principal| !principal.restricts_peer()) {
+        return Err(serde::de::Error::custom("a unix principal needs a uid or a gid"));
+    }
+    Ok(principals)
+}
+
+fn default_iterations() -> u32 {
+    1000
+}
+
+fn default_keylen() -> u32 {
+    32
+}
+
+impl Default for RouterConfig {
+    fn default() -> RouterConfig {
+        RouterConfig {
+            realms: default_realms(),
+            auto_create_realms: false,
+            tls: None,
+            rawsocket: None,
+            resumption: None,
+            durable_subscriptions: DurableConfig::default(),
+            close_timeout: default_close_timeout(),
+            handshake_timeout: default_handshake_timeout(),
+            ping_interval: default_ping_interval(),
+            batch_window: default_batch_window(),
+            serializers: Serializers::default(),
+        }
+    }
+}
+
+impl Default for DurableConfig {
+    fn default() -> Self {
+        DurableConfig {
+            max_events: default_max_events(),
+            max_age: default_max_age(),
+        }
+    }
+}
+
+impl RouterConfig {
+    /// Reads the configuration from the JSON file at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> WampResult<RouterConfig> {
+        let file = File::open(path).map_err(|e| Error::new(ErrorKind::IOError(e)))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| Error::new(ErrorKind::JSONError(e)))
+    }
+
+    /// Returns the configuration of the realm with the given name, if it is configured.
+    pub fn realm(&self, name: &str) -> Option<&RealmConfig> {
+        self.realms.iter().find(|realm| realm.name == name)
+    }
+
+    /// Returns whether a client may join the realm with the given name.
+    pub fn allows_realm(&self, name: &str) -> bool {
+        self.auto_create_realms || self.realm(name).is_some()
+    }
+
+    /// Validates the tickets of sessions joining the given realm with the given validator.  The
+    /// realm is added to the configuration if it is not configured yet.
+    pub fn set_ticket_validator<V>(&mut self, realm: &str, validator: V)
+    where
+        V: TicketValidator + 'static,
+    {
+        if self.realm(realm).is_none() {
+            self.realms.push(RealmConfig::new(realm));
+        }
+        if let Some(config) = self.realms.iter_mut().find(|config| config.name == realm) {
+            config.auth.ticket
+                .get_or_insert_with(TicketConfig::default)
+                .validator = Some(Arc::new(validator));
+        }
+    }
+
+    /// Offers the given serializer to clients that negotiate its sub-protocol.  A built-in
+    /// serializer with the same sub-protocol is replaced.
+    pub fn register_serializer<S>(&mut self, serializer: S)
+    where
+        S: Serializer + 'static,
+    {
+        self.serializers.register(Arc::new(serializer));
+    }
+
+    /// Returns the configuration a client joining the realm with the given name is subject to.
+    /// Realms that are created automatically use the default configuration.
+    pub fn realm_or_default(&self, name: &str) -> Option<RealmConfig> {
+        match self.realm(name) {
+            Some(realm) => Some(realm.clone()),
+            None if self.auto_create_realms => Some(RealmConfig::new(name)),
+            None => None,
+        }
+    }
+}
+
+impl RealmConfig {
+    #[inline]
+    pub fn new(name: &str) -> RealmConfig {
+        RealmConfig {
+            name: name.to_string(),
+            auth: AuthConfig::default(),
+            roles: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+impl UnixPrincipal {
+    /// Whether the principal names the user or group of the peers it matches.
+    pub fn restricts_peer(&self) -> bool {
+        self.uid.is_some() || self.gid.is_some()
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> AuthConfig {
+        AuthConfig {
+            anonymous: default_anonymous(),
+            wampcra: None,
+            ticket: None,
+            cryptosign: None,
+            scram: None,
+            tls: None,
+            unix: None,
+        }
+    }
+}
+
+impl fmt::Debug for TicketConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TicketConfig")
+            .field("principals", &self.principals.keys().collect::<Vec<_>>())
+            .field("jwt", &self.jwt)
+            .field("validator", &self.validator.is_some())
+            .field("authenticator", &self.authenticator)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RouterConfig;
+
+    #[test]
+    fn parse_realms() {
+        let config: RouterConfig = serde_json::from_str(
+            "{\"realms\":[{\"name\":\"tenant.a\"},{\"name\":\"tenant.b\"}]}"
+        ).unwrap();
+        assert!(config.allows_realm("tenant.a"));
+        assert!(config.allows_realm("tenant.b"));
+        assert!(!config.allows_realm("default"));
+
+        let config: RouterConfig = serde_json::from_str("{\"auto_create_realms\":true}").unwrap();
+        assert!(config.realm("default").is_some());
+        assert!(config.allows_realm("anything"));
+    }
+
+    #[test]
+    fn parse_wampcra() {
+        let config: RouterConfig = serde_json::from_str(
+            "{\"realms\":[{\"name\":\"default\",\"auth\":{\"anonymous\":null,\"wampcra\":{\"principals\":{\"joe\":{\"secret\":\"secret123\",\"role\":\"frontend\",\"salt\":\"salt123\"}}}}}]}"
+        ).unwrap();
+        let auth = &config.realm("default").unwrap().auth;
+        assert_eq!(auth.anonymous, None);
+        let joe = &auth.wampcra.as_ref().unwrap().principals["joe"];
+        assert_eq!(joe.role, "frontend");
+        assert_eq!(joe.iterations, 1000);
+        assert_eq!(joe.keylen, 32);
+    }
+
+    #[test]
+    fn parse_resumption() {
+        let config: RouterConfig = serde_json::from_str("{\"resumption\":{\"grace_period\":5000}}").unwrap();
+        let resumption = config.resumption.unwrap();
+        assert_eq!(resumption.grace_period, 5000);
+        assert_eq!(resumption.max_buffered, 1000);
+        assert!(RouterConfig::default().resumption.is_none());
+    }
+
+    #[test]
+    fn parse_timeouts() {
+        let config: RouterConfig = serde_json::from_str(
+            "{\"ping_interval\":null,\"realms\":[{\"name\":\"devices\",\"idle_timeout\":60000}]}"
+        ).unwrap();
+        assert_eq!(config.ping_interval, None);
+        assert_eq!(config.handshake_timeout, 10000);
+        assert_eq!(config.realms[0].idle_timeout, Some(60000));
+        assert_eq!(RouterConfig::default().ping_interval, Some(30000));
+        assert_eq!(config.batch_window, 5);
+    }
+
+    #[test]
+    fn parse_rawsocket() {
+        let config: RouterConfig = serde_json::from_str("{\"rawsocket\":{\"address\":\"127.0.0.1:8091\"}}").unwrap();
+        let rawsocket = config.rawsocket.unwrap();
+        assert_eq!(rawsocket.address, Some("127.0.0.1:8091".to_string()));
+        assert_eq!(rawsocket.path, None);
+        assert_eq!(rawsocket.max_message_size, 1 << 24);
+
+        let config: RouterConfig = serde_json::from_str(
+            "{\"rawsocket\":{\"path\":\"/run/wamp.sock\",\"permissions\":\"0660\"}}"
+        ).unwrap();
+        let rawsocket = config.rawsocket.unwrap();
+        assert_eq!(rawsocket.path, Some("/run/wamp.sock".to_string()));
+        assert_eq!(rawsocket.permissions, Some("0660".to_string()));
+    }
+
+    #[test]
+    fn parse_unix() {
+        let config: RouterConfig = serde_json::from_str(
+            "{\"realms\":[{\"name\":\"default\",\"auth\":{\"unix\":{\"principals\":[{\"gid\":2000,\"role\":\"monitor\"}]}}}]}"
+        ).unwrap();
+        let unix = config.realm("default").unwrap().auth.unix.as_ref().unwrap();
+        assert_eq!(unix.principals[0].gid, Some(2000));
+
+        let config = serde_json::from_str::<RouterConfig>(
+            "{\"realms\":[{\"name\":\"default\",\"auth\":{\"unix\":{\"principals\":[{\"role\":\"anyone\"}]}}}]}"
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn parse_durable_subscriptions() {
+        let config: RouterConfig = serde_json::from_str("{\"durable_subscriptions\":{\"max_age\":60}}").unwrap();
+        assert_eq!(config.durable_subscriptions.max_age, 60);
+        assert_eq!(config.durable_subscriptions.max_events, 1000);
+        assert_eq!(RouterConfig::default().durable_subscriptions.max_age, 3600);
+    }
+}
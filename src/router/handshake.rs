@@ -1,89 +1,221 @@
-use super::{ConnectionHandler, ConnectionState, WAMP_JSON, WAMP_MSGPACK};
+use super::{ConnectionHandler, ConnectionState};
 
+use ws::util::Token;
 use ws::{CloseCode, Error as WSError, ErrorKind as WSErrorKind, Request, Response,
          Result as WSResult};
 
-use crate::messages::{ErrorDetails, HelloDetails, Message, Reason, RouterRoles, WelcomeDetails, URI};
+use crate::messages::{Dict, ErrorDetails, HelloDetails, Message, Reason, RouterRoles, WelcomeDetails,
+                      URI};
+use crate::router::auth::{self, AuthIdentity, AuthStep, DynamicAuthentication};
+use crate::router::meta::SessionDetails;
+use crate::router::resumption;
+use crate::router::rpc::INVOCATION_TIMEOUT;
 use crate::{Error, ErrorKind, WampResult};
 
+/// Wakes a handler whose session did not answer the `GOODBYE` of the router in time.
+pub const CLOSE_TIMEOUT: Token = Token(3);
+
 impl ConnectionHandler {
-    pub fn handle_hello(&self, realm: URI, _details: HelloDetails) -> WampResult<()> {
+    pub fn handle_hello(&mut self, realm: URI, details: HelloDetails) -> WampResult<()> {
         log::debug!("Responding to hello message (realm: {:?})", realm);
-        self.set_realm(realm.uri)?;
-        self.router.set_state(self.info_id, ConnectionState::Connected);
-        self.send_message(Message::Welcome(self.info_id, WelcomeDetails::new(RouterRoles::new())));
+        let realm_config = match self.router.config.realm_or_default(&realm.uri) {
+            Some(realm_config) => realm_config,
+            None => return Err(Error::new(ErrorKind::HandshakeError(Reason::NoSuchRealm))),
+        };
+        if details.roles.is_empty() {
+            log::warn!("Connection {} announced no roles", self.info_id);
+            return Err(Error::new(ErrorKind::HandshakeError(Reason::NoSuchRole)));
+        }
+        self.agent = details.agent.clone();
+        self.roles = Some(details.roles.clone());
+        if let (Some(session_id), Some(ref token)) = (details.resume_session, &details.resume_token) {
+            if self.router.config.resumption.is_some() {
+                if self.resume(&realm.uri, session_id, token) {
+                    return self.start_idle_timer();
+                }
+                log::info!("Connection {} could not resume session {}", self.info_id, session_id);
+            }
+        }
+        let step = auth::hello(&realm_config, self.info_id, &details, &self.transport);
+        self.auth_step(realm.uri, step)
+    }
+
+    pub fn handle_authenticate(&mut self, signature: String, extra: Dict) -> WampResult<()> {
+        match self.authentication.take() {
+            Some((realm, pending)) => {
+                let step = auth::authenticate(pending, &signature, &extra);
+                self.auth_step(realm, step)
+            }
+            None => Err(Error::new(ErrorKind::UnexpectedMessage(
+                "Received an authenticate message without a pending challenge",
+            ))),
+        }
+    }
+
+    /// Takes the result of the authenticator once the handler is woken up.
+    pub fn handle_authenticator_result(&mut self) -> WampResult<()> {
+        let invocation_id = match self.authenticator {
+            Some((_, invocation_id, _)) => invocation_id,
+            None => return Ok(()),
+        };
+        match self.router.take_invocation_result(invocation_id) {
+            Some(result) => {
+                let (realm, _, authentication) = self.authenticator.take().unwrap();
+                match authentication.verify(result) {
+                    Ok(identity) => self.welcome(realm, identity),
+                    Err(reason) => Err(Error::new(ErrorKind::HandshakeError(reason))),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn handle_authenticator_timeout(&mut self) -> WampResult<()> {
+        match self.authenticator.take() {
+            Some((_, invocation_id, authentication)) => {
+                log::warn!("The authenticator {} did not answer in time", authentication.procedure());
+                self.router.cancel_invocation(invocation_id);
+                Err(Error::new(ErrorKind::HandshakeError(Reason::AuthenticationFailed)))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn auth_step(&mut self, realm: String, step: Result<AuthStep, Reason>) -> WampResult<()> {
+        match step {
+            Ok(AuthStep::Welcome(identity)) => self.welcome(realm, identity),
+            Ok(AuthStep::Challenge(authmethod, extra, pending)) => {
+                log::debug!("Challenging connection {} with {}", self.info_id, authmethod);
+                self.authentication = Some((realm, pending));
+                self.send_message(Message::Challenge(authmethod, extra));
+                Ok(())
+            }
+            Ok(AuthStep::Invoke(authentication)) => self.invoke_authenticator(realm, authentication),
+            Err(reason) => Err(Error::new(ErrorKind::HandshakeError(reason))),
+        }
+    }
+
+    fn invoke_authenticator(&mut self, realm: String, authentication: DynamicAuthentication) -> WampResult<()> {
+        let registration = self.router.registration(
+            authentication.authenticator_realm().to_string(),
+            authentication.procedure().to_string(),
+        );
+        let registration = match registration {
+            Some(registration) => registration,
+            None => {
+                log::warn!("The authenticator {} is not registered", authentication.procedure());
+                return Err(Error::new(ErrorKind::HandshakeError(Reason::AuthenticationFailed)));
+            }
+        };
+        log::debug!("Invoking authenticator {} for connection {}", authentication.procedure(), self.info_id);
+        let invocation_id = self.invoke(registration, authentication.arguments());
+        self.schedule(authentication.timeout(), INVOCATION_TIMEOUT)?;
+        self.authenticator = Some((realm, invocation_id, authentication));
         Ok(())
     }
 
+    fn welcome(&mut self, realm: String, identity: AuthIdentity) -> WampResult<()> {
+        log::info!(
+            "Welcoming connection {} as {} ({}) to realm {}",
+            self.info_id, identity.authid, identity.authrole, realm,
+        );
+        self.realm = self.router.config.realm_or_default(&realm);
+        let session = SessionDetails {
+            authid: identity.authid.clone(),
+            authrole: identity.authrole.clone(),
+            authmethod: identity.authmethod.clone(),
+            authprovider: identity.authprovider.clone(),
+            transport: self.transport.kind.to_string(),
+            peer: self.transport.peer.clone(),
+            agent: self.agent.clone(),
+            roles: self.roles.clone().unwrap_or_default(),
+        };
+        let token = self.router.config.resumption.as_ref().map(|_| resumption::new_token());
+        self.set_realm(realm, session.clone(), token.as_ref().map(|token| resumption::token_hash(token)));
+        self.router.set_state(self.info_id, ConnectionState::Connected);
+        let mut details = identity.welcome_details(WelcomeDetails::new(RouterRoles::new()));
+        details.resume_token = token;
+        self.identity = Some(identity);
+        self.send_message(Message::Welcome(self.info_id, details));
+        self.publish_join(&session);
+        self.start_idle_timer()
+    }
+
+    /// Handles a session that gives up its handshake.
+    pub fn handle_abort(&mut self, reason: Reason) {
+        log::info!("Connection {} aborted its handshake with reason: {:?}", self.info_id, reason);
+        self.close_connection(CloseCode::Normal);
+    }
+
     pub fn handle_goodbye(&self, _details: ErrorDetails, reason: Reason) -> WampResult<()> {
-        if let Ok(info) = self.info() {
-            let state = info.lock().unwrap().state.clone();
-            match state {
-                ConnectionState::Initializing => {
-                    // TODO check specification for how this ought to work.
-                    Err(Error::new(ErrorKind::InvalidState(
-                        "Received a goodbye message before handshake complete",
-                    )))
-                }
-                ConnectionState::Connected => {
-                    log::info!("Received goodbye message with reason: {:?}", reason);
-                    self.remove();
-                    self.send_message(Message::Goodbye(ErrorDetails::new(), Reason::GoodbyeAndOut));
-                    self.router.set_state(self.info_id, ConnectionState::Disconnected);
-                    let senders = self.router.senders.lock().unwrap();
-                    let sender = senders.get(&self.info_id).unwrap();
-                    match sender.close(CloseCode::Normal) {
-                        Err(e) => Err(Error::new(ErrorKind::WSError(e))),
-                        _ => Ok(()),
-                    }
-                }
-                ConnectionState::ShuttingDown => {
-                    log::info!(
-                        "Received goodbye message in response to our goodbye message with reason: {:?}",
-                        reason
-                    );
-                    self.router.set_state(self.info_id, ConnectionState::Disconnected);
-                    let senders = self.router.senders.lock().unwrap();
-                    let sender = senders.get(&self.info_id).unwrap();
-                    match sender.close(CloseCode::Normal) {
-                        Err(e) => Err(Error::new(ErrorKind::WSError(e))),
-                        _ => Ok(()),
-                    }
-                }
-                ConnectionState::Disconnected => {
-                    log::warn!("Received goodbye message after closing connection");
-                    Ok(())
-                }
+        let state = match self.info() {
+            Ok(info) => info.lock().unwrap().state.clone(),
+            Err(_) => return Ok(()),
+        };
+        match state {
+            ConnectionState::Connected => {
+                log::info!("Received goodbye message with reason: {:?}", reason);
+                self.send_message(Message::Goodbye(ErrorDetails::new(), Reason::GoodbyeAndOut));
+                self.close_connection(CloseCode::Normal);
+                Ok(())
             }
-        } else {
-            Ok(())
+            ConnectionState::ShuttingDown => {
+                log::info!(
+                    "Received goodbye message in response to our goodbye message with reason: {:?}",
+                    reason
+                );
+                self.close_connection(CloseCode::Normal);
+                Ok(())
+            }
+            ConnectionState::Initializing
+            | ConnectionState::Detached
+            | ConnectionState::Disconnected => Err(Error::new(
+                ErrorKind::InvalidState("Received a goodbye message outside of a session"),
+            )),
         }
     }
 
-    fn set_realm(&self, realm: String) -> WampResult<()> {
-        log::debug!("Setting realm to {}", realm);
-        if realm == "default" {
-            Ok(())
-        } else {
-            Err(Error::new(ErrorKind::HandshakeError(Reason::NoSuchRealm)))
+    /// Closes the connection of a session that did not answer the `GOODBYE` of the router.
+    pub fn handle_close_timeout(&self) -> WampResult<()> {
+        let state = match self.info() {
+            Ok(info) => info.lock().unwrap().state.clone(),
+            Err(_) => return Ok(()),
+        };
+        if state == ConnectionState::ShuttingDown {
+            log::info!("Session {} did not answer our goodbye in time", self.info_id);
+            self.close_connection(CloseCode::Away);
         }
+        Ok(())
+    }
+
+    fn set_realm(&mut self, realm: String, session: SessionDetails, resume_token: Option<String>) {
+        log::debug!("Setting realm to {}", realm);
+        self.router.join_realm(self.info_id, realm.clone(), session, resume_token);
+        self.subscriptions = Some(self.router.subscriptions(realm.clone()));
+        self.durable = Some(self.router.durable_subscriptions(realm));
     }
 
     pub fn process_protocol(&self, request: &Request, response: &mut Response) -> WSResult<()> {
         log::debug!("Checking protocol");
         let protocols = request.protocols()?;
         for protocol in protocols {
-            if protocol == WAMP_JSON || protocol == WAMP_MSGPACK {
+            if let Some(serializer) = self.router.config.serializers.get(protocol) {
                 response.set_protocol(protocol);
                 self.router.set_protocol(self.info_id, protocol.to_string());
+                if serializer.batched() {
+                    let mut senders = self.router.senders.lock().unwrap();
+                    if let Some(sender) = senders.remove(&self.info_id) {
+                        senders.insert(self.info_id, sender.batched(serializer, self.router.config.batch_window));
+                    }
+                }
                 return Ok(());
             }
         }
         Err(WSError::new(
             WSErrorKind::Protocol,
             format!(
-                "Neither {} nor {} were selected as Websocket sub-protocols",
-                WAMP_JSON, WAMP_MSGPACK
+                "None of {} were selected as Websocket sub-protocols",
+                self.router.config.serializers.protocols().join(", ")
             ),
         ))
     }
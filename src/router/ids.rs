@@ -0,0 +1,117 @@
+//! Generates the IDs of the router.
+//!
+//! WAMP IDs are integers in `[1, 2^53]`, so JavaScript clients can represent them exactly.
+//! Global scope IDs (sessions and publications) are drawn at random, router scope IDs
+//! (subscriptions, registrations and invocations) are counted up.  Both are prefixed with the
+//! ID of the node that generates them, so the nodes of a cluster never hand out the same ID.
+//! The counter starts at a random offset, so a restarted node does not hand out the IDs of
+//! subscriptions and registrations that outlived it in the replicated state.
+//! Session scope IDs are chosen by the clients and only validated.
+use crate::ID;
+use rand::distributions::{Distribution, Uniform};
+use rand::thread_rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The largest valid ID.
+pub const MAX_ID: ID = 1 << 53;
+/// The bits of an ID below the node prefix.
+const NODE_SHIFT: u32 = 43;
+/// The largest node ID that fits in the prefix of an ID.
+pub const MAX_NODE: u64 = (1 << (53 - NODE_SHIFT)) - 1;
+/// The number of router scope IDs of a node.
+const COUNTER_RANGE: u64 = (1 << NODE_SHIFT) - 1;
+
+/// Whether an ID is in the range WAMP allows.
+pub fn is_valid(id: ID) -> bool {
+    id >= 1 && id <= MAX_ID
+}
+
+/// Draws a random valid ID.
+pub fn random_id() -> ID {
+    Uniform::new_inclusive(1, MAX_ID).sample(&mut thread_rng())
+}
+
+/// Generates the IDs of one node of the cluster.
+#[derive(Debug, Clone, Default)]
+pub struct IdGenerator {
+    node: u64,
+    counter: Arc<AtomicU64>,
+}
+
+impl IdGenerator {
+    /// Creates the generator of a node.  Node IDs above `MAX_NODE` are not supported.
+    pub fn new(node: u64) -> IdGenerator {
+        IdGenerator::starting_at(node, Uniform::new(0, COUNTER_RANGE).sample(&mut thread_rng()))
+    }
+
+    fn starting_at(node: u64, offset: u64) -> IdGenerator {
+        assert!(node <= MAX_NODE, "node IDs above {} are not supported", MAX_NODE);
+        IdGenerator {
+            node,
+            counter: Arc::new(AtomicU64::new(offset)),
+        }
+    }
+
+    /// The ID of the node.
+    pub fn node(&self) -> u64 {
+        self.node
+    }
+
+    /// A random global scope ID, such as a session or publication ID.
+    pub fn global(&self) -> ID {
+        let random = Uniform::new(1, 1 << NODE_SHIFT).sample(&mut thread_rng());
+        self.node << NODE_SHIFT | random
+    }
+
+    /// The next router scope ID, such as a subscription or registration ID.
+    pub fn router(&self) -> ID {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed) % COUNTER_RANGE + 1;
+        self.node << NODE_SHIFT | count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_valid, random_id, IdGenerator, COUNTER_RANGE, MAX_ID, MAX_NODE};
+
+    #[test]
+    fn range() {
+        assert!(!is_valid(0));
+        assert!(is_valid(1));
+        assert!(is_valid(MAX_ID));
+        assert!(!is_valid(MAX_ID + 1));
+        assert!(is_valid(random_id()));
+        for node in &[0, 1, MAX_NODE] {
+            let ids = IdGenerator::new(*node);
+            assert!(is_valid(ids.global()));
+            assert!(is_valid(ids.router()));
+        }
+    }
+
+    #[test]
+    fn nodes() {
+        let first = IdGenerator::starting_at(1, 0);
+        let second = IdGenerator::starting_at(2, 0);
+        assert_eq!(first.router(), second.router() - (1 << 43));
+        assert_eq!(first.router() + 1, first.router());
+        assert_eq!(IdGenerator::new(1).router() >> 43, 1);
+        assert_ne!(first.global() >> 43, second.global() >> 43);
+    }
+
+    #[test]
+    fn offsets() {
+        let ids = IdGenerator::starting_at(1, COUNTER_RANGE - 1);
+        assert_eq!(ids.router(), 1 << 43 | COUNTER_RANGE);
+        assert_eq!(ids.router(), 1 << 43 | 1);
+        let restarted = IdGenerator::new(1);
+        let ids = (0..3).map(|_| restarted.router()).collect::<Vec<_>>();
+        assert!(ids.iter().all(|id| is_valid(*id) && id >> 43 == 1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_many_nodes() {
+        IdGenerator::new(MAX_NODE + 1);
+    }
+}
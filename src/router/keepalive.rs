@@ -0,0 +1,114 @@
+//! Drops connections whose peers are gone or idle.
+//!
+//! A client has to complete its handshake within the handshake timeout.  The router pings
+//! every connection at the ping interval and drops peers that did not answer the previous
+//! ping, so half-open connections do not leave sessions behind.  Realms can close sessions
+//! that sent no message for their idle timeout.
+use super::{ConnectionHandler, ConnectionState};
+
+use crate::{Error, ErrorKind, WampResult};
+use std::time::{Duration, Instant};
+use ws::util::Token;
+use ws::CloseCode;
+
+/// Wakes a handler whose client had to complete its handshake.
+pub const HANDSHAKE_TIMEOUT: Token = Token(4);
+/// Wakes a handler that has to ping its peer.
+pub const PING: Token = Token(5);
+/// Wakes a handler whose session may be idle.
+pub const IDLE_TIMEOUT: Token = Token(6);
+
+/// The milliseconds until an idle timeout elapses, if it did not already.
+fn idle_remaining(timeout: u64, idle: Duration) -> Option<u64> {
+    let timeout = Duration::from_millis(timeout);
+    if idle < timeout {
+        Some((timeout - idle).as_millis() as u64 + 1)
+    } else {
+        None
+    }
+}
+
+impl ConnectionHandler {
+    /// Starts the timers of a new connection.
+    pub fn start_timers(&self) -> WampResult<()> {
+        self.schedule(self.router.config.handshake_timeout, HANDSHAKE_TIMEOUT)?;
+        if let Some(interval) = self.router.config.ping_interval {
+            self.schedule(interval, PING)?;
+        }
+        Ok(())
+    }
+
+    /// Starts the idle timer of a session that joined a realm.
+    pub fn start_idle_timer(&self) -> WampResult<()> {
+        match self.realm.as_ref().and_then(|realm| realm.idle_timeout) {
+            Some(timeout) => self.schedule(timeout, IDLE_TIMEOUT),
+            None => Ok(()),
+        }
+    }
+
+    pub fn handle_handshake_timeout(&self) -> WampResult<()> {
+        let state = match self.info() {
+            Ok(info) => info.lock().unwrap().state.clone(),
+            Err(_) => return Ok(()),
+        };
+        if state == ConnectionState::Initializing && self.authenticator.is_none() {
+            log::warn!("Connection {} did not complete its handshake in time", self.info_id);
+            return Err(Error::new(ErrorKind::Timeout));
+        }
+        Ok(())
+    }
+
+    pub fn handle_ping(&mut self) -> WampResult<()> {
+        if self.awaiting_pong {
+            log::warn!("Connection {} did not answer our ping", self.info_id);
+            return Err(Error::new(ErrorKind::Timeout));
+        }
+        let interval = match self.router.config.ping_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        if let Some(sender) = self.router.senders.lock().unwrap().get(&self.info_id) {
+            sender.ping(Vec::new()).map_err(|e| Error::new(ErrorKind::WSError(e)))?;
+        }
+        self.awaiting_pong = true;
+        self.schedule(interval, PING)
+    }
+
+    pub fn handle_idle_timeout(&self) -> WampResult<()> {
+        let timeout = match self.realm.as_ref().and_then(|realm| realm.idle_timeout) {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+        match idle_remaining(timeout, self.last_message.elapsed()) {
+            Some(remaining) => self.schedule(remaining, IDLE_TIMEOUT),
+            None => {
+                log::info!("Closing idle session {}", self.info_id);
+                self.close_connection(CloseCode::Away);
+                Ok(())
+            }
+        }
+    }
+
+    /// Notes that the peer sent a frame, which answers any ping.
+    pub fn peer_alive(&mut self) {
+        self.awaiting_pong = false;
+    }
+
+    /// Notes that the session sent a message.
+    pub fn session_active(&mut self) {
+        self.last_message = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::idle_remaining;
+    use std::time::Duration;
+
+    #[test]
+    fn idle() {
+        assert_eq!(idle_remaining(1000, Duration::from_millis(400)), Some(601));
+        assert_eq!(idle_remaining(1000, Duration::from_millis(1000)), None);
+        assert_eq!(idle_remaining(1000, Duration::from_secs(5)), None);
+    }
+}
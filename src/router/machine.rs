@@ -11,6 +11,7 @@ use simple_raft_node::{
 use crate::router::{
     RouterInfo,
     RouterCore,
+    Realm,
     ConnectionInfo,
     ConnectionState,
     MatchingPolicy,
@@ -18,16 +19,20 @@ use crate::router::{
     ID,
     SubscriptionPatternNode,
     Message,
-    WAMP_JSON,
 };
-use ws::{Message as WSMessage, Result as WSResult, Sender};
+use crate::router::meta::SessionDetails;
+use crate::router::pubsub::{DurableSubscriptions, QueuedEvent};
+use crate::router::rpc::{self, InvocationResult, PendingInvocation, Registration};
+use crate::messages::{InvocationDetails, Reason, WelcomeDetails};
+use crate::{Dict, Error, ErrorKind, List, WampResult};
+use crate::router::sender::Sender;
+use crate::router::serializer::Serializer;
+use ws::Message as WSMessage;
 
-use crate::utils::StructMapWriter;
 use failure::Backtrace;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
-use rmp_serde::Serializer;
 use futures::executor;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,10 +50,47 @@ pub enum RouterChange {
     },
     AddConnection {
         connection_id: u64,
+        node: u64,
     },
     RemoveConnection {
         connection_id: u64,
     },
+    JoinRealm {
+        connection_id: u64,
+        realm: String,
+        session: SessionDetails,
+        resume_token: Option<String>,
+    },
+    KillSession {
+        connection_id: u64,
+        reason: Reason,
+        message: Option<String>,
+        publication_id: ID,
+    },
+    DetachSession {
+        connection_id: u64,
+        generation: u64,
+        until: u64,
+    },
+    BufferMessages {
+        connection_id: u64,
+        messages: Vec<Message>,
+    },
+    ResumeSession {
+        connection_id: u64,
+        session_id: u64,
+        node: u64,
+        resume_token: String,
+        welcome: WelcomeDetails,
+    },
+    ExpireSession {
+        connection_id: u64,
+        publication_id: ID,
+    },
+    RenewLease {
+        node: u64,
+        until: u64,
+    },
     AddSubscription {
         connection_id: u64,
         request_id: u64,
@@ -56,12 +98,29 @@ pub enum RouterChange {
         matching_policy: MatchingPolicy,
         id: ID,
         prefix_id: ID,
+        durable: Option<String>,
     },
     RemoveSubscription {
         connection_id: u64,
         subscription_id: u64,
         request_id: u64,
     },
+    QueueEvent {
+        realm: String,
+        topic: URI,
+        event: QueuedEvent,
+    },
+    AddRegistration {
+        connection_id: u64,
+        request_id: u64,
+        procedure: URI,
+        id: ID,
+    },
+    RemoveRegistration {
+        connection_id: u64,
+        registration_id: u64,
+        request_id: u64,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,15 +130,30 @@ pub enum Broadcast {
         connection_id: u64,
         protocol: String,
     },
+    InvocationResult {
+        callee: u64,
+        invocation_id: ID,
+        result: InvocationResult,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub enum RouterProperty {
-    Subscriptions,
+    Subscriptions {
+        realm: String,
+    },
     Connections,
     Connection {
         connection_id: u64,
     },
+    Registration {
+        realm: String,
+        procedure: String,
+    },
+    DurableSubscriptions {
+        realm: String,
+    },
+    Leases,
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +162,9 @@ pub enum RouterPropertyValue {
     Connections(Arc<Mutex<HashMap<u64, Arc<Mutex<ConnectionInfo>>>>>),
     Connection(Arc<Mutex<ConnectionInfo>>),
     TopicId(u64),
+    Registration(Registration),
+    DurableSubscriptions(Arc<Mutex<DurableSubscriptions>>),
+    Leases(HashMap<u64, u64>),
 }
 
 impl MachineCore for RouterCore {
@@ -119,14 +196,42 @@ impl MachineCore for RouterCore {
                 log::trace!("setting protocol of connection {} to {}", connection_id, protocol);
                 self.set_protocol(connection_id, protocol);
             },
-            RouterChange::AddConnection { connection_id } => {
-                log::trace!("adding connection {}", connection_id);
-                self.add_connection(connection_id);
+            RouterChange::AddConnection { connection_id, node } => {
+                log::trace!("adding connection {} on node {}", connection_id, node);
+                self.add_connection(connection_id, node);
             },
             RouterChange::RemoveConnection { connection_id } => {
                 log::trace!("removing connection {}", connection_id);
                 self.remove_connection(connection_id);
             },
+            RouterChange::JoinRealm { connection_id, realm, session, resume_token } => {
+                log::trace!("connection {} joins realm {}", connection_id, realm);
+                self.join_realm(connection_id, realm, session, resume_token);
+            },
+            RouterChange::KillSession { connection_id, reason, message, publication_id } => {
+                log::trace!("killing session {} with reason {}", connection_id, reason);
+                self.kill_session(connection_id, reason, message, publication_id);
+            },
+            RouterChange::DetachSession { connection_id, generation, until } => {
+                log::trace!("detaching session {}", connection_id);
+                self.detach_session(connection_id, generation, until);
+            },
+            RouterChange::BufferMessages { connection_id, messages } => {
+                log::trace!("buffering {} messages for detached session {}", messages.len(), connection_id);
+                self.buffer_messages(connection_id, messages);
+            },
+            RouterChange::ResumeSession { connection_id, session_id, node, resume_token, welcome } => {
+                log::trace!("connection {} resumes session {}", connection_id, session_id);
+                self.resume_session(connection_id, session_id, node, resume_token, welcome);
+            },
+            RouterChange::ExpireSession { connection_id, publication_id } => {
+                log::trace!("expiring detached session {}", connection_id);
+                self.expire_session(connection_id, publication_id);
+            },
+            RouterChange::RenewLease { node, until } => {
+                log::trace!("node {} is alive until {}", node, until);
+                self.renew_lease(node, until);
+            },
             RouterChange::AddSubscription {
                 connection_id,
                 request_id,
@@ -134,20 +239,24 @@ impl MachineCore for RouterCore {
                 matching_policy,
                 id,
                 prefix_id,
+                durable,
             } => {
                 log::trace!(
                     "adding subscription for topic {:?} on connection {}",
                     topic,
                     connection_id,
                 );
-                self.add_subscription(
+                let result = self.add_subscription(
                     connection_id,
                     request_id,
-                    topic,
+                    topic.clone(),
                     matching_policy,
                     id,
                     prefix_id,
-                ).ok();
+                );
+                if let (Ok(subscription_id), Some(name)) = (result, durable) {
+                    self.attach_durable(connection_id, name, &topic, matching_policy, subscription_id);
+                }
             },
             RouterChange::RemoveSubscription { connection_id, subscription_id, request_id } => {
                 log::trace!(
@@ -155,15 +264,45 @@ impl MachineCore for RouterCore {
                     subscription_id,
                     connection_id,
                 );
-                self.remove_subscription(&connection_id, &subscription_id, &request_id).ok();
+                if self.remove_subscription(&connection_id, &subscription_id, &request_id).is_ok() {
+                    self.remove_durable(connection_id, subscription_id);
+                }
+            },
+            RouterChange::QueueEvent { realm, topic, event } => {
+                log::trace!("queueing event for durable subscriptions to {:?}", topic);
+                self.queue_event(&realm, &topic, event);
+            },
+            RouterChange::AddRegistration { connection_id, request_id, procedure, id } => {
+                log::trace!(
+                    "adding registration for procedure {:?} on connection {}",
+                    procedure,
+                    connection_id,
+                );
+                if let Err(e) = self.add_registration(connection_id, request_id, procedure, id) {
+                    self.send_error(connection_id, e);
+                }
+            },
+            RouterChange::RemoveRegistration { connection_id, registration_id, request_id } => {
+                log::trace!(
+                    "removing registration {} from connection {}",
+                    registration_id,
+                    connection_id,
+                );
+                if let Err(e) = self.remove_registration(connection_id, registration_id, request_id) {
+                    self.send_error(connection_id, e);
+                }
             },
         }
     }
 
     fn retrieve(&self, state_identifier: RouterProperty) -> Result<RouterPropertyValue, RequestError> {
         match state_identifier {
-            RouterProperty::Subscriptions => {
-                Ok(RouterPropertyValue::Subscriptions(self.subscription_manager.subscriptions.clone()))
+            RouterProperty::Subscriptions { realm } => {
+                self.realms.get(&realm)
+                    .ok_or(RequestError::StateRetrieval(Backtrace::new()))
+                    .map(|r| RouterPropertyValue::Subscriptions(
+                        r.subscription_manager.subscriptions.clone()
+                    ))
             },
             RouterProperty::Connections => {
                 Ok(RouterPropertyValue::Connections(self.connections.clone()))
@@ -173,6 +312,19 @@ impl MachineCore for RouterCore {
                     .ok_or(RequestError::StateRetrieval(Backtrace::new()))
                     .map(|c| RouterPropertyValue::Connection(c.clone()))
             },
+            RouterProperty::Registration { realm, procedure } => {
+                self.registration(&realm, &procedure)
+                    .ok_or(RequestError::StateRetrieval(Backtrace::new()))
+                    .map(RouterPropertyValue::Registration)
+            },
+            RouterProperty::DurableSubscriptions { realm } => {
+                self.realms.get(&realm)
+                    .ok_or(RequestError::StateRetrieval(Backtrace::new()))
+                    .map(|r| RouterPropertyValue::DurableSubscriptions(r.durable.clone()))
+            },
+            RouterProperty::Leases => {
+                Ok(RouterPropertyValue::Leases(self.leases.clone()))
+            },
         }
     }
 
@@ -183,6 +335,10 @@ impl MachineCore for RouterCore {
                     log::trace!("sending message {:?} to {}", message, connection_id);
                     self.send_message(connection_id, protocol, message).ok();
                 },
+                Broadcast::InvocationResult { callee, invocation_id, result } => {
+                    log::trace!("received result of invocation {}", invocation_id);
+                    rpc::deliver_result(&self.invocations, &self.senders, callee, invocation_id, result);
+                },
             }
         }
     }
@@ -198,9 +354,16 @@ impl Machine for RouterInfo {
 
     fn core(&self) -> RouterCore {
         RouterCore {
-            subscription_manager: Default::default(),
+            node: self.ids.node(),
+            realms: self.config.realms
+                .iter()
+                .map(|realm| (realm.name.clone(), Realm::default()))
+                .collect(),
+            config: self.config.clone(),
             connections: Default::default(),
             senders: self.senders.clone(),
+            invocations: self.invocations.clone(),
+            leases: HashMap::new(),
         }
     }
 }
@@ -239,8 +402,10 @@ impl RouterInfo {
         log::trace!("senders map: {:?}", self.senders.lock().unwrap());
 
         if let Some(ref manager) = self.request_manager {
-            executor::block_on(apply(manager, RouterChange::AddConnection { connection_id }))
-                .expect("failed to add connection");
+            executor::block_on(apply(manager, RouterChange::AddConnection {
+                connection_id,
+                node: self.ids.node(),
+            })).expect("failed to add connection");
         } else {
             panic!("router is not initialized");
         }
@@ -257,6 +422,166 @@ impl RouterInfo {
         }
     }
 
+    pub fn join_realm(
+        &self,
+        connection_id: u64,
+        realm: String,
+        session: SessionDetails,
+        resume_token: Option<String>,
+    ) {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(manager, RouterChange::JoinRealm {
+                connection_id,
+                realm,
+                session,
+                resume_token,
+            })).expect("failed to join realm");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Says goodbye to a session, which may be connected to another node, and closes its
+    /// connection.
+    pub fn kill_session(&self, connection_id: u64, reason: Reason, message: Option<String>) {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(manager, RouterChange::KillSession {
+                connection_id,
+                reason,
+                message,
+                publication_id: self.ids.global(),
+            })).expect("failed to kill session");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Lets a session of this node go while the node shuts down.  The session is detached
+    /// until the given milliseconds since the epoch, or receives a goodbye if resumption is
+    /// not configured.  The node may have lost the cluster, so a change the cluster does not
+    /// accept is logged instead of ending the shutdown.
+    pub fn release_session(&self, connection_id: u64, generation: u64, detach_until: Option<u64>) {
+        let change = match detach_until {
+            Some(until) => {
+                self.senders.lock().unwrap().remove(&connection_id);
+                RouterChange::DetachSession { connection_id, generation, until }
+            }
+            None => RouterChange::KillSession {
+                connection_id,
+                reason: Reason::SystemShutdown,
+                message: None,
+                publication_id: self.ids.global(),
+            },
+        };
+        if let Some(ref manager) = self.request_manager {
+            if let Err(e) = executor::block_on(apply(manager, change)) {
+                log::warn!("Could not release session {}: {:?}", connection_id, e);
+            }
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Keeps a session of this node that lost its connection until the given milliseconds
+    /// since the epoch, unless another connection took over the session.
+    pub fn detach_session(&self, connection_id: u64, generation: u64, until: u64) {
+        self.senders.lock().unwrap().remove(&connection_id);
+
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(manager, RouterChange::DetachSession {
+                connection_id,
+                generation,
+                until,
+            })).expect("failed to detach session");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Keeps the messages for detached sessions that were sent through this node in the
+    /// replicated state, with one change per session.  Messages the cluster does not accept
+    /// are dropped.
+    pub fn buffer_messages(&self) {
+        let unbuffered = self.unbuffered.lock().unwrap().drain().collect::<Vec<_>>();
+        for (connection_id, messages) in unbuffered {
+            if messages.is_empty() {
+                continue;
+            }
+            if let Some(ref manager) = self.request_manager {
+                let change = RouterChange::BufferMessages {
+                    connection_id,
+                    messages: messages.into_iter().collect(),
+                };
+                if let Err(e) = executor::block_on(apply(manager, change)) {
+                    log::warn!("Could not buffer the messages for session {}: {:?}", connection_id, e);
+                }
+            } else {
+                panic!("router is not initialized");
+            }
+        }
+    }
+
+    /// Moves a session to the connection of this node that presented its resume token.  The
+    /// session is resumed if the connection is gone afterwards.
+    pub fn resume_session(
+        &self,
+        connection_id: u64,
+        session_id: u64,
+        resume_token: String,
+        welcome: WelcomeDetails,
+    ) {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(manager, RouterChange::ResumeSession {
+                connection_id,
+                session_id,
+                node: self.ids.node(),
+                resume_token,
+                welcome,
+            })).expect("failed to resume session");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Ends a detached session that was not resumed in time.
+    pub fn expire_session(&self, connection_id: u64) {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(manager, RouterChange::ExpireSession {
+                connection_id,
+                publication_id: self.ids.global(),
+            })).expect("failed to expire session");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Notes that this node is alive until the given milliseconds since the epoch.
+    pub fn renew_lease(&self, until: u64) {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(manager, RouterChange::RenewLease {
+                node: self.ids.node(),
+                until,
+            })).expect("failed to renew lease");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// The milliseconds since the epoch until which each node of the cluster is known to be
+    /// alive.
+    pub fn leases(&self) -> HashMap<u64, u64> {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(retrieve(manager, RouterProperty::Leases))
+                .and_then(|res| match res {
+                    RouterPropertyValue::Leases(leases) => Ok(leases),
+                    _ => Err(RequestError::StateRetrieval(Backtrace::new())),
+                })
+                .expect("failed to retrieve leases")
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
     pub fn remove_subscription(
         &self,
         connection_id: u64,
@@ -285,6 +610,7 @@ impl RouterInfo {
         matching_policy: MatchingPolicy,
         id: ID,
         prefix_id: ID,
+        durable: Option<String>,
     ) {
         log::debug!(
             "machine is proposing to add subscription ({}, {}, {:?}, {:?})",
@@ -303,6 +629,7 @@ impl RouterInfo {
                     matching_policy,
                     id,
                     prefix_id,
+                    durable,
                 },
             )).expect("failed to add subscription");
         } else {
@@ -310,9 +637,120 @@ impl RouterInfo {
         }
     }
 
-    pub fn subscriptions(&self) -> Arc<Mutex<SubscriptionPatternNode<u64>>> {
+    /// Queues an event for the durable subscriptions of a realm whose owners are offline.
+    pub fn queue_event(&self, realm: String, topic: URI, event: QueuedEvent) {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(manager, RouterChange::QueueEvent { realm, topic, event }))
+                .expect("failed to queue event");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    pub fn add_registration(&self, connection_id: u64, request_id: u64, procedure: URI, id: ID) {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(apply(
+                manager,
+                RouterChange::AddRegistration {
+                    connection_id,
+                    request_id,
+                    procedure,
+                    id,
+                },
+            )).expect("failed to add registration");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    pub fn remove_registration(&self, connection_id: u64, registration_id: u64, request_id: u64) {
         if let Some(ref manager) = self.request_manager {
-            executor::block_on(retrieve(manager, RouterProperty::Subscriptions))
+            executor::block_on(apply(
+                manager,
+                RouterChange::RemoveRegistration {
+                    connection_id,
+                    registration_id,
+                    request_id,
+                },
+            )).expect("failed to remove registration");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    pub fn registration(&self, realm: String, procedure: String) -> Option<Registration> {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(retrieve(manager, RouterProperty::Registration { realm, procedure }))
+                .ok()
+                .and_then(|res| match res {
+                    RouterPropertyValue::Registration(registration) => Some(registration),
+                    _ => None,
+                })
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Sends an invocation of a registration on behalf of a connection of this node.
+    pub fn invoke(
+        &self,
+        caller: u64,
+        registration: Registration,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+    ) -> ID {
+        let invocation_id = self.ids.router();
+        self.invocations.lock().unwrap().insert(invocation_id, PendingInvocation {
+            caller,
+            callee: registration.callee,
+            result: None,
+        });
+        self.send_message(registration.callee, Message::Invocation(
+            invocation_id,
+            registration.id,
+            InvocationDetails::new(),
+            args,
+            kwargs,
+        ));
+        invocation_id
+    }
+
+    /// Passes the result of an invocation to its caller, which may be connected to another node.
+    pub fn invocation_result(&self, callee: u64, invocation_id: ID, result: InvocationResult) {
+        if rpc::deliver_result(&self.invocations, &self.senders, callee, invocation_id, result.clone()) {
+            return;
+        }
+        if let Some(ref manager) = self.request_manager {
+            log::trace!("broadcasting result of invocation {}", invocation_id);
+            let bc = rmp_serde::encode::to_vec(&Broadcast::InvocationResult {
+                callee,
+                invocation_id,
+                result,
+            }).expect("failed to encode broadcast");
+            broadcast(manager, bc).expect("failed to send invocation result");
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
+    /// Removes an invocation if its result has arrived.
+    pub fn take_invocation_result(&self, invocation_id: ID) -> Option<InvocationResult> {
+        let mut invocations = self.invocations.lock().unwrap();
+        if invocations.get(&invocation_id)?.result.is_some() {
+            invocations.remove(&invocation_id).and_then(|invocation| invocation.result)
+        } else {
+            None
+        }
+    }
+
+    /// Forgets an invocation, so a late result is dropped.
+    pub fn cancel_invocation(&self, invocation_id: ID) {
+        self.invocations.lock().unwrap().remove(&invocation_id);
+    }
+
+    pub fn subscriptions(&self, realm: String) -> Arc<Mutex<SubscriptionPatternNode<u64>>> {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(retrieve(manager, RouterProperty::Subscriptions { realm }))
                 .and_then(|res| match res {
                     RouterPropertyValue::Subscriptions(subscriptions) => Ok(subscriptions),
                     _ => Err(RequestError::StateRetrieval(Backtrace::new())),
@@ -323,6 +761,19 @@ impl RouterInfo {
         }
     }
 
+    pub fn durable_subscriptions(&self, realm: String) -> Arc<Mutex<DurableSubscriptions>> {
+        if let Some(ref manager) = self.request_manager {
+            executor::block_on(retrieve(manager, RouterProperty::DurableSubscriptions { realm }))
+                .and_then(|res| match res {
+                    RouterPropertyValue::DurableSubscriptions(durable) => Ok(durable),
+                    _ => Err(RequestError::StateRetrieval(Backtrace::new())),
+                })
+                .expect("failed to retrieve durable subscriptions")
+        } else {
+            panic!("router is not initialized");
+        }
+    }
+
     pub fn connections(&self) -> Arc<Mutex<HashMap<u64, Arc<Mutex<ConnectionInfo>>>>> {
         if let Some(ref manager) = self.request_manager {
             executor::block_on(retrieve(manager, RouterProperty::Connections))
@@ -351,18 +802,32 @@ impl RouterInfo {
     pub fn send_message(&self, connection_id: u64, message: Message) {
         if let Ok(arc) = self.connection(connection_id) {
             let connection = arc.lock().unwrap();
+            {
+                // messages for detached sessions are buffered in batches, and later messages
+                // queue up behind them to keep their order
+                let mut unbuffered = self.unbuffered.lock().unwrap();
+                if connection.state == ConnectionState::Detached || unbuffered.contains_key(&connection_id) {
+                    let max_buffered = self.config.resumption
+                        .as_ref()
+                        .map_or(0, |resumption| resumption.max_buffered);
+                    let messages = unbuffered.entry(connection_id).or_insert_with(VecDeque::new);
+                    messages.push_back(message);
+                    if messages.len() > max_buffered {
+                        messages.pop_front();
+                    }
+                    return;
+                }
+            }
             {
                 if let Some(sender) = self.senders.lock().unwrap().get(&connection_id) {
                     log::info!("Sending message {:?} via {}", message, connection.protocol);
-                    let send_result = if connection.protocol == WAMP_JSON {
-                        log::debug!("json");
-                        send_message_json(sender, &message)
-                    } else {
-                        log::debug!("msgpack");
-                        send_message_msgpack(sender, &message)
-                    };
-                    log::info!("sent");
-                    send_result.expect("failed to send message");
+                    match self.config.serializers.get(&connection.protocol) {
+                        Some(serializer) => {
+                            send_message_with(sender, serializer.as_ref(), &message).expect("failed to send message");
+                            log::info!("sent");
+                        }
+                        None => log::warn!("No serializer for {:?}, dropping message", connection.protocol),
+                    }
                     return;
                 }
             }
@@ -387,18 +852,18 @@ impl RouterInfo {
     }
 }
 
-pub fn send_message_json(sender: &Sender, message: &Message) -> WSResult<()> {
-    // Send the message
-    let text = serde_json::to_string(message).unwrap();
-    log::info!("sending {}", text);
-    sender.send(WSMessage::Text(text))
-}
-
-pub fn send_message_msgpack(sender: &Sender, message: &Message) -> WSResult<()> {
-    // Send the message
-    let mut buf: Vec<u8> = Vec::new();
-    message
-        .serialize(&mut Serializer::with(&mut buf, StructMapWriter))
-        .unwrap();
-    sender.send(WSMessage::Binary(buf))
+/// Encodes a message with the serializer of a connection and sends it.  Events may wait for
+/// other events on batched connections.
+pub fn send_message_with(sender: &Sender, serializer: &dyn Serializer, message: &Message) -> WampResult<()> {
+    let payload = serializer.encode(message)?;
+    let frame = if serializer.binary() {
+        WSMessage::Binary(payload)
+    } else {
+        WSMessage::Text(String::from_utf8(payload).map_err(|_| Error::new(ErrorKind::MalformedData))?)
+    };
+    let result = match *message {
+        Message::Event(..) => sender.send_event(frame),
+        _ => sender.send(frame),
+    };
+    result.map_err(|e| Error::new(ErrorKind::WSError(e)))
 }
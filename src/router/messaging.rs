@@ -1,31 +1,90 @@
 use crate::router::{ConnectionHandler, ConnectionState};
-use ws::{CloseCode, Handler, Message as WSMessage,
+use crate::router::batching::FLUSH;
+use crate::router::authorization::Decision;
+use crate::router::protocol::{self, Verdict};
+use crate::router::handshake::CLOSE_TIMEOUT;
+use crate::router::keepalive::{HANDSHAKE_TIMEOUT, IDLE_TIMEOUT, PING};
+use crate::router::rpc::{INVOCATION_RESULT, INVOCATION_TIMEOUT};
+use ws::{CloseCode, Frame, Handler, Handshake, Message as WSMessage,
          Request, Response, Result as WSResult};
+use ws::util::Token;
 
 use crate::messages::{ErrorDetails, ErrorType, Message, Reason};
-use rmp_serde::Deserializer as RMPDeserializer;
-use serde_json;
-use serde::Deserialize;
 use std::collections::HashMap;
-use std::io::Cursor;
+use std::io;
 use crate::{Error, ErrorKind, WampResult, ID};
+#[cfg(feature = "ssl")]
+use crate::router::tls;
+#[cfg(feature = "ssl")]
+use openssl::ssl::SslStream;
+#[cfg(feature = "ssl")]
+use std::net::TcpStream;
+#[cfg(feature = "ssl")]
+use ws::{Error as WSError, ErrorKind as WSErrorKind};
 
 impl ConnectionHandler {
     pub fn send_message(&self, message: Message) {
         self.router.send_message(self.info_id, message)
     }
 
+    /// Wakes this handler with the given token after the given milliseconds.
+    pub fn schedule(&self, ms: u64, token: Token) -> WampResult<()> {
+        match self.router.senders.lock().unwrap().get(&self.info_id) {
+            Some(sender) => sender.timeout(ms, token).map_err(|e| Error::new(ErrorKind::WSError(e))),
+            None => Ok(()),
+        }
+    }
+
     fn handle_message(&mut self, message: Message) -> WampResult<()> {
         log::debug!("Received message {:?}", message);
+        match self.verdict(&message) {
+            Verdict::Handle => {}
+            Verdict::Ignore => {
+                log::debug!("Ignoring message of connection {}, which is shutting down or was taken over", self.info_id);
+                return Ok(());
+            }
+            Verdict::Violation(violation) => {
+                return Err(Error::new(ErrorKind::ProtocolViolation(violation)));
+            }
+        }
+        if !self.has_role(&message) {
+            log::warn!("Connection {} did not announce the role to send {:?}", self.info_id, message);
+            return self.refuse(message, Reason::NoSuchRole);
+        }
+        match self.authorize(&message)? {
+            Some(decision) => self.dispatch(message, decision),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles a message once it is authorized.
+    pub fn dispatch(&mut self, message: Message, decision: Decision) -> WampResult<()> {
+        let disclose = match decision {
+            Decision::Allow { disclose } => disclose,
+            Decision::Deny => return self.refuse(message, Reason::NotAuthorized),
+        };
+        if let Message::Publish(_, ref options, _, _, _) = message {
+            if options.should_disclose() && !self.may_disclose() {
+                log::warn!("Connection {} may not disclose its identity", self.info_id);
+                return self.refuse(message, Reason::OptionDisallowedDiscloseMe);
+            }
+        }
         match message {
             Message::Hello(realm, details) => {
                 self.handle_hello(realm, details)?;
             },
+            Message::Authenticate(signature, extra) => {
+                self.handle_authenticate(signature, extra)?;
+            },
+            Message::Abort(_details, reason) => {
+                self.handle_abort(reason);
+            },
             Message::Subscribe(request_id, options, topic) => {
-                self.handle_subscribe(request_id, options, topic);
+                self.handle_subscribe(request_id, options, topic)?;
             }
             Message::Publish(request_id, options, topic, args, kwargs) => {
-                self.handle_publish(request_id, options, topic, args, kwargs);
+                let disclose = disclose || options.should_disclose();
+                self.handle_publish(request_id, options, topic, args, kwargs, disclose);
             }
             Message::Unsubscribe(request_id, topic_id) => {
                 self.handle_unsubscribe(request_id, topic_id);
@@ -33,20 +92,20 @@ impl ConnectionHandler {
             Message::Goodbye(details, reason) => {
                 self.handle_goodbye(details, reason)?;
             },
-            Message::Register(_request_id, _options, _procedure) => {
-                unimplemented!();
+            Message::Register(request_id, options, procedure) => {
+                self.handle_register(request_id, options, procedure)?;
             }
-            Message::Unregister(_request_id, _procedure_id) => {
-                unimplemented!();
+            Message::Unregister(request_id, registration_id) => {
+                self.handle_unregister(request_id, registration_id);
             }
-            Message::Call(_request_id, _options, _procedure, _args, _kwargs) => {
-                unimplemented!();
+            Message::Call(request_id, options, procedure, args, kwargs) => {
+                self.handle_call(request_id, options, procedure, args, kwargs)?;
             }
-            Message::Yield(_invocation_id, _options, _args, _kwargs) => {
-                unimplemented!();
+            Message::Yield(invocation_id, options, args, kwargs) => {
+                self.handle_yield(invocation_id, options, args, kwargs);
             }
-            Message::Error(_e_type, _request_id, _details, _reason, _args, _kwargs) => {
-                unimplemented!();
+            Message::Error(ErrorType::Invocation, invocation_id, _details, reason, args, kwargs) => {
+                self.handle_invocation_error(invocation_id, reason, args, kwargs);
             }
             t => Err(Error::new(ErrorKind::InvalidMessageType(t)))?,
         }
@@ -54,76 +113,148 @@ impl ConnectionHandler {
         Ok(())
     }
 
-    fn parse_message(&self, msg: WSMessage) -> WampResult<Message> {
-        match msg {
-            WSMessage::Text(payload) => match serde_json::from_str(&payload) {
-                Ok(message) => Ok(message),
-                Err(e) => Err(Error::new(ErrorKind::JSONError(e))),
-            },
-            WSMessage::Binary(payload) => {
-                let mut de = RMPDeserializer::new(Cursor::new(payload));
-                match Deserialize::deserialize(&mut de) {
-                    Ok(message) => Ok(message),
-                    Err(e) => Err(Error::new(ErrorKind::MsgPackError(e))),
-                }
-            }
+    /// Whether the session announced the role that sends the message in its `HELLO` message.
+    fn has_role(&self, message: &Message) -> bool {
+        let roles = match self.roles {
+            Some(ref roles) => roles,
+            None => return true,
+        };
+        match *message {
+            Message::Publish(..) => roles.publisher.is_some(),
+            Message::Subscribe(..) | Message::Unsubscribe(..) => roles.subscriber.is_some(),
+            Message::Call(..) => roles.caller.is_some(),
+            Message::Register(..)
+            | Message::Unregister(..)
+            | Message::Yield(..)
+            | Message::Error(ErrorType::Invocation, ..) => roles.callee.is_some(),
+            _ => true,
         }
     }
 
+    /// Answers a message that is not handled with an error of the given reason.
+    fn refuse(&self, message: Message, reason: Reason) -> WampResult<()> {
+        let (err_type, request_id) = match message {
+            // Publications are only answered if the publisher asked for an acknowledgement.
+            Message::Publish(request_id, ref options, _, _, _) if options.should_acknowledge() => {
+                (ErrorType::Publish, request_id)
+            }
+            Message::Subscribe(request_id, _, _) => (ErrorType::Subscribe, request_id),
+            Message::Unsubscribe(request_id, _) => (ErrorType::Unsubscribe, request_id),
+            Message::Register(request_id, _, _) => (ErrorType::Register, request_id),
+            Message::Unregister(request_id, _) => (ErrorType::Unregister, request_id),
+            Message::Call(request_id, _, _, _, _) => (ErrorType::Call, request_id),
+            _ => return Ok(()),
+        };
+        Err(Error::new(ErrorKind::ErrorReason(err_type, request_id, reason)))
+    }
+
+    /// Handles the messages that waited for the authorizer, until one has to wait again.
+    fn handle_parked_messages(&mut self) -> WSResult<()> {
+        handle_in_order(
+            self,
+            |handler| handler.authorizations.next_parked(),
+            ConnectionHandler::handle_message,
+            ConnectionHandler::on_message_error,
+        )
+    }
+
+    /// Parses the messages of a frame with the serializer of the connection.  A frame holds
+    /// several messages with a batched sub-protocol.
+    fn parse_messages(&self, msg: WSMessage) -> WampResult<Vec<Message>> {
+        let protocol = self.info().map(|info| info.lock().unwrap().protocol.clone()).unwrap_or_default();
+        let serializer = match self.router.config.serializers.get(&protocol) {
+            Some(serializer) => serializer,
+            None => return Err(Error::new(ErrorKind::InvalidState("No serializer was negotiated"))),
+        };
+        let frame = msg.into_data();
+        serializer.split(&frame)?
+            .into_iter()
+            .map(|payload| serializer.deserialize(payload))
+            .collect()
+    }
+
     fn send_error(&self, err_type: ErrorType, request_id: ID, reason: Reason) {
         self.send_message(Message::Error(err_type, request_id, HashMap::new(), reason, None, None));
     }
 
-    fn send_abort(&self, reason: Reason) {
-        self.send_message(Message::Abort(ErrorDetails::new(), reason));
+    /// Aborts the session and closes its connection.
+    fn abort(&self, reason: Reason, message: Option<&str>) {
+        let details = match message {
+            Some(message) => ErrorDetails::new_with_message(message),
+            None => ErrorDetails::new(),
+        };
+        let code = if reason == Reason::ProtocolViolation {
+            CloseCode::Protocol
+        } else {
+            CloseCode::Normal
+        };
+        self.send_message(Message::Abort(details, reason));
+        self.close_connection(code);
     }
 
     fn on_message_error(&self, error: Error) -> WSResult<()> {
         use std::error::Error as StdError;
-        match error.get_kind() {
+        let violation = match error.get_kind() {
             ErrorKind::WSError(e) => Err(e)?,
-            ErrorKind::URLError(_) => unimplemented!(),
             ErrorKind::HandshakeError(r) => {
                 log::error!("Handshake error: {}", r);
-                self.send_abort(r);
-                self.terminate_connection()?;
-            }
-            ErrorKind::UnexpectedMessage(msg) => {
-                log::error!("Unexpected Message: {}", msg);
-                self.terminate_connection()?;
-            }
-            ErrorKind::ThreadError(_) => unimplemented!(),
-            ErrorKind::ConnectionLost => unimplemented!(),
-            ErrorKind::Closing(_) => {
-                unimplemented!{}
+                self.abort(r, None);
+                return Ok(());
             }
-            ErrorKind::JSONError(e) => {
-                log::error!("Could not parse JSON: {}", e);
-                self.terminate_connection()?;
+            ErrorKind::ErrorReason(err_type, id, reason) => {
+                self.send_error(err_type, id, reason);
+                return Ok(());
             }
-            ErrorKind::MsgPackError(e) => {
-                log::error!("Could not parse MsgPack: {}", e.description());
-                self.terminate_connection()?;
-            }
-            ErrorKind::MalformedData => unimplemented!(),
+            ErrorKind::ProtocolViolation(violation) => violation,
+            ErrorKind::UnexpectedMessage(msg) | ErrorKind::InvalidState(msg) => msg.to_string(),
             ErrorKind::InvalidMessageType(msg) => {
-                log::error!("Router unable to handle message {:?}", msg);
-                self.terminate_connection()?;
-            }
-            ErrorKind::InvalidState(s) => {
-                log::error!("Invalid State: {}", s);
-                self.terminate_connection()?;
+                format!("Unexpected {} message", protocol::message_name(&msg))
             }
+            ErrorKind::JSONError(e) => format!("Could not parse JSON: {}", e),
+            ErrorKind::MsgPackError(e) => format!("Could not parse MsgPack: {}", e.description()),
+            ErrorKind::CBORError(e) => format!("Could not parse CBOR: {}", e),
+            ErrorKind::UBJSONError(e) => format!("Could not parse UBJSON: {}", e),
+            ErrorKind::MalformedData => "Received malformed data".to_string(),
             ErrorKind::Timeout => {
-                log::error!("Connection timeout");
-                self.terminate_connection()?;
+                log::warn!("Dropping connection {}, which timed out", self.info_id);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the peer timed out").into());
             }
-            ErrorKind::ErrorReason(err_type, id, reason) => self.send_error(err_type, id, reason),
-        }
+            kind => {
+                log::error!("Closing connection {}: {}", self.info_id, kind.description());
+                self.close_connection(CloseCode::Error);
+                return Ok(());
+            }
+        };
+        log::error!("Protocol violation of connection {}: {}", self.info_id, violation);
+        self.abort(Reason::ProtocolViolation, Some(&violation));
         Ok(())
     }
 }
 
+/// Handles the messages `next` returns in order and passes their errors on.  The messages after
+/// an error that ends the session are not taken, while a refused request does not affect the
+/// others.
+fn handle_in_order<T, F>(
+    target: &mut T,
+    mut next: F,
+    handle: fn(&mut T, Message) -> WampResult<()>,
+    fail: fn(&T, Error) -> WSResult<()>,
+) -> WSResult<()>
+where
+    F: FnMut(&mut T) -> Option<Message>,
+{
+    while let Some(message) = next(target) {
+        if let Err(e) = handle(target, message) {
+            let refusal = e.is_refusal();
+            fail(target, e)?;
+            if !refusal {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
 impl Handler for ConnectionHandler {
     fn on_request(&mut self, request: &Request) -> WSResult<Response> {
         log::info!("New request");
@@ -139,26 +270,143 @@ impl Handler for ConnectionHandler {
         Ok(response)
     }
 
+    #[cfg(feature = "ssl")]
+    fn upgrade_ssl_server(&mut self, sock: TcpStream) -> WSResult<SslStream<TcpStream>> {
+        match self.tls {
+            Some(ref acceptor) => tls::accept(acceptor, sock, &mut self.transport),
+            None => Err(WSError::new(WSErrorKind::Internal, "TLS is not configured")),
+        }
+    }
+
+    fn on_open(&mut self, shake: Handshake) -> WSResult<()> {
+        self.transport.peer = shake.peer_addr.map(|addr| addr.to_string());
+        match self.start_timers() {
+            Err(e) => self.on_message_error(e),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> WSResult<Option<Frame>> {
+        self.peer_alive();
+        Ok(Some(frame))
+    }
+
     fn on_message(&mut self, msg: WSMessage) -> WSResult<()> {
         log::debug!("Receveied message: {:?}", msg);
-        let message = match self.parse_message(msg) {
+        self.session_active();
+        let messages = match self.parse_messages(msg) {
             Err(e) => return self.on_message_error(e),
             Ok(m) => m,
         };
-        match self.handle_message(message) {
-            Err(e) => self.on_message_error(e),
-            _ => Ok(()),
+        let mut messages = messages.into_iter();
+        handle_in_order(
+            self,
+            |_| messages.next(),
+            ConnectionHandler::handle_message,
+            ConnectionHandler::on_message_error,
+        )
+    }
+
+    fn on_timeout(&mut self, event: Token) -> WSResult<()> {
+        let results = match event {
+            INVOCATION_RESULT => vec![
+                self.handle_authenticator_result(),
+                self.handle_authorizer_result(),
+                self.handle_call_results(),
+            ],
+            INVOCATION_TIMEOUT => vec![self.handle_authenticator_timeout(), self.handle_authorizer_timeout()],
+            CLOSE_TIMEOUT => vec![self.handle_close_timeout()],
+            HANDSHAKE_TIMEOUT => vec![self.handle_handshake_timeout()],
+            PING => vec![self.handle_ping()],
+            IDLE_TIMEOUT => vec![self.handle_idle_timeout()],
+            FLUSH => vec![self.handle_flush()],
+            _ => Vec::new(),
+        };
+        for result in results {
+            if let Err(e) = result {
+                self.on_message_error(e)?;
+            }
         }
+        self.handle_parked_messages()
     }
 
     fn on_close(&mut self, code: CloseCode, reason: &str) {
         log::debug!("connection closed with {:?}: {}", code, reason);
         if let Ok(conn) = self.router.connection(self.info_id) {
-            let state = conn.lock().unwrap().state.clone();
-            if state != ConnectionState::Disconnected {
+            let (state, generation) = {
+                let conn = conn.lock().unwrap();
+                (conn.state.clone(), conn.generation)
+            };
+            if generation != self.generation {
+                log::trace!("Session {} was resumed by another connection", self.info_id);
+                self.cancel_invocations();
+            } else if state == ConnectionState::Connected && self.router.config.resumption.is_some() {
+                self.detach();
+            } else if state == ConnectionState::Detached {
+                log::trace!("Session {} was detached before its connection closed", self.info_id);
+            } else if state != ConnectionState::Disconnected {
                 log::trace!("Client disconnected.  Closing connection");
                 self.terminate_connection().ok();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::handle_in_order;
+    use crate::messages::{ErrorType, Message, PublishOptions, Reason, SubscribeOptions, URI};
+    use crate::{Error, ErrorKind, WampResult};
+    use std::cell::Cell;
+    use ws::Result as WSResult;
+
+    /// Records the requests it handles and refuses subscriptions.
+    #[derive(Default)]
+    struct Session {
+        handled: Vec<u64>,
+        errors: Cell<usize>,
+    }
+
+    fn handle(session: &mut Session, message: Message) -> WampResult<()> {
+        match message {
+            Message::Subscribe(request_id, _, _) => {
+                session.handled.push(request_id);
+                Err(Error::new(ErrorKind::ErrorReason(ErrorType::Subscribe, request_id, Reason::NotAuthorized)))
+            }
+            Message::Publish(request_id, _, _, _, _) => {
+                session.handled.push(request_id);
+                Ok(())
+            }
+            _ => Err(Error::new(ErrorKind::ProtocolViolation("unexpected message".to_string()))),
+        }
+    }
+
+    fn fail(session: &Session, _error: Error) -> WSResult<()> {
+        session.errors.set(session.errors.get() + 1);
+        Ok(())
+    }
+
+    fn publish(request_id: u64) -> Message {
+        Message::Publish(request_id, PublishOptions::new(false), URI::new("com.example.topic"), None, None)
+    }
+
+    #[test]
+    fn batch_with_violation() {
+        let mut session = Session::default();
+        let mut messages = vec![publish(1), Message::Unsubscribed(2), publish(3)].into_iter();
+        handle_in_order(&mut session, |_| messages.next(), handle, fail).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(session.handled, vec![1]);
+        assert_eq!(session.errors.get(), 1);
+    }
+
+    #[test]
+    fn batch_with_refusal() {
+        let mut session = Session::default();
+        let subscribe = Message::Subscribe(2, SubscribeOptions::new(), URI::new("com.example.topic"));
+        let mut messages = vec![publish(1), subscribe, publish(3)].into_iter();
+        handle_in_order(&mut session, |_| messages.next(), handle, fail).unwrap();
+        assert_eq!(session.handled, vec![1, 2, 3]);
+        assert_eq!(session.errors.get(), 1);
+    }
+}
@@ -0,0 +1,290 @@
+//! Implements the session meta API, which lets sessions inspect and kill the sessions of their
+//! realm and subscribe to sessions joining and leaving it.
+//!
+//! The details of every session are part of the replicated connection state, so the meta API
+//! covers the sessions of all nodes of the cluster.
+//!
+//! Sessions may only inspect or kill other sessions if a permission of their authrole allows
+//! calling the procedure.  Realms without roles do not let any session call them.
+use super::ConnectionHandler;
+
+use crate::messages::{ClientRoles, Dict, List, Reason, URI, Value};
+use crate::router::authorization;
+use crate::router::config::{Action, RealmConfig};
+use crate::ID;
+use serde::{Deserialize, Serialize};
+
+/// The details of a session that joined a realm.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionDetails {
+    pub authid: String,
+    pub authrole: String,
+    pub authmethod: String,
+    pub authprovider: String,
+    /// The kind of transport, such as `websocket`.
+    pub transport: String,
+    /// The address of the peer.
+    pub peer: Option<String>,
+    /// The agent the session announced in its `HELLO` message.
+    pub agent: Option<String>,
+    /// The roles and features the session announced in its `HELLO` message.
+    pub roles: ClientRoles,
+}
+
+impl SessionDetails {
+    /// The details as they are returned by `wamp.session.get` and published with
+    /// `wamp.session.on_join`.
+    pub fn to_dict(&self, session_id: ID, realm: &str) -> Dict {
+        let mut transport = Dict::new();
+        transport.insert("type".to_string(), Value::String(self.transport.clone()));
+        if let Some(ref peer) = self.peer {
+            transport.insert("peer".to_string(), Value::String(peer.clone()));
+        }
+        let mut details = Dict::new();
+        details.insert("session".to_string(), Value::UnsignedInteger(session_id));
+        details.insert("realm".to_string(), Value::String(realm.to_string()));
+        details.insert("authid".to_string(), Value::String(self.authid.clone()));
+        details.insert("authrole".to_string(), Value::String(self.authrole.clone()));
+        details.insert("authmethod".to_string(), Value::String(self.authmethod.clone()));
+        details.insert("authprovider".to_string(), Value::String(self.authprovider.clone()));
+        details.insert("transport".to_string(), Value::Dict(transport));
+        if let Some(ref agent) = self.agent {
+            details.insert("agent".to_string(), Value::String(agent.clone()));
+        }
+        details
+    }
+}
+
+fn session_id(value: Option<&Value>) -> Result<ID, Reason> {
+    match value {
+        Some(Value::UnsignedInteger(id)) => Ok(*id),
+        _ => Err(Reason::InvalidArgument),
+    }
+}
+
+/// The procedures of the session meta API.
+const PROCEDURES: [&str; 6] = [
+    "wamp.session.count",
+    "wamp.session.list",
+    "wamp.session.get",
+    "wamp.session.kill",
+    "wamp.session.kill_by_authid",
+    "wamp.session.kill_by_authrole",
+];
+
+/// Whether a session of the authrole may call a procedure of the session meta API.
+fn may_call(realm: Option<&RealmConfig>, authrole: &str, procedure: &str) -> bool {
+    realm.map_or(false, |realm| authorization::granted(realm, authrole, procedure, Action::Call))
+}
+
+fn string(value: Option<&Value>) -> Result<&str, Reason> {
+    match value {
+        Some(Value::String(string)) => Ok(string),
+        _ => Err(Reason::InvalidArgument),
+    }
+}
+
+/// The authroles a `wamp.session.count` or `wamp.session.list` call is restricted to.
+fn authroles(value: Option<&Value>) -> Result<Option<Vec<&str>>, Reason> {
+    match value {
+        None => Ok(None),
+        Some(Value::List(authroles)) => authroles.iter()
+            .map(|authrole| string(Some(authrole)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+        _ => Err(Reason::InvalidArgument),
+    }
+}
+
+/// The reason and message of a `wamp.session.kill*` call.
+fn kill_reason(kwargs: &Dict) -> Result<(Reason, Option<String>), Reason> {
+    let reason = match kwargs.get("reason") {
+        None => Reason::Killed,
+        reason => Reason::CustomReason(URI::new(string(reason)?)),
+    };
+    let message = match kwargs.get("message") {
+        None => None,
+        message => Some(string(message)?.to_string()),
+    };
+    Ok((reason, message))
+}
+
+impl ConnectionHandler {
+    /// Answers a call of a meta procedure.  Returns `None` if the procedure is not a meta
+    /// procedure.
+    pub fn meta_call(&self, procedure: &str, args: &[Value], kwargs: &Dict) -> Option<Result<List, Reason>> {
+        if !PROCEDURES.contains(&procedure) {
+            return None;
+        }
+        let authrole = self.identity.as_ref().map_or("", |identity| identity.authrole.as_str());
+        if !may_call(self.realm.as_ref(), authrole, procedure) {
+            log::warn!("Connection {} is not granted {}", self.info_id, procedure);
+            return Some(Err(Reason::NotAuthorized));
+        }
+        let result = match procedure {
+            "wamp.session.count" => authroles(args.get(0)).map(|authroles| {
+                vec![Value::UnsignedInteger(self.sessions(authroles).len() as u64)]
+            }),
+            "wamp.session.list" => authroles(args.get(0)).map(|authroles| {
+                let ids = self.sessions(authroles)
+                    .into_iter()
+                    .map(|(id, _)| Value::UnsignedInteger(id))
+                    .collect();
+                vec![Value::List(ids)]
+            }),
+            "wamp.session.get" => session_id(args.get(0)).and_then(|id| {
+                let realm = self.realm.as_ref().ok_or(Reason::NoSuchSession)?;
+                self.sessions(None)
+                    .into_iter()
+                    .find(|(session, _)| *session == id)
+                    .map(|(_, details)| vec![Value::Dict(details.to_dict(id, &realm.name))])
+                    .ok_or(Reason::NoSuchSession)
+            }),
+            "wamp.session.kill" => session_id(args.get(0)).and_then(|id| {
+                if id == self.info_id {
+                    return Err(Reason::InvalidArgument);
+                }
+                if !self.sessions(None).iter().any(|(session, _)| *session == id) {
+                    return Err(Reason::NoSuchSession);
+                }
+                let (reason, message) = kill_reason(kwargs)?;
+                self.router.kill_session(id, reason, message);
+                Ok(Vec::new())
+            }),
+            "wamp.session.kill_by_authid" => string(args.get(0)).and_then(|authid| {
+                self.kill_sessions(|details| details.authid == authid, kwargs)
+            }),
+            "wamp.session.kill_by_authrole" => string(args.get(0)).and_then(|authrole| {
+                self.kill_sessions(|details| details.authrole == authrole, kwargs)
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// The sessions of the realm of this connection, optionally restricted to some authroles.
+    fn sessions(&self, authroles: Option<Vec<&str>>) -> Vec<(ID, SessionDetails)> {
+        let realm = match self.realm {
+            Some(ref realm) => &realm.name,
+            None => return Vec::new(),
+        };
+        let connections = self.router.connections();
+        let connections = connections.lock().unwrap();
+        connections.values()
+            .filter_map(|connection| {
+                let connection = connection.lock().unwrap();
+                match (&connection.realm, &connection.session) {
+                    (Some(r), Some(details)) if r == realm => Some((connection.id, details.clone())),
+                    _ => None,
+                }
+            })
+            .filter(|(_, details)| match authroles {
+                Some(ref authroles) => authroles.contains(&details.authrole.as_str()),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Kills the other sessions of the realm that match a predicate and returns their ids.
+    fn kill_sessions<F>(&self, predicate: F, kwargs: &Dict) -> Result<List, Reason>
+    where
+        F: Fn(&SessionDetails) -> bool,
+    {
+        let (reason, message) = kill_reason(kwargs)?;
+        let mut killed = Vec::new();
+        for (id, details) in self.sessions(None) {
+            if id != self.info_id && predicate(&details) {
+                self.router.kill_session(id, reason.clone(), message.clone());
+                killed.push(Value::UnsignedInteger(id));
+            }
+        }
+        Ok(vec![Value::List(killed)])
+    }
+
+    /// Publishes `wamp.session.on_join` once this session joined its realm.
+    pub fn publish_join(&self, details: &SessionDetails) {
+        if let Some(ref realm) = self.realm {
+            let details = details.to_dict(self.info_id, &realm.name);
+            self.publish_meta_event("wamp.session.on_join", vec![Value::Dict(details)]);
+        }
+    }
+
+    /// Publishes `wamp.session.on_leave` when this session leaves its realm.
+    pub fn publish_leave(&self) {
+        if let Some(ref identity) = self.identity {
+            self.publish_meta_event("wamp.session.on_leave", vec![
+                Value::UnsignedInteger(self.info_id),
+                Value::String(identity.authid.clone()),
+                Value::String(identity.authrole.clone()),
+            ]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{kill_reason, may_call, SessionDetails};
+    use crate::messages::{ClientRoles, Dict, Reason, URI, Value};
+    use crate::router::config::{RealmConfig, RouterConfig};
+
+    #[test]
+    fn details() {
+        let details = SessionDetails {
+            authid: "joe".to_string(),
+            authrole: "user".to_string(),
+            authmethod: "ticket".to_string(),
+            authprovider: "static".to_string(),
+            transport: "websocket".to_string(),
+            peer: Some("127.0.0.1:50000".to_string()),
+            agent: None,
+            roles: ClientRoles::new(),
+        };
+        let dict = details.to_dict(42, "default");
+        assert_eq!(dict.get("session"), Some(&Value::UnsignedInteger(42)));
+        assert_eq!(dict.get("authrole"), Some(&Value::String("user".to_string())));
+        assert!(!dict.contains_key("agent"));
+        match dict.get("transport") {
+            Some(Value::Dict(transport)) => {
+                assert_eq!(transport.get("type"), Some(&Value::String("websocket".to_string())));
+                assert_eq!(transport.get("peer"), Some(&Value::String("127.0.0.1:50000".to_string())));
+            }
+            transport => panic!("unexpected transport {:?}", transport),
+        }
+    }
+
+    #[test]
+    fn kill_permissions() {
+        let config: RouterConfig = serde_json::from_str(r#"{"realms":[{"name":"default","roles":{
+            "admin":{"permissions":[{"uri":"wamp.session.","match":"prefix","allow":["call"]}]},
+            "user":{"permissions":[{"uri":"wamp.session.count","allow":["call"]}]}
+        }}]}"#).unwrap();
+        let realm = config.realm("default");
+        assert!(may_call(realm, "admin", "wamp.session.kill_by_authid"));
+        assert!(!may_call(realm, "user", "wamp.session.kill"));
+        assert!(!may_call(realm, "anonymous", "wamp.session.kill"));
+        assert!(!may_call(Some(&RealmConfig::new("open")), "anonymous", "wamp.session.kill"));
+        assert!(!may_call(None, "admin", "wamp.session.kill"));
+
+        assert!(may_call(realm, "admin", "wamp.session.get"));
+        assert!(may_call(realm, "user", "wamp.session.count"));
+        assert!(!may_call(realm, "user", "wamp.session.get"));
+        assert!(!may_call(realm, "user", "wamp.session.list"));
+        assert!(!may_call(realm, "anonymous", "wamp.session.list"));
+        assert!(!may_call(Some(&RealmConfig::new("open")), "anonymous", "wamp.session.get"));
+        assert!(!may_call(Some(&RealmConfig::new("open")), "anonymous", "wamp.session.count"));
+    }
+
+    #[test]
+    fn reasons() {
+        assert_eq!(kill_reason(&Dict::new()), Ok((Reason::Killed, None)));
+        let mut kwargs = Dict::new();
+        kwargs.insert("reason".to_string(), Value::String("com.example.banned".to_string()));
+        kwargs.insert("message".to_string(), Value::String("Bye".to_string()));
+        assert_eq!(
+            kill_reason(&kwargs),
+            Ok((Reason::CustomReason(URI::new("com.example.banned")), Some("Bye".to_string())))
+        );
+        kwargs.insert("reason".to_string(), Value::UnsignedInteger(1));
+        assert_eq!(kill_reason(&kwargs), Err(Reason::InvalidArgument));
+    }
+}
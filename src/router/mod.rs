@@ -1,26 +1,60 @@
+mod auth;
+mod authorization;
+mod batching;
+mod config;
 mod handshake;
+mod ids;
+mod keepalive;
 mod messaging;
+mod meta;
+mod protocol;
 mod pubsub;
+mod rawsocket;
+mod resumption;
 mod machine;
-
-use crate::messages::{ErrorDetails, Message, Reason, URI};
-use rand::distributions::{Distribution, Uniform};
-use rand::thread_rng;
-use crate::router::pubsub::SubscriptionPatternNode;
-use crate::router::machine::send_message_json;
-use crate::router::machine::send_message_msgpack;
-use std::collections::HashMap;
+mod rpc;
+mod sender;
+mod serializer;
+#[cfg(feature = "ssl")]
+mod tls;
+
+use crate::messages::{ClientRoles, ErrorDetails, EventDetails, List, Message, Reason, URI, Value,
+                      WelcomeDetails};
+use crate::router::auth::{AuthIdentity, PendingAuthentication, TransportDetails};
+use crate::router::authorization::Authorizations;
+use crate::router::ids::IdGenerator;
+use crate::router::meta::SessionDetails;
+use crate::router::pubsub::{DurableSubscriptions, SubscriptionPatternNode};
+use crate::router::rpc::{PendingInvocation, Registration};
+use crate::router::sender::Sender;
+use crate::router::auth::DynamicAuthentication;
+use crate::router::machine::send_message_with;
+use crate::utils::now_millis;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::marker::Sync;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::env;
-use ws::{Result as WSResult, Sender, Builder, Settings};
+use ws::{CloseCode, Result as WSResult, Builder, Settings};
 use simple_raft_node::{RequestManager, RequestError, Node, Config, transports::TcpConnectionManager, storages::MemStorage};
 use regex::Regex;
 use crate::{ID, Error, ErrorType, ErrorKind, MatchingPolicy, WampResult};
 use serde::{Serialize, Deserialize};
 use std::net::ToSocketAddrs;
+#[cfg(feature = "ssl")]
+use openssl::ssl::SslAcceptor;
+
+pub use crate::router::auth::{Principal, TicketValidator};
+pub use crate::router::serializer::{Serializer, Serializers};
+pub use crate::router::config::{Action, AuthConfig, AuthenticatorConfig, AuthorizerConfig,
+                                CryptosignConfig, CryptosignPrincipal, DurableConfig, JwtConfig, JwtKey,
+                                Permission, RawSocketConfig, RealmConfig, ResumptionConfig, RoleConfig,
+                                RouterConfig, ScramConfig,
+                                ScramKdf, ScramPrincipal, TicketConfig, TicketPrincipal,
+                                TlsAuthConfig, TlsConfig, TlsPrincipal, UnixAuthConfig, UnixPrincipal,
+                                WampCraConfig, WampCraPrincipal};
 
 #[derive(Debug, Clone, Default)]
 struct SubscriptionManager {
@@ -28,41 +62,90 @@ struct SubscriptionManager {
     subscription_ids_to_uris: HashMap<u64, (String, bool)>,
 }
 
+#[derive(Debug, Clone, Default)]
+struct Realm {
+    subscription_manager: SubscriptionManager,
+    registrations: HashMap<String, Registration>,
+    sessions: HashSet<u64>,
+    durable: Arc<Mutex<DurableSubscriptions>>,
+}
+
 pub struct Router {
     node: Node<RouterInfo>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RouterCore {
-    subscription_manager: SubscriptionManager,
+    node: u64,
+    config: Arc<RouterConfig>,
+    realms: HashMap<String, Realm>,
     connections: Arc<Mutex<HashMap<u64, Arc<Mutex<ConnectionInfo>>>>>,
     senders: Arc<Mutex<HashMap<u64, Sender>>>,
+    invocations: Arc<Mutex<HashMap<ID, PendingInvocation>>>,
+    /// The milliseconds since the epoch until which each node is known to be alive.
+    leases: HashMap<u64, u64>,
 }
 
 #[derive(Debug, Clone, Default)]
 struct RouterInfo {
     request_manager: Option<RequestManager<RouterCore>>,
+    config: Arc<RouterConfig>,
+    ids: IdGenerator,
     senders: Arc<Mutex<HashMap<u64, Sender>>>,
+    invocations: Arc<Mutex<HashMap<ID, PendingInvocation>>>,
+    /// The messages for detached sessions that wait to be buffered in the replicated state.
+    unbuffered: Arc<Mutex<HashMap<u64, VecDeque<Message>>>>,
 }
 
 struct ConnectionHandler {
     info_id: u64,
+    generation: u64,
     router: RouterInfo,
-    subscribed_topics: Vec<(ID, ID)>,
-    subscriptions: Arc<Mutex<SubscriptionPatternNode<u64>>>,
+    subscriptions: Option<Arc<Mutex<SubscriptionPatternNode<u64>>>>,
+    durable: Option<Arc<Mutex<DurableSubscriptions>>>,
+    authentication: Option<(String, PendingAuthentication)>,
+    authenticator: Option<(String, ID, DynamicAuthentication)>,
+    agent: Option<String>,
+    roles: Option<ClientRoles>,
+    realm: Option<RealmConfig>,
+    identity: Option<AuthIdentity>,
+    authorizations: Authorizations,
+    calls: HashMap<ID, ID>,
+    transport: TransportDetails,
+    /// Whether the peer did not send a frame since the last ping.
+    awaiting_pong: bool,
+    /// When the session sent its last message.
+    last_message: Instant,
+    #[cfg(feature = "ssl")]
+    tls: Option<Arc<SslAcceptor>>,
 }
 
 #[derive(Debug)]
 pub struct ConnectionInfo {
     state: ConnectionState,
     protocol: String,
+    realm: Option<String>,
+    session: Option<SessionDetails>,
     id: u64,
+    /// The node the session is connected to.
+    node: u64,
+    /// Counts how often the session was resumed.  Handlers of earlier connections of the
+    /// session ignore it.
+    generation: u64,
+    /// The hash of the token that resumes the session.
+    resume_token: Option<String>,
+    /// The milliseconds since the epoch until a detached session can be resumed.
+    detached_until: Option<u64>,
+    /// The messages for a detached session.
+    buffer: VecDeque<Message>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ConnectionState {
     Initializing,
     Connected,
+    /// The session lost its connection and waits to be resumed.
+    Detached,
     ShuttingDown,
     Disconnected,
 }
@@ -71,13 +154,10 @@ unsafe impl Sync for Router {}
 
 static WAMP_JSON: &'static str = "wamp.2.json";
 static WAMP_MSGPACK: &'static str = "wamp.2.msgpack";
-
-fn random_id() -> u64 {
-    let mut rng = thread_rng();
-    // TODO make this a constant
-    let between = Uniform::new(0, 1u64.rotate_left(56) - 1);
-    between.sample(&mut rng)
-}
+static WAMP_CBOR: &'static str = "wamp.2.cbor";
+static WAMP_UBJSON: &'static str = "wamp.2.ubjson";
+static WAMP_JSON_BATCHED: &'static str = "wamp.2.json.batched";
+static WAMP_MSGPACK_BATCHED: &'static str = "wamp.2.msgpack.batched";
 
 impl Default for Router {
     fn default() -> Self {
@@ -86,8 +166,22 @@ impl Default for Router {
 }
 
 impl Router {
+    /// Creates a new router node.  If the `WAMP_CONFIG` environment variable is set, the router
+    /// configuration is read from the file it points to, otherwise only the realm `default` is
+    /// served.
     #[inline]
     pub fn new() -> Router {
+        let config = match env::var("WAMP_CONFIG") {
+            Ok(path) => RouterConfig::from_file(&path).unwrap_or_else(|e| {
+                panic!("Could not read the router configuration {}: {}", path, e)
+            }),
+            Err(_) => RouterConfig::default(),
+        };
+        Router::with_config(config)
+    }
+
+    /// Creates a new router node serving the realms of the given configuration.
+    pub fn with_config(config: RouterConfig) -> Router {
         let node_id_msg = "Please specify a NODE_ID >= 0 via an environment variable!";
         let re = Regex::new(r"\d+").unwrap();
         let node_id_str = env::var("NODE_ID").expect(node_id_msg);
@@ -98,6 +192,9 @@ impl Router {
             .expect(node_id_msg);
 
         node_id += 1;
+        if node_id > ids::MAX_NODE {
+            panic!("Please specify a NODE_ID below {}!", ids::MAX_NODE);
+        }
 
         let node_address = env::var("NODE_ADDRESS").ok()
         .map(|address| {
@@ -133,25 +230,31 @@ impl Router {
                 }
             }).expect("The gateway address environment variable NODE_GATEWAY is not specified!");
 
-        let config = Config {
+        let node_config = Config {
             id: node_id,
             election_tick: 10,
             heartbeat_tick: 3,
             ..Default::default()
         };
-        let machine = RouterInfo::default();
+        let machine = RouterInfo {
+            config: Arc::new(config),
+            ids: IdGenerator::new(node_id),
+            ..Default::default()
+        };
         let storage = MemStorage::new();
         let mgr = TcpConnectionManager::new(node_address).unwrap();
         let node = Node::new(
-            config,
+            node_config,
             gateway,
             machine,
             storage,
             mgr,
         );
         let stop = node.stop_handler();
+        let machine = node.machine().clone();
 
         ctrlc::set_handler(move || {
+            close_sessions(&machine);
             stop();
 
             // wait until stop is finished
@@ -170,38 +273,121 @@ impl Router {
         A: ToSocketAddrs + std::fmt::Debug + Send + Sync + 'static
     {
         let router_info = self.node.machine().clone();
+        #[cfg(feature = "ssl")]
+        let tls = router_info.config.tls.as_ref().map(|config| {
+            Arc::new(tls::acceptor(config).expect("failed to set up TLS"))
+        });
+        #[cfg(not(feature = "ssl"))]
+        {
+            if router_info.config.tls.is_some() {
+                log::warn!("TLS is configured, but the router was built without the ssl feature");
+            }
+        }
         let ws = Builder::new().with_settings(Settings {
+            #[cfg(feature = "ssl")]
+            encrypt_server: tls.is_some(),
             ..Settings::default()
-        }).build(|sender| {
-            let id = random_id();
-            router_info.add_connection(id, sender);
+        }).build(|sender: ws::Sender| {
+            let transport = TransportDetails { kind: "websocket", ..TransportDetails::default() };
             ConnectionHandler {
-                info_id: id,
-                subscribed_topics: Vec::new(),
-                router: router_info.clone(),
-                subscriptions: router_info.subscriptions(),
+                #[cfg(feature = "ssl")]
+                tls: tls.clone(),
+                ..ConnectionHandler::new(router_info.clone(), sender.into(), transport)
             }
         }).expect("websocket to be built");
+        if let Some(ref config) = router_info.config.rawsocket {
+            if let Some(ref address) = config.address {
+                rawsocket::listen(router_info.clone(), address).expect("RawSocket listener to be bound");
+            }
+            if let Some(ref path) = config.path {
+                #[cfg(unix)]
+                rawsocket::listen_unix(router_info.clone(), path, config.permissions.as_ref().map(String::as_str))
+                    .expect("RawSocket listener to be bound");
+                #[cfg(not(unix))]
+                log::warn!("Unix domain sockets are not supported on this platform, not listening on {}", path);
+            }
+        }
+        if router_info.config.resumption.is_some() {
+            let router_info = router_info.clone();
+            thread::spawn(move || resumption::maintain(&router_info));
+        }
         ws.listen(url).unwrap();
     }
 
+    /// Lets the sessions of this node go and closes their connections.  Sessions are detached
+    /// if resumption is configured, so their clients can resume them on another node.
+    /// Otherwise, the connections are closed once the sessions answer the goodbye, or after
+    /// the close timeout.
     pub fn shutdown(&self) {
+        close_sessions(self.node.machine());
+    }
+
+    /// Says goodbye to a session, which may be connected to any node of the cluster, and closes
+    /// its connection once it answers, or after the close timeout.  Returns whether the session
+    /// exists.
+    pub fn kill_session(&self, session_id: ID, reason: Reason, message: Option<String>) -> bool {
         let info = self.node.machine();
-        let arc = info.connections();
-        let connections = arc.lock().unwrap();
-        for id in connections.keys() {
-            info.send_message(
-                *id,
-                Message::Goodbye(ErrorDetails::new(), Reason::SystemShutdown),
-            );
-            info.set_state(*id, ConnectionState::ShuttingDown);
+        if info.connection(session_id).is_err() {
+            return false;
+        }
+        info.kill_session(session_id, reason, message);
+        true
+    }
+}
+
+/// Detaches the sessions of this node if resumption is configured, otherwise says goodbye with
+/// `wamp.close.system_shutdown` to them.  Waits until their connections are closed, but no
+/// longer than the close timeout, since a node that lost the cluster cannot change the
+/// replicated state.
+fn close_sessions(info: &RouterInfo) {
+    let timeout = Duration::from_millis(info.config.close_timeout);
+    let deadline = Instant::now() + timeout;
+    let (done, released) = mpsc::channel();
+    let releasing = info.clone();
+    thread::spawn(move || {
+        release_sessions(&releasing);
+        done.send(()).ok();
+    });
+    if released.recv_timeout(timeout).is_ok() {
+        log::info!("Sessions released.  Waiting for their connections to close");
+        while !info.senders.lock().unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
         }
-        log::info!("Goodbye messages sent.  Waiting 5 seconds for response");
-        thread::sleep(Duration::from_secs(5));
-        for id in connections.keys() {
-            info.shutdown_sender(id);
+    } else {
+        log::warn!("The cluster did not release the sessions in time");
+    }
+    for (id, sender) in info.senders.lock().unwrap().iter() {
+        log::warn!("Connection {} is still open, shutting it down", id);
+        sender.shutdown().ok();
+    }
+}
+
+/// Detaches or says goodbye to every session of this node.
+fn release_sessions(info: &RouterInfo) {
+    let ids = info.senders.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+    for id in ids {
+        let connection = info.connection(id).map(|connection| {
+            let connection = connection.lock().unwrap();
+            (connection.state.clone(), connection.session.is_some(), connection.generation)
+        });
+        match (connection, &info.config.resumption) {
+            (Ok((ConnectionState::Connected, true, generation)), Some(resumption)) => {
+                let sender = info.senders.lock().unwrap().get(&id).cloned();
+                info.release_session(id, generation, Some(now_millis() + resumption.grace_period));
+                if let Some(sender) = sender {
+                    sender.close(CloseCode::Away).ok();
+                }
+            }
+            (Ok((ConnectionState::Connected, _, generation)), _) => info.release_session(id, generation, None),
+            (Ok((ConnectionState::ShuttingDown, _, _)), _) => {}
+            _ => {
+                if let Some(sender) = info.senders.lock().unwrap().get(&id) {
+                    sender.close(CloseCode::Away).ok();
+                }
+            }
         }
     }
+    info.buffer_messages();
 }
 
 impl RouterCore {
@@ -214,15 +400,12 @@ impl RouterCore {
         log::debug!("handling send_message");
         if let Some(sender) = self.senders.lock().unwrap().get(&connection_id) {
             log::debug!("Sending message {:?} via {}", message, protocol);
-            let send_result = if protocol == WAMP_JSON {
-                send_message_json(sender, &message)
-            } else {
-                send_message_msgpack(sender, &message)
-            };
-            log::debug!("sending succeeded");
-            match send_result {
-                Ok(()) => Ok(()),
-                Err(e) => Err(Error::new(ErrorKind::WSError(e))),
+            match self.config.serializers.get(&protocol) {
+                Some(serializer) => send_message_with(sender, serializer.as_ref(), &message),
+                None => {
+                    log::warn!("No serializer for {:?}, dropping message", protocol);
+                    Ok(())
+                }
             }
         } else {
             log::debug!("connection {} is not on this node, dropping message", connection_id);
@@ -237,9 +420,9 @@ impl RouterCore {
     }
 
     pub fn set_state(&self, connection_id: u64, state: ConnectionState) {
-        let connections = self.connections.lock().unwrap();
-        let connection = connections.get(&connection_id).unwrap();
-        connection.lock().unwrap().state = state;
+        if let Some(connection) = self.connections.lock().unwrap().get(&connection_id) {
+            connection.lock().unwrap().state = state;
+        }
     }
 
     pub fn set_protocol(&self, connection_id: u64, protocol: String) {
@@ -248,24 +431,276 @@ impl RouterCore {
         connection.lock().unwrap().protocol = protocol;
     }
 
-    pub fn add_connection(&mut self, connection_id: u64) {
+    pub fn add_connection(&mut self, connection_id: u64, node: u64) {
         self.connections.lock().unwrap().insert(connection_id, Arc::new(Mutex::new(ConnectionInfo {
             state: ConnectionState::Initializing,
             protocol: String::new(),
+            realm: None,
+            session: None,
             id: connection_id,
+            node,
+            generation: 0,
+            resume_token: None,
+            detached_until: None,
+            buffer: VecDeque::new(),
         })));
     }
 
     pub fn remove_connection(&mut self, connection_id: u64) {
+        let connection = self.connections.lock().unwrap().remove(&connection_id);
+        if let Some(connection) = connection {
+            if let Some(ref realm) = connection.lock().unwrap().realm {
+                if let Some(realm) = self.realms.get_mut(realm) {
+                    realm.sessions.remove(&connection_id);
+                    realm.registrations.retain(|_, registration| registration.callee != connection_id);
+                    let manager = &realm.subscription_manager;
+                    let mut subscriptions = manager.subscriptions.lock().unwrap();
+                    for (topic_uri, is_prefix) in manager.subscription_ids_to_uris.values() {
+                        subscriptions.unsubscribe_with(topic_uri, &connection_id, *is_prefix).ok();
+                    }
+                    pubsub::release(&mut realm.durable.lock().unwrap(), connection_id);
+                }
+            }
+        }
+        rpc::cancel_callee(&self.invocations, &self.senders, connection_id);
+    }
+
+    pub fn join_realm(
+        &mut self,
+        connection_id: u64,
+        realm: String,
+        session: SessionDetails,
+        resume_token: Option<String>,
+    ) {
+        if !self.realms.contains_key(&realm) {
+            if !self.config.allows_realm(&realm) {
+                log::warn!("Connection {} tried to join unknown realm {}", connection_id, realm);
+                return;
+            }
+            log::info!("Creating realm {}", realm);
+        }
+        if let Some(connection) = self.connections.lock().unwrap().get(&connection_id) {
+            let mut connection = connection.lock().unwrap();
+            connection.realm = Some(realm.clone());
+            connection.session = Some(session);
+            connection.resume_token = resume_token;
+        }
+        let sessions = &mut self.realms
+            .entry(realm.clone())
+            .or_insert_with(Realm::default)
+            .sessions;
+        sessions.insert(connection_id);
+        log::debug!("realm {} has {} sessions", realm, sessions.len());
+    }
+
+    /// Says goodbye to a session of this node.  The connection is closed once the session
+    /// answers, or after the close timeout.
+    pub fn kill_session(
+        &mut self,
+        connection_id: u64,
+        reason: Reason,
+        message: Option<String>,
+        publication_id: ID,
+    ) {
+        if self.state_of(connection_id) == Some(ConnectionState::Detached) {
+            self.expire_session(connection_id, publication_id);
+            return;
+        }
+        let details = match message {
+            Some(ref message) => ErrorDetails::new_with_message(message),
+            None => ErrorDetails::new(),
+        };
+        self.set_state(connection_id, ConnectionState::ShuttingDown);
+        if let Err(e) = self.send_to(connection_id, Message::Goodbye(details, reason)) {
+            log::warn!("Could not say goodbye to killed session {}: {}", connection_id, e);
+        }
+        if let Some(sender) = self.senders.lock().unwrap().get(&connection_id) {
+            log::info!("Waiting for killed session {} to say goodbye", connection_id);
+            if sender.timeout(self.config.close_timeout, handshake::CLOSE_TIMEOUT).is_err() {
+                sender.close(CloseCode::Normal).ok();
+            }
+        }
+    }
+
+    /// Keeps a session that lost its connection, unless the connection was already replaced
+    /// by a resumed one.  If the session is still connected to this node, for example because
+    /// the other nodes lost contact with it, the connection is closed, so the client resumes
+    /// the session elsewhere.
+    pub fn detach_session(&mut self, connection_id: u64, generation: u64, until: u64) {
+        if let Some(connection) = self.connections.lock().unwrap().get(&connection_id) {
+            let mut connection = connection.lock().unwrap();
+            if connection.generation == generation && connection.state == ConnectionState::Connected {
+                log::info!("Session {} lost its connection and waits to be resumed", connection_id);
+                connection.state = ConnectionState::Detached;
+                connection.detached_until = Some(until);
+            }
+        }
+        if self.state_of(connection_id) == Some(ConnectionState::Detached) {
+            if let Some(sender) = self.senders.lock().unwrap().remove(&connection_id) {
+                sender.close(CloseCode::Away).ok();
+            }
+        }
+    }
+
+    /// Keeps messages for a detached session.  If the session was resumed in the meantime,
+    /// the messages are sent instead.
+    pub fn buffer_messages(&mut self, connection_id: u64, messages: Vec<Message>) {
+        let max_buffered = match self.config.resumption {
+            Some(ref resumption) => resumption.max_buffered,
+            None => return,
+        };
+        let connection = match self.connections.lock().unwrap().get(&connection_id) {
+            Some(connection) => connection.clone(),
+            None => return,
+        };
+        let mut connection = connection.lock().unwrap();
+        match connection.state {
+            ConnectionState::Detached if max_buffered > 0 => {
+                connection.buffer.extend(messages);
+                let excess = connection.buffer.len().saturating_sub(max_buffered);
+                if excess > 0 {
+                    log::debug!("Dropping the {} oldest buffered messages of session {}", excess, connection_id);
+                    connection.buffer.drain(..excess);
+                }
+            }
+            ConnectionState::Connected => {
+                let protocol = connection.protocol.clone();
+                drop(connection);
+                for message in messages {
+                    self.send_message(connection_id, protocol.clone(), message).ok();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Notes that a node is alive until the given milliseconds since the epoch.
+    pub fn renew_lease(&mut self, node: u64, until: u64) {
+        self.leases.insert(node, until);
+    }
+
+    /// Moves a session to a new connection of the given node.  The node that hosts the new
+    /// connection welcomes it and sends the buffered messages, any other node closes the old
+    /// connection of the session.
+    pub fn resume_session(
+        &mut self,
+        connection_id: u64,
+        session_id: u64,
+        node: u64,
+        resume_token: String,
+        welcome: WelcomeDetails,
+    ) {
+        let protocol = match self.connections.lock().unwrap().get(&connection_id) {
+            Some(connection) => connection.lock().unwrap().protocol.clone(),
+            None => return,
+        };
+        let buffered = match self.connections.lock().unwrap().get(&session_id) {
+            Some(session) => {
+                let mut session = session.lock().unwrap();
+                match session.state {
+                    ConnectionState::Detached | ConnectionState::Connected => {}
+                    _ => return,
+                }
+                session.state = ConnectionState::Connected;
+                session.node = node;
+                session.protocol = protocol;
+                session.resume_token = Some(resume_token);
+                session.generation += 1;
+                session.detached_until = None;
+                session.buffer.drain(..).collect::<Vec<_>>()
+            }
+            None => return,
+        };
         self.connections.lock().unwrap().remove(&connection_id);
+        log::info!("Connection {} resumed session {}", connection_id, session_id);
+        if node != self.node {
+            if let Some(sender) = self.senders.lock().unwrap().remove(&session_id) {
+                sender.close(CloseCode::Away).ok();
+            }
+            return;
+        }
+        self.send_to(session_id, Message::Welcome(session_id, welcome)).ok();
+        for message in buffered {
+            self.send_to(session_id, message).ok();
+        }
+    }
+
+    /// Ends a session that is still detached and tells the subscribers of its realm.
+    pub fn expire_session(&mut self, connection_id: u64, publication_id: ID) {
+        let (realm, session) = match self.connections.lock().unwrap().get(&connection_id) {
+            Some(connection) => {
+                let connection = connection.lock().unwrap();
+                if connection.state != ConnectionState::Detached {
+                    return;
+                }
+                (connection.realm.clone(), connection.session.clone())
+            }
+            None => return,
+        };
+        log::info!("Session {} was not resumed in time", connection_id);
+        self.remove_connection(connection_id);
+        if let (Some(realm), Some(session)) = (realm, session) {
+            self.publish_local(&realm, "wamp.session.on_leave", publication_id, vec![
+                Value::UnsignedInteger(connection_id),
+                Value::String(session.authid),
+                Value::String(session.authrole),
+            ]);
+        }
+    }
+
+    fn state_of(&self, connection_id: u64) -> Option<ConnectionState> {
+        self.connections
+            .lock().unwrap()
+            .get(&connection_id)
+            .map(|connection| connection.lock().unwrap().state.clone())
+    }
+
+    /// Sends an event of the router to the subscribers of a realm that are connected to this
+    /// node.
+    fn publish_local(&self, realm: &str, topic: &str, publication_id: ID, args: List) {
+        let subscriptions = match self.realms.get(realm) {
+            Some(realm) => realm.subscription_manager.subscriptions.clone(),
+            None => return,
+        };
+        let topic = URI::new(topic);
+        let receivers = subscriptions.lock().unwrap()
+            .filter(topic.clone())
+            .map(|(subscriber_id, topic_id, policy)| (*subscriber_id, topic_id, policy))
+            .collect::<Vec<_>>();
+        for (subscriber_id, topic_id, policy) in receivers {
+            let details = if policy == MatchingPolicy::Strict {
+                EventDetails::new()
+            } else {
+                EventDetails::new_with_topic(topic.clone())
+            };
+            let event = Message::Event(topic_id, publication_id, details, Some(args.clone()), None);
+            self.send_to(subscriber_id, event).ok();
+        }
+    }
+
+    fn realm_of(&self, connection_id: &u64) -> Option<String> {
+        self.connections
+            .lock().unwrap()
+            .get(connection_id)
+            .and_then(|connection| connection.lock().unwrap().realm.clone())
     }
 
     pub fn remove_subscription(&mut self, connection_id: &u64, subscription_id: &u64, request_id: &u64) -> WampResult<()> {
+        let subscription_manager = match self.realm_of(connection_id)
+            .and_then(|realm| self.realms.get(&realm))
+        {
+            Some(realm) => &realm.subscription_manager,
+            None => return Err(Error::new(ErrorKind::ErrorReason(
+                ErrorType::Unsubscribe,
+                *request_id,
+                Reason::NoSuchSubscription,
+            ))),
+        };
         if let Some(&(ref topic_uri, is_prefix)) =
-            self.subscription_manager.subscription_ids_to_uris.get(subscription_id)
+            subscription_manager.subscription_ids_to_uris.get(subscription_id)
         {
             log::trace!("Removing subscription to {:?}", topic_uri);
-            self.subscription_manager
+            subscription_manager
                 .subscriptions
                 .lock().unwrap()
                 .unsubscribe_with(topic_uri, connection_id, is_prefix)
@@ -274,7 +709,7 @@ impl RouterCore {
                     *request_id,
                     e.reason(),
                 )))?;
-            log::trace!("Subscription tree: {:?}", self.subscription_manager.subscriptions);
+            log::trace!("Subscription tree: {:?}", subscription_manager.subscriptions);
         } else {
             return Err(Error::new(ErrorKind::ErrorReason(
                 ErrorType::Unsubscribe,
@@ -301,7 +736,7 @@ impl RouterCore {
         matching_policy: MatchingPolicy,
         id: ID,
         prefix_id: ID,
-    ) -> WampResult<()> {
+    ) -> WampResult<ID> {
         log::debug!(
             "machine is adding subscription ({}, {}, {:?}, {:?})",
             connection_id,
@@ -309,7 +744,17 @@ impl RouterCore {
             topic,
             matching_policy,
         );
-        let topic_id = match self.subscription_manager.subscriptions.lock().unwrap().subscribe_with(
+        let subscription_manager = match self.realm_of(&connection_id)
+            .and_then(|realm| self.realms.get_mut(&realm))
+        {
+            Some(realm) => &mut realm.subscription_manager,
+            None => return Err(Error::new(ErrorKind::ErrorReason(
+                ErrorType::Subscribe,
+                request_id,
+                Reason::NoSuchRealm,
+            ))),
+        };
+        let topic_id = match subscription_manager.subscriptions.lock().unwrap().subscribe_with(
             &topic,
             connection_id,
             matching_policy,
@@ -326,7 +771,7 @@ impl RouterCore {
             }
         };
         log::debug!("subscription for {} on {} got id {}", topic.uri, connection_id, topic_id);
-        self.subscription_manager.subscription_ids_to_uris.insert(
+        subscription_manager.subscription_ids_to_uris.insert(
             topic_id,
             (topic.uri, matching_policy == MatchingPolicy::Prefix),
         );
@@ -338,22 +783,146 @@ impl RouterCore {
             Message::Subscribed(request_id, topic_id),
         )?;
 
-        Ok(())
+        Ok(topic_id)
+    }
+}
+
+impl RouterCore {
+    /// Sends a message to a connection of this node.
+    fn send_to(&self, connection_id: u64, message: Message) -> WampResult<()> {
+        let protocol = match self.connections.lock().unwrap().get(&connection_id) {
+            Some(connection) => connection.lock().unwrap().protocol.clone(),
+            None => return Ok(()),
+        };
+        self.send_message(connection_id, protocol, message)
+    }
+
+    /// Sends the `ERROR` message of a failed request to a connection of this node.
+    pub fn send_error(&self, connection_id: u64, error: Error) {
+        if let ErrorKind::ErrorReason(err_type, request_id, reason) = error.get_kind() {
+            self.send_to(
+                connection_id,
+                Message::Error(err_type, request_id, HashMap::new(), reason, None, None),
+            ).ok();
+        }
+    }
+
+    pub fn registration(&self, realm: &str, procedure: &str) -> Option<Registration> {
+        self.realms.get(realm)
+            .and_then(|realm| realm.registrations.get(procedure))
+            .cloned()
+    }
+
+    pub fn add_registration(
+        &mut self,
+        connection_id: u64,
+        request_id: u64,
+        procedure: URI,
+        id: ID,
+    ) -> WampResult<()> {
+        let registrations = match self.realm_of(&connection_id)
+            .and_then(|realm| self.realms.get_mut(&realm))
+        {
+            Some(realm) => &mut realm.registrations,
+            None => return Err(Error::new(ErrorKind::ErrorReason(
+                ErrorType::Register,
+                request_id,
+                Reason::NoSuchRealm,
+            ))),
+        };
+        if registrations.contains_key(&procedure.uri) {
+            return Err(Error::new(ErrorKind::ErrorReason(
+                ErrorType::Register,
+                request_id,
+                Reason::ProcedureAlreadyExists,
+            )));
+        }
+        log::debug!("registration for {} on {} got id {}", procedure.uri, connection_id, id);
+        registrations.insert(procedure.uri, Registration { id, callee: connection_id });
+        self.send_to(connection_id, Message::Registered(request_id, id))
+    }
+
+    pub fn remove_registration(
+        &mut self,
+        connection_id: u64,
+        registration_id: u64,
+        request_id: u64,
+    ) -> WampResult<()> {
+        let registrations = match self.realm_of(&connection_id)
+            .and_then(|realm| self.realms.get_mut(&realm))
+        {
+            Some(realm) => &mut realm.registrations,
+            None => return Err(Error::new(ErrorKind::ErrorReason(
+                ErrorType::Unregister,
+                request_id,
+                Reason::NoSuchRegistration,
+            ))),
+        };
+        let procedure = registrations.iter()
+            .find(|(_, registration)| {
+                registration.id == registration_id && registration.callee == connection_id
+            })
+            .map(|(procedure, _)| procedure.clone());
+        match procedure {
+            Some(procedure) => {
+                log::trace!("Removing registration of {}", procedure);
+                registrations.remove(&procedure);
+                self.send_to(connection_id, Message::Unregistered(request_id))
+            }
+            None => Err(Error::new(ErrorKind::ErrorReason(
+                ErrorType::Unregister,
+                request_id,
+                Reason::NoSuchRegistration,
+            ))),
+        }
     }
 }
 
 impl ConnectionHandler {
+    /// Creates the handler of a new connection and registers its sender.
+    fn new(router: RouterInfo, sender: Sender, transport: TransportDetails) -> ConnectionHandler {
+        let id = router.ids.global();
+        router.add_connection(id, sender);
+        ConnectionHandler {
+            info_id: id,
+            generation: 0,
+            router,
+            subscriptions: None,
+            durable: None,
+            authentication: None,
+            authenticator: None,
+            agent: None,
+            roles: None,
+            realm: None,
+            identity: None,
+            authorizations: Authorizations::default(),
+            calls: HashMap::new(),
+            transport,
+            awaiting_pong: false,
+            last_message: Instant::now(),
+            #[cfg(feature = "ssl")]
+            tls: None,
+        }
+    }
+
     fn remove(&self) {
-        log::trace!(
-            "Removing subscriptions for client {}",
-            self.info_id,
-        );
-        for (topic_id, request_id) in &self.subscribed_topics {
-            log::trace!("Looking for subscription {}", topic_id);
+        log::trace!("Removing client {}", self.info_id);
+        self.cancel_invocations();
+        self.publish_leave();
+        self.router.remove_connection(self.info_id);
+    }
 
-            self.router.remove_subscription(self.info_id, *topic_id, *request_id);
+    /// Forgets the invocations this connection waits for, so their late results are dropped.
+    fn cancel_invocations(&self) {
+        if let Some((_, invocation_id, _)) = self.authenticator {
+            self.router.cancel_invocation(invocation_id);
+        }
+        if let Some(invocation_id) = self.authorizations.pending_invocation() {
+            self.router.cancel_invocation(invocation_id);
+        }
+        for invocation_id in self.calls.keys() {
+            self.router.cancel_invocation(*invocation_id);
         }
-        self.router.remove_connection(self.info_id);
     }
 
     fn terminate_connection(&self) -> WSResult<()> {
@@ -361,6 +930,14 @@ impl ConnectionHandler {
         Ok(())
     }
 
+    /// Closes the websocket of this connection and removes the connection from the router.
+    fn close_connection(&self, code: CloseCode) {
+        if let Some(sender) = self.router.senders.lock().unwrap().get(&self.info_id) {
+            sender.close(code).ok();
+        }
+        self.remove();
+    }
+
     fn info(&self) -> Result<Arc<Mutex<ConnectionInfo>>, RequestError> {
         self.router.connection(self.info_id)
     }
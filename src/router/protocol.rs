@@ -0,0 +1,198 @@
+//! Enforces the WAMP session state machine.
+//!
+//! A session starts with `HELLO`, may answer challenges with `AUTHENTICATE` and is established
+//! with `WELCOME`.  Messages that do not fit the state of the session, and messages that only
+//! routers send, are protocol violations.  The router answers them with an `ABORT` and closes
+//! the connection.  So are request IDs outside of the range of WAMP IDs.
+use super::{ConnectionHandler, ConnectionState};
+
+use crate::messages::{ErrorType, Message};
+use crate::router::ids;
+use crate::ID;
+
+/// What the router does with a message of a session.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Handle,
+    /// The message is dropped, as the router already said goodbye to the session or another
+    /// connection resumed it.
+    Ignore,
+    Violation(String),
+}
+
+pub fn message_name(message: &Message) -> &'static str {
+    match *message {
+        Message::Hello(..) => "HELLO",
+        Message::Welcome(..) => "WELCOME",
+        Message::Abort(..) => "ABORT",
+        Message::Challenge(..) => "CHALLENGE",
+        Message::Authenticate(..) => "AUTHENTICATE",
+        Message::Goodbye(..) => "GOODBYE",
+        Message::Error(..) => "ERROR",
+        Message::Subscribe(..) => "SUBSCRIBE",
+        Message::Subscribed(..) => "SUBSCRIBED",
+        Message::Unsubscribe(..) => "UNSUBSCRIBE",
+        Message::Unsubscribed(..) => "UNSUBSCRIBED",
+        Message::Publish(..) => "PUBLISH",
+        Message::Published(..) => "PUBLISHED",
+        Message::Event(..) => "EVENT",
+        Message::Register(..) => "REGISTER",
+        Message::Registered(..) => "REGISTERED",
+        Message::Unregister(..) => "UNREGISTER",
+        Message::Unregistered(..) => "UNREGISTERED",
+        Message::Call(..) => "CALL",
+        Message::Invocation(..) => "INVOCATION",
+        Message::Yield(..) => "YIELD",
+        Message::Result(..) => "RESULT",
+    }
+}
+
+/// Whether only routers send the message.  Clients only send errors of invocations.
+fn is_router_message(message: &Message) -> bool {
+    match *message {
+        Message::Welcome(..)
+        | Message::Challenge(..)
+        | Message::Subscribed(..)
+        | Message::Unsubscribed(..)
+        | Message::Published(..)
+        | Message::Event(..)
+        | Message::Registered(..)
+        | Message::Unregistered(..)
+        | Message::Invocation(..)
+        | Message::Result(..) => true,
+        Message::Error(ref err_type, ..) => *err_type != ErrorType::Invocation,
+        _ => false,
+    }
+}
+
+/// The session scope ID of a request of a client.
+fn request_id(message: &Message) -> Option<ID> {
+    match *message {
+        Message::Subscribe(request_id, ..)
+        | Message::Unsubscribe(request_id, ..)
+        | Message::Publish(request_id, ..)
+        | Message::Register(request_id, ..)
+        | Message::Unregister(request_id, ..)
+        | Message::Call(request_id, ..) => Some(request_id),
+        _ => None,
+    }
+}
+
+/// Decides what to do with a message in a state of a session.  `greeted` tells whether the
+/// session sent its `HELLO`, and `challenged` whether the router waits for an `AUTHENTICATE`.
+pub fn check(state: &ConnectionState, greeted: bool, challenged: bool, message: &Message) -> Verdict {
+    let name = message_name(message);
+    if is_router_message(message) {
+        return Verdict::Violation(format!("Clients must not send {} messages", name));
+    }
+    if let Some(request_id) = request_id(message) {
+        if !ids::is_valid(request_id) {
+            return Verdict::Violation(format!("The request ID {} of a {} message is invalid", request_id, name));
+        }
+    }
+    let violation = match (state, message) {
+        (ConnectionState::Initializing, Message::Hello(..)) if !greeted => return Verdict::Handle,
+        (ConnectionState::Initializing, Message::Authenticate(..)) if challenged => return Verdict::Handle,
+        (ConnectionState::Initializing, Message::Abort(..)) => return Verdict::Handle,
+        (ConnectionState::Initializing, Message::Hello(..)) => "Received a second HELLO message".to_string(),
+        (ConnectionState::Initializing, Message::Authenticate(..)) => {
+            "Received an AUTHENTICATE message without a pending challenge".to_string()
+        }
+        (ConnectionState::Initializing, _) if !greeted => format!("Received a {} message before HELLO", name),
+        (ConnectionState::Initializing, _) => format!("Received a {} message before WELCOME", name),
+        (ConnectionState::Connected, Message::Hello(..))
+        | (ConnectionState::Connected, Message::Authenticate(..))
+        | (ConnectionState::Connected, Message::Abort(..)) => {
+            format!("Received a {} message after WELCOME", name)
+        }
+        (ConnectionState::Connected, _) => return Verdict::Handle,
+        (ConnectionState::ShuttingDown, Message::Goodbye(..)) => return Verdict::Handle,
+        (ConnectionState::ShuttingDown, _) => return Verdict::Ignore,
+        (ConnectionState::Detached, _) => format!("Received a {} message for a detached session", name),
+        (ConnectionState::Disconnected, _) => format!("Received a {} message after GOODBYE", name),
+    };
+    Verdict::Violation(violation)
+}
+
+impl ConnectionHandler {
+    /// Decides what to do with a message in the current state of this session.
+    pub fn verdict(&self, message: &Message) -> Verdict {
+        let (state, generation) = match self.info() {
+            Ok(info) => {
+                let info = info.lock().unwrap();
+                (info.state.clone(), info.generation)
+            }
+            Err(_) => (ConnectionState::Disconnected, self.generation),
+        };
+        if generation != self.generation {
+            return Verdict::Ignore;
+        }
+        check(&state, self.roles.is_some(), self.authentication.is_some(), message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, Verdict};
+    use crate::messages::{ClientRoles, Dict, ErrorDetails, ErrorType, HelloDetails, Message,
+                          PublishOptions, Reason, URI};
+    use crate::router::ConnectionState;
+
+    fn hello() -> Message {
+        Message::Hello(URI::new("default"), HelloDetails::new(ClientRoles::new()))
+    }
+
+    fn publish() -> Message {
+        Message::Publish(1, PublishOptions::new(false), URI::new("com.example.topic"), None, None)
+    }
+
+    fn is_violation(verdict: Verdict) -> bool {
+        match verdict {
+            Verdict::Violation(_) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let state = ConnectionState::Initializing;
+        assert_eq!(check(&state, false, false, &hello()), Verdict::Handle);
+        assert!(is_violation(check(&state, false, false, &publish())));
+        assert!(is_violation(check(&state, true, false, &hello())));
+        assert!(is_violation(check(&state, true, false, &publish())));
+        let authenticate = Message::Authenticate("signature".to_string(), Dict::new());
+        assert_eq!(check(&state, true, true, &authenticate), Verdict::Handle);
+        assert!(is_violation(check(&state, true, false, &authenticate)));
+        let abort = Message::Abort(ErrorDetails::new(), Reason::Cancelled);
+        assert_eq!(check(&state, true, true, &abort), Verdict::Handle);
+    }
+
+    #[test]
+    fn established() {
+        let state = ConnectionState::Connected;
+        assert_eq!(check(&state, true, false, &publish()), Verdict::Handle);
+        assert!(is_violation(check(&state, true, false, &hello())));
+        assert_eq!(
+            check(&state, true, false, &Message::Event(1, 2, Default::default(), None, None)),
+            Verdict::Violation("Clients must not send EVENT messages".to_string())
+        );
+        let error = |err_type| Message::Error(err_type, 1, Dict::new(), Reason::Cancelled, None, None);
+        assert_eq!(check(&state, true, false, &error(ErrorType::Invocation)), Verdict::Handle);
+        assert!(is_violation(check(&state, true, false, &error(ErrorType::Call))));
+        let publish = |request_id| {
+            Message::Publish(request_id, PublishOptions::new(false), URI::new("com.example.topic"), None, None)
+        };
+        assert!(is_violation(check(&state, true, false, &publish(0))));
+        assert!(is_violation(check(&state, true, false, &publish((1 << 53) + 1))));
+        assert_eq!(check(&state, true, false, &publish(1 << 53)), Verdict::Handle);
+    }
+
+    #[test]
+    fn closing() {
+        let goodbye = Message::Goodbye(ErrorDetails::new(), Reason::GoodbyeAndOut);
+        assert_eq!(check(&ConnectionState::ShuttingDown, true, false, &goodbye), Verdict::Handle);
+        assert_eq!(check(&ConnectionState::ShuttingDown, true, false, &publish()), Verdict::Ignore);
+        assert!(is_violation(check(&ConnectionState::Disconnected, true, false, &publish())));
+        assert!(is_violation(check(&ConnectionState::Detached, true, false, &publish())));
+    }
+}
@@ -0,0 +1,287 @@
+//! Implements durable subscriptions.
+//!
+//! A session subscribes durably by setting `durable` and a `name` in the options of its
+//! `SUBSCRIBE`.  The subscription belongs to the authid of the session.  While no session of
+//! the authid holds the subscription, the events that match it are queued in the replicated
+//! state, up to the configured size and age limits.  The next session of the authid that
+//! subscribes with the same name receives the queued events in order right after `SUBSCRIBED`.
+//! Unsubscribing ends the durable subscription.
+use crate::messages::{EventDetails, Message};
+use crate::router::config::DurableConfig;
+use crate::router::{ConnectionHandler, RouterCore};
+use crate::utils::now_millis;
+use crate::{Dict, List, MatchingPolicy, ID, URI};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// The durable subscriptions of a realm by authid and name.
+pub type DurableSubscriptions = HashMap<(String, String), DurableSubscription>;
+
+/// An event that waits for the owner of a durable subscription.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedEvent {
+    pub publication_id: ID,
+    pub details: EventDetails,
+    pub args: Option<List>,
+    pub kwargs: Option<Dict>,
+    /// The milliseconds since the epoch when the event was published.
+    pub published: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DurableSubscription {
+    topic: String,
+    policy: MatchingPolicy,
+    /// The session that holds the subscription, if any.
+    subscriber: Option<u64>,
+    subscription_id: ID,
+    queue: VecDeque<QueuedEvent>,
+}
+
+impl DurableSubscription {
+    fn new(topic: String, policy: MatchingPolicy) -> DurableSubscription {
+        DurableSubscription {
+            topic,
+            policy,
+            subscriber: None,
+            subscription_id: 0,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Queues an event and drops the events beyond the limits.
+    fn push(&mut self, event: QueuedEvent, limits: &DurableConfig) {
+        let oldest = event.published.saturating_sub(limits.max_age * 1000);
+        self.queue.push_back(event);
+        while self.queue.len() > limits.max_events
+            || self.queue.front().map_or(false, |event| event.published < oldest)
+        {
+            self.queue.pop_front();
+        }
+    }
+}
+
+/// Hands the durable subscription of an authid to a session and takes its queued events.
+/// Subscribing to another topic starts over with an empty queue.
+fn attach(
+    durable: &mut DurableSubscriptions,
+    authid: String,
+    name: String,
+    topic: &URI,
+    policy: MatchingPolicy,
+    connection_id: u64,
+    subscription_id: ID,
+) -> Vec<QueuedEvent> {
+    let subscription = durable
+        .entry((authid, name))
+        .or_insert_with(|| DurableSubscription::new(topic.uri.clone(), policy));
+    if subscription.topic != topic.uri || subscription.policy != policy {
+        log::debug!("Durable subscription of session {} changed its topic", connection_id);
+        *subscription = DurableSubscription::new(topic.uri.clone(), policy);
+    }
+    subscription.subscriber = Some(connection_id);
+    subscription.subscription_id = subscription_id;
+    subscription.queue.drain(..).collect()
+}
+
+/// Queues an event for the durable subscriptions whose owners are offline.
+fn queue(durable: &mut DurableSubscriptions, topic: &URI, event: QueuedEvent, limits: &DurableConfig) {
+    for subscription in durable.values_mut() {
+        if subscription.subscriber.is_none() && subscription.policy.matches(&subscription.topic, &topic.uri) {
+            let mut event = event.clone();
+            if subscription.policy != MatchingPolicy::Strict {
+                event.details.topic = Some(topic.clone());
+            }
+            subscription.push(event, limits);
+        }
+    }
+}
+
+/// Marks the durable subscriptions of a session that left as offline.
+pub fn release(durable: &mut DurableSubscriptions, connection_id: u64) {
+    for subscription in durable.values_mut() {
+        if subscription.subscriber == Some(connection_id) {
+            subscription.subscriber = None;
+        }
+    }
+}
+
+impl RouterCore {
+    /// Hands the durable subscription of the authid of a session to the session and sends it
+    /// the queued events.
+    pub fn attach_durable(
+        &self,
+        connection_id: u64,
+        name: String,
+        topic: &URI,
+        policy: MatchingPolicy,
+        subscription_id: ID,
+    ) {
+        let (realm, authid, identifies_publishers) = match self.connections.lock().unwrap().get(&connection_id) {
+            Some(connection) => {
+                let connection = connection.lock().unwrap();
+                match (&connection.realm, &connection.session) {
+                    (Some(realm), Some(session)) => (
+                        realm.clone(),
+                        session.authid.clone(),
+                        session.roles.publisher_identification(),
+                    ),
+                    _ => return,
+                }
+            }
+            None => return,
+        };
+        let durable = match self.realms.get(&realm) {
+            Some(realm) => realm.durable.clone(),
+            None => return,
+        };
+        let events = attach(
+            &mut durable.lock().unwrap(),
+            authid,
+            name,
+            topic,
+            policy,
+            connection_id,
+            subscription_id,
+        );
+        let oldest = now_millis().saturating_sub(self.config.durable_subscriptions.max_age * 1000);
+        log::debug!("Replaying {} queued events to session {}", events.len(), connection_id);
+        for event in events.into_iter().filter(|event| event.published >= oldest) {
+            let mut details = event.details;
+            if !identifies_publishers {
+                details.publisher = None;
+                details.publisher_authid = None;
+                details.publisher_authrole = None;
+            }
+            let message = Message::Event(subscription_id, event.publication_id, details, event.args, event.kwargs);
+            self.send_to(connection_id, message).ok();
+        }
+    }
+
+    /// Ends the durable subscription a session unsubscribed from.
+    pub fn remove_durable(&self, connection_id: u64, subscription_id: ID) {
+        if let Some(realm) = self.realm_of(&connection_id).and_then(|realm| self.realms.get(&realm)) {
+            realm.durable.lock().unwrap().retain(|_, subscription| {
+                subscription.subscriber != Some(connection_id) || subscription.subscription_id != subscription_id
+            });
+        }
+    }
+
+    /// Queues an event for the durable subscriptions of a realm whose owners are offline.
+    pub fn queue_event(&self, realm: &str, topic: &URI, event: QueuedEvent) {
+        let durable = match self.realms.get(realm) {
+            Some(realm) => realm.durable.clone(),
+            None => return,
+        };
+        queue(&mut durable.lock().unwrap(), topic, event, &self.config.durable_subscriptions);
+    }
+}
+
+impl ConnectionHandler {
+    /// Queues an event for the durable subscriptions of the realm of this connection whose
+    /// owners are offline.
+    pub fn queue_durable(
+        &self,
+        topic: &URI,
+        publication_id: ID,
+        details: &EventDetails,
+        args: &Option<List>,
+        kwargs: &Option<Dict>,
+    ) {
+        let (realm, durable) = match (&self.realm, &self.durable) {
+            (Some(realm), Some(durable)) => (realm.name.clone(), durable),
+            _ => return,
+        };
+        let offline = durable
+            .lock().unwrap()
+            .values()
+            .any(|subscription| {
+                subscription.subscriber.is_none() && subscription.policy.matches(&subscription.topic, &topic.uri)
+            });
+        if offline {
+            self.router.queue_event(realm, topic.clone(), QueuedEvent {
+                publication_id,
+                details: details.clone(),
+                args: args.clone(),
+                kwargs: kwargs.clone(),
+                published: now_millis(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attach, queue, release, DurableSubscription, DurableSubscriptions, QueuedEvent};
+    use crate::messages::EventDetails;
+    use crate::router::config::DurableConfig;
+    use crate::{MatchingPolicy, URI};
+
+    fn event(publication_id: u64, published: u64) -> QueuedEvent {
+        QueuedEvent {
+            publication_id,
+            details: EventDetails::new(),
+            args: None,
+            kwargs: None,
+            published,
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let limits = DurableConfig { max_events: 2, max_age: 10 };
+        let mut subscription = DurableSubscription::new("com.example.commands".to_string(), MatchingPolicy::Strict);
+        subscription.push(event(1, 1000), &limits);
+        subscription.push(event(2, 2000), &limits);
+        subscription.push(event(3, 3000), &limits);
+        let ids = subscription.queue.iter().map(|event| event.publication_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 3]);
+        subscription.push(event(4, 12500), &limits);
+        let ids = subscription.queue.iter().map(|event| event.publication_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 4]);
+        subscription.push(event(5, 30000), &limits);
+        assert_eq!(subscription.queue.len(), 1);
+    }
+
+    #[test]
+    fn queue_then_attach() {
+        let limits = DurableConfig { max_events: 10, max_age: 3600 };
+        let topic = URI::new("com.example.commands");
+        let mut durable = DurableSubscriptions::new();
+        let attach_to = |durable: &mut DurableSubscriptions, topic: &URI, connection_id| {
+            let name = "commands".to_string();
+            attach(durable, "device".to_string(), name, topic, MatchingPolicy::Strict, connection_id, 7)
+                .iter()
+                .map(|event| event.publication_id)
+                .collect::<Vec<_>>()
+        };
+        assert!(attach_to(&mut durable, &topic, 1).is_empty());
+
+        queue(&mut durable, &topic, event(1, 1000), &limits);
+        assert!(durable.values().all(|subscription| subscription.queue.is_empty()));
+
+        release(&mut durable, 1);
+        queue(&mut durable, &topic, event(2, 2000), &limits);
+        queue(&mut durable, &URI::new("com.example.other"), event(3, 3000), &limits);
+        queue(&mut durable, &topic, event(4, 4000), &limits);
+        assert_eq!(attach_to(&mut durable, &topic, 2), vec![2, 4]);
+        assert!(attach_to(&mut durable, &topic, 2).is_empty());
+
+        release(&mut durable, 2);
+        queue(&mut durable, &topic, event(5, 5000), &limits);
+        assert!(attach_to(&mut durable, &URI::new("com.example.other"), 3).is_empty());
+    }
+
+    #[test]
+    fn queue_for_patterns() {
+        let limits = DurableConfig { max_events: 10, max_age: 3600 };
+        let mut durable = DurableSubscriptions::new();
+        let prefix = URI::new("com.example");
+        attach(&mut durable, "device".to_string(), "all".to_string(), &prefix, MatchingPolicy::Prefix, 1, 7);
+        release(&mut durable, 1);
+        queue(&mut durable, &URI::new("com.example.commands"), event(1, 1000), &limits);
+        let events = attach(&mut durable, "device".to_string(), "all".to_string(), &prefix, MatchingPolicy::Prefix, 2, 8);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].details.topic, Some(URI::new("com.example.commands")));
+    }
+}
@@ -1,9 +1,11 @@
+mod durable;
 mod patterns;
-use super::{random_id, ConnectionHandler};
+use super::ConnectionHandler;
 
-use crate::messages::{EventDetails, Message, PublishOptions, SubscribeOptions, URI};
+use crate::messages::{EventDetails, Message, PublishOptions, Reason, SubscribeOptions, URI};
+pub use crate::router::pubsub::durable::{release, DurableSubscriptions, QueuedEvent};
 pub use crate::router::pubsub::patterns::SubscriptionPatternNode;
-use crate::{Dict, List, MatchingPolicy };
+use crate::{Dict, Error, ErrorKind, ErrorType, List, MatchingPolicy, WampResult, ID};
 
 impl ConnectionHandler {
     pub fn handle_subscribe(
@@ -11,26 +13,37 @@ impl ConnectionHandler {
         request_id: u64,
         options: SubscribeOptions,
         topic: URI,
-    ) {
+    ) -> WampResult<()> {
         log::debug!(
             "Responding to subscribe message (conn: {}, id: {}, topic: {})",
             self.info_id, request_id, topic.uri
         );
-        let topic_id = random_id();
-        self.subscribed_topics.push((topic_id, request_id));
+        let durable = match (options.durable, options.name) {
+            (true, Some(name)) => Some(name),
+            (true, None) => {
+                log::warn!("Connection {} subscribed durably without a name", self.info_id);
+                return Err(Error::new(ErrorKind::ErrorReason(
+                    ErrorType::Subscribe,
+                    request_id,
+                    Reason::InvalidArgument,
+                )));
+            }
+            (false, _) => None,
+        };
         self.router.add_subscription(
             self.info_id,
             request_id,
             topic.clone(),
             options.pattern_match,
-            topic_id,
-            random_id(),
+            self.router.ids.router(),
+            self.router.ids.router(),
+            durable,
         );
+        Ok(())
     }
 
     pub fn handle_unsubscribe(&mut self, request_id: u64, subscription_id: u64) {
         self.router.remove_subscription(self.info_id, subscription_id, request_id);
-        self.subscribed_topics.retain(|(tid, _rid)| *tid != subscription_id);
     }
 
     pub fn handle_publish(
@@ -40,46 +53,84 @@ impl ConnectionHandler {
         topic: URI,
         args: Option<List>,
         kwargs: Option<Dict>,
+        disclose: bool,
     ) {
         log::debug!(
             "Responding to publish message (id: {}, topic: {})",
             request_id, topic.uri
         );
-        let publication_id = random_id();
-        let mut event_message = Message::Event(
-            1,
-            publication_id,
-            EventDetails::new(),
-            args.clone(),
-            kwargs.clone(),
-        );
-        let my_id = self.info_id;
-        for (subscriber_id, topic_id, policy) in self.subscriptions.lock().unwrap()
-            .filter(topic.clone())
-        {
-            if *subscriber_id != my_id {
-                log::debug!("publishing to {} with id {}", subscriber_id, topic_id);
-                if let Message::Event(
-                    ref mut old_topic,
-                    ref _publish_id,
-                    ref mut details,
-                    ref _args,
-                    ref _kwargs,
-                ) = event_message {
-                    *old_topic = topic_id;
-                    details.topic = if policy == MatchingPolicy::Strict {
-                        None
-                    } else {
-                        Some(topic.clone())
-                    };
-                }
-
-                self.router.send_message(*subscriber_id, event_message.clone());
+        if self.subscriptions.is_none() {
+            log::warn!("Connection {} published before joining a realm", self.info_id);
+            return;
+        }
+        let mut details = EventDetails::new();
+        if disclose {
+            if let Some(ref identity) = self.identity {
+                details.publisher = Some(self.info_id);
+                details.publisher_authid = Some(identity.authid.clone());
+                details.publisher_authrole = Some(identity.authrole.clone());
             }
         }
+        let publication_id = self.router.ids.global();
+        self.send_event(&topic, publication_id, details, args, kwargs);
 
         if options.should_acknowledge() {
             self.send_message(Message::Published(request_id, publication_id));
         }
     }
+
+    /// Publishes an event of the router to the subscribers of the realm of this connection.
+    pub fn publish_meta_event(&self, topic: &str, args: List) {
+        let publication_id = self.router.ids.global();
+        self.send_event(&URI::new(topic), publication_id, EventDetails::new(), Some(args), None);
+    }
+
+    /// Sends an event to the subscribers of a topic other than this connection.
+    fn send_event(
+        &self,
+        topic: &URI,
+        publication_id: ID,
+        details: EventDetails,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+    ) {
+        let subscriptions = match self.subscriptions {
+            Some(ref subscriptions) => subscriptions,
+            None => return,
+        };
+        let receivers = subscriptions.lock().unwrap()
+            .filter(topic.clone())
+            .filter(|(subscriber_id, _, _)| **subscriber_id != self.info_id)
+            .map(|(subscriber_id, topic_id, policy)| (*subscriber_id, topic_id, policy))
+            .collect::<Vec<_>>();
+        for (subscriber_id, topic_id, policy) in receivers {
+            log::debug!("publishing to {} with id {}", subscriber_id, topic_id);
+            let mut details = details.clone();
+            details.topic = if policy == MatchingPolicy::Strict {
+                None
+            } else {
+                Some(topic.clone())
+            };
+            if details.publisher.is_some() && !self.identifies_publishers(subscriber_id) {
+                details.publisher = None;
+                details.publisher_authid = None;
+                details.publisher_authrole = None;
+            }
+            self.router.send_message(
+                subscriber_id,
+                Message::Event(topic_id, publication_id, details, args.clone(), kwargs.clone()),
+            );
+        }
+        self.queue_durable(topic, publication_id, &details, &args, &kwargs);
+    }
+
+    /// Whether a subscriber announced that it receives the identity of publishers.
+    fn identifies_publishers(&self, subscriber_id: u64) -> bool {
+        self.router.connection(subscriber_id)
+            .map(|connection| {
+                let connection = connection.lock().unwrap();
+                connection.session.as_ref().map_or(false, |session| session.roles.publisher_identification())
+            })
+            .unwrap_or(false)
+    }
 }
@@ -1,6 +1,7 @@
 //! Contains the `SubscriptionPatternNode` struct, which is used for constructing a trie corresponding
 //! to pattern based subscription
-use super::super::{random_id, ConnectionInfo};
+use super::super::ConnectionInfo;
+use crate::router::ids::random_id;
 use itertools::Itertools;
 use crate::messages::Reason;
 use std::collections::HashMap;
@@ -399,7 +400,7 @@ impl<'a, P: PatternData> Iterator for MatchIterator<'a, P> {
 #[cfg(test)]
 mod test {
     use super::{PatternData, SubscriptionPatternNode};
-    use crate::{MatchingPolicy, ID, URI, router::random_id};
+    use crate::{MatchingPolicy, ID, URI, router::ids::random_id};
 
     #[derive(Clone)]
     struct MockData {
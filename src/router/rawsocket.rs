@@ -0,0 +1,575 @@
+//! Implements the WAMP RawSocket transport.
+//!
+//! A client opens a RawSocket connection with a 4-byte handshake that names its serializer and
+//! the longest message it accepts.  The router answers with its own maximum length, or with an
+//! error.  Afterwards, every message is framed by a 4-byte header that holds the frame type
+//! (message, ping or pong) and the 24-bit length of the payload.
+//!
+//! The router listens on a TCP address, a Unix domain socket, or both.
+//!
+//! Each connection is served by a thread that feeds the frames, timers and closing of the
+//! connection to the same `ConnectionHandler` the WebSocket transport uses.  A second thread
+//! reads the frames and a third one fires the timers.
+use super::{ConnectionHandler, RouterInfo};
+
+use crate::router::auth::TransportDetails;
+use crate::router::sender::Sender;
+use crate::router::serializer::{Serializer, Serializers};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender as EventSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use ws::util::Token;
+use ws::{CloseCode, Error as WSError, ErrorKind as WSErrorKind, Handler, Message as WSMessage,
+         Result as WSResult};
+
+/// The first octet of every handshake.
+const MAGIC: u8 = 0x7F;
+
+const ERROR_SERIALIZER_UNSUPPORTED: u8 = 1;
+const ERROR_USE_OF_RESERVED_BITS: u8 = 3;
+
+const FRAME_MESSAGE: u8 = 0;
+const FRAME_PING: u8 = 1;
+const FRAME_PONG: u8 = 2;
+
+/// The longest payload a frame can carry.
+const MAX_LENGTH: u32 = (1 << 24) - 1;
+
+/// A stream a RawSocket connection runs on.
+pub trait Stream: Read + Write + Send + 'static {
+    fn try_clone_stream(&self) -> io::Result<Box<dyn Stream>>;
+    fn shutdown_stream(&self) -> io::Result<()>;
+    fn set_stream_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn try_clone_stream(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_stream_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)
+    }
+
+    fn shutdown_stream(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::Stream;
+    use crate::router::auth::PeerCredentials;
+    use std::io;
+    use std::net::Shutdown;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    impl Stream for UnixStream {
+        fn try_clone_stream(&self) -> io::Result<Box<dyn Stream>> {
+            Ok(Box::new(self.try_clone()?))
+        }
+
+        fn set_stream_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.set_read_timeout(timeout)
+        }
+
+        fn shutdown_stream(&self) -> io::Result<()> {
+            self.shutdown(Shutdown::Both)
+        }
+    }
+
+    /// Asks the kernel for the user and group of the process that connected to the socket.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn peer_credentials(stream: &UnixStream) -> Option<PeerCredentials> {
+        let mut credentials = libc::ucred { pid: 0, uid: 0, gid: 0 };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result == 0 {
+            Some(PeerCredentials { uid: credentials.uid, gid: credentials.gid })
+        } else {
+            log::warn!("Could not read the peer credentials: {}", io::Error::last_os_error());
+            None
+        }
+    }
+
+    /// Asks the kernel for the user and group of the process that connected to the socket.
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn peer_credentials(stream: &UnixStream) -> Option<PeerCredentials> {
+        let mut uid = 0;
+        let mut gid = 0;
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == 0 {
+            Some(PeerCredentials { uid, gid })
+        } else {
+            log::warn!("Could not read the peer credentials: {}", io::Error::last_os_error());
+            None
+        }
+    }
+}
+
+/// What happens to a connection.
+enum Event {
+    Message(Vec<u8>),
+    Pong,
+    Timeout(Token),
+    Close(CloseCode),
+}
+
+/// Sends frames to the peer of a RawSocket connection.
+#[derive(Clone)]
+pub struct RawSender {
+    stream: Arc<Mutex<Box<dyn Stream>>>,
+    events: EventSender<Event>,
+    /// The timers of the connection, which fire on the timer thread of the connection.
+    timers: EventSender<(Instant, Token)>,
+    /// The longest message the peer accepts.
+    max_length: u32,
+}
+
+impl fmt::Debug for RawSender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RawSender {{ max_length: {} }}", self.max_length)
+    }
+}
+
+impl RawSender {
+    pub fn send(&self, message: WSMessage) -> WSResult<()> {
+        let payload = message.into_data();
+        if payload.len() > self.max_length as usize {
+            log::warn!("Closing the connection instead of sending {} bytes the peer does not accept", payload.len());
+            self.events.send(Event::Close(CloseCode::Size)).ok();
+            return Ok(());
+        }
+        self.write_frame(FRAME_MESSAGE, &payload);
+        Ok(())
+    }
+
+    pub fn ping(&self, data: Vec<u8>) -> WSResult<()> {
+        self.write_frame(FRAME_PING, &data);
+        Ok(())
+    }
+
+    pub fn close(&self, code: CloseCode) -> WSResult<()> {
+        self.events.send(Event::Close(code)).ok();
+        Ok(())
+    }
+
+    pub fn timeout(&self, ms: u64, token: Token) -> WSResult<()> {
+        self.timers.send((Instant::now() + Duration::from_millis(ms), token)).ok();
+        Ok(())
+    }
+
+    /// Writes a frame.  The connection is closed if the peer is gone.
+    fn write_frame(&self, frame_type: u8, payload: &[u8]) {
+        let mut stream = self.stream.lock().unwrap();
+        let result = stream.write_all(&frame_header(frame_type, payload.len() as u32))
+            .and_then(|_| stream.write_all(payload))
+            .and_then(|_| stream.flush());
+        if let Err(e) = result {
+            log::debug!("Could not write to RawSocket connection: {}", e);
+            self.events.send(Event::Close(CloseCode::Abnormal)).ok();
+        }
+    }
+
+    fn shutdown(&self) {
+        self.stream.lock().unwrap().shutdown_stream().ok();
+    }
+}
+
+/// The exponent that announces a maximum message length, which is `2^(9 + exponent)`.  The
+/// announced length does not exceed the given one, unless it is shorter than 512 bytes.
+fn length_exponent(max_length: u32) -> u8 {
+    (0..=15u8).rev().find(|exponent| exponent_length(*exponent) <= max_length).unwrap_or(0)
+}
+
+fn exponent_length(exponent: u8) -> u32 {
+    (1u32 << (9 + exponent)).min(MAX_LENGTH)
+}
+
+/// Reads the handshake of a client.  Returns the serializer it asks for and the longest message
+/// it accepts, or the error to answer with.
+fn parse_handshake(serializers: &Serializers, request: [u8; 4]) -> Result<(Arc<dyn Serializer>, u32), u8> {
+    if request[2] != 0 || request[3] != 0 {
+        return Err(ERROR_USE_OF_RESERVED_BITS);
+    }
+    match serializers.by_rawsocket_id(request[1] & 0x0F) {
+        Some(serializer) => Ok((serializer, exponent_length(request[1] >> 4))),
+        None => Err(ERROR_SERIALIZER_UNSUPPORTED),
+    }
+}
+
+fn handshake_reply(serializer_id: u8, max_length: u32) -> [u8; 4] {
+    [MAGIC, length_exponent(max_length) << 4 | serializer_id, 0, 0]
+}
+
+fn handshake_error(error: u8) -> [u8; 4] {
+    [MAGIC, error << 4, 0, 0]
+}
+
+fn frame_header(frame_type: u8, length: u32) -> [u8; 4] {
+    [frame_type, (length >> 16) as u8, (length >> 8) as u8, length as u8]
+}
+
+/// Reads the type and payload length of a frame.  Frames with reserved bits are rejected.
+fn parse_frame_header(header: [u8; 4]) -> Option<(u8, u32)> {
+    if header[0] & 0xF8 != 0 || header[0] > FRAME_PONG {
+        return None;
+    }
+    let length = u32::from(header[1]) << 16 | u32::from(header[2]) << 8 | u32::from(header[3]);
+    Some((header[0], length))
+}
+
+/// Accepts RawSocket connections on a TCP address.
+pub fn listen(router: RouterInfo, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    log::info!("Listening for RawSocket connections on {}", address);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let transport = TransportDetails {
+                        kind: "rawsocket",
+                        peer: stream.peer_addr().ok().map(|addr| addr.to_string()),
+                        ..TransportDetails::default()
+                    };
+                    spawn_connection(router.clone(), Box::new(stream), transport);
+                }
+                Err(e) => log::warn!("Could not accept RawSocket connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Accepts RawSocket connections on a Unix domain socket.  An existing socket at the path is
+/// replaced, but any other file is left alone and fails the call.  The user and group of each
+/// peer are available to the `unix` authentication.
+#[cfg(unix)]
+pub fn listen_unix(router: RouterInfo, path: &str, permissions: Option<&str>) -> io::Result<()> {
+    use std::fs;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    let mode = match permissions {
+        Some(permissions) => Some(u32::from_str_radix(permissions, 8).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("invalid permissions {}", permissions))
+        })?),
+        None => None,
+    };
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is not a socket", path)));
+        }
+        Err(_) => {}
+    }
+    // the socket is created with the configured permissions, so it is never more accessible
+    let listener = match mode {
+        Some(mode) => {
+            let umask = unsafe { libc::umask(!mode as libc::mode_t & 0o777) };
+            let listener = UnixListener::bind(path);
+            unsafe { libc::umask(umask) };
+            listener?
+        }
+        None => UnixListener::bind(path)?,
+    };
+    log::info!("Listening for RawSocket connections on {}", path);
+    let peer = format!("unix:{}", path);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let transport = TransportDetails {
+                        kind: "rawsocket",
+                        peer: Some(peer.clone()),
+                        peer_credentials: unix::peer_credentials(&stream),
+                        ..TransportDetails::default()
+                    };
+                    spawn_connection(router.clone(), Box::new(stream), transport);
+                }
+                Err(e) => log::warn!("Could not accept RawSocket connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn spawn_connection(router: RouterInfo, stream: Box<dyn Stream>, transport: TransportDetails) {
+    thread::spawn(move || {
+        if let Err(e) = serve(router, stream, transport) {
+            log::warn!("RawSocket connection failed: {}", e);
+        }
+    });
+}
+
+/// Fires the timers of a connection until the connection is gone.
+fn run_timers(timers: Receiver<(Instant, Token)>, events: EventSender<Event>) {
+    let mut pending = Vec::<(Instant, Token)>::new();
+    loop {
+        let now = Instant::now();
+        let (due, waiting) = pending.into_iter().partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        pending = waiting;
+        for (_, token) in due {
+            if events.send(Event::Timeout(token)).is_err() {
+                return;
+            }
+        }
+        let timer = match pending.iter().map(|(deadline, _)| *deadline).min() {
+            Some(next) => match timers.recv_timeout(next - now) {
+                Ok(timer) => timer,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return,
+            },
+            None => match timers.recv() {
+                Ok(timer) => timer,
+                Err(_) => return,
+            },
+        };
+        pending.push(timer);
+    }
+}
+
+/// Serves a RawSocket connection until it is closed.
+pub fn serve(router: RouterInfo, mut stream: Box<dyn Stream>, transport: TransportDetails) -> io::Result<()> {
+    let mut request = [0; 4];
+    stream.set_stream_timeout(Some(Duration::from_millis(router.config.handshake_timeout.max(1))))?;
+    stream.read_exact(&mut request)?;
+    if request[0] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a RawSocket handshake"));
+    }
+    let max_length = router.config.rawsocket.as_ref().map_or(MAX_LENGTH, |config| config.max_message_size);
+    let (serializer, peer_max_length) = match parse_handshake(&router.config.serializers, request) {
+        Ok(negotiated) => negotiated,
+        Err(error) => {
+            log::warn!("Rejecting RawSocket handshake {:?}", request);
+            return stream.write_all(&handshake_error(error));
+        }
+    };
+    stream.write_all(&handshake_reply(request[1] & 0x0F, max_length))?;
+    stream.set_stream_timeout(None)?;
+
+    let (events, receiver) = mpsc::channel();
+    let (timers, timer_receiver) = mpsc::channel();
+    let timer_events = events.clone();
+    thread::spawn(move || run_timers(timer_receiver, timer_events));
+    let reader = stream.try_clone_stream()?;
+    let sender = RawSender {
+        stream: Arc::new(Mutex::new(stream)),
+        events: events.clone(),
+        timers,
+        max_length: peer_max_length,
+    };
+    let mut handler = ConnectionHandler::new(router.clone(), Sender::RawSocket(sender.clone()), transport);
+    router.set_protocol(handler.info_id, serializer.protocol().to_string());
+    let frames = sender.clone();
+    thread::spawn(move || read_frames(reader, frames, max_length.min(MAX_LENGTH)));
+    if let Err(e) = handler.start_timers() {
+        log::warn!("Could not start the timers of connection {}: {}", handler.info_id, e);
+    }
+
+    let binary = serializer.binary();
+    for event in receiver {
+        let result = match event {
+            Event::Message(payload) => {
+                handler.peer_alive();
+                let message = if binary {
+                    Ok(WSMessage::Binary(payload))
+                } else {
+                    String::from_utf8(payload).map(WSMessage::Text)
+                };
+                match message {
+                    Ok(message) => handler.on_message(message),
+                    Err(_) => Err(WSError::new(WSErrorKind::Protocol, "a JSON message is not valid UTF-8")),
+                }
+            }
+            Event::Pong => {
+                handler.peer_alive();
+                Ok(())
+            }
+            Event::Timeout(token) => handler.on_timeout(token),
+            Event::Close(code) => {
+                sender.shutdown();
+                handler.on_close(code, "");
+                break;
+            }
+        };
+        if let Err(e) = result {
+            log::warn!("Closing RawSocket connection {}: {}", handler.info_id, e);
+            sender.shutdown();
+            handler.on_close(CloseCode::Abnormal, "");
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the frames of a connection, answers pings and passes messages and pongs on.
+fn read_frames(mut stream: Box<dyn Stream>, sender: RawSender, max_length: u32) {
+    let code = loop {
+        let mut header = [0; 4];
+        if let Err(e) = stream.read_exact(&mut header) {
+            break if e.kind() == io::ErrorKind::UnexpectedEof {
+                CloseCode::Normal
+            } else {
+                CloseCode::Abnormal
+            };
+        }
+        let (frame_type, length) = match parse_frame_header(header) {
+            Some(frame) if frame.1 <= max_length => frame,
+            _ => {
+                log::warn!("Received an invalid or too long RawSocket frame {:?}", header);
+                break CloseCode::Protocol;
+            }
+        };
+        let mut payload = vec![0; length as usize];
+        if stream.read_exact(&mut payload).is_err() {
+            break CloseCode::Abnormal;
+        }
+        let event = match frame_type {
+            FRAME_MESSAGE => Event::Message(payload),
+            FRAME_PING => {
+                sender.write_frame(FRAME_PONG, &payload);
+                continue;
+            }
+            _ => Event::Pong,
+        };
+        if sender.events.send(event).is_err() {
+            return;
+        }
+    };
+    sender.events.send(Event::Close(code)).ok();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{frame_header, handshake_error, handshake_reply, length_exponent, parse_frame_header,
+                parse_handshake, MAX_LENGTH};
+    use crate::router::{Serializers, WAMP_CBOR, WAMP_JSON, WAMP_MSGPACK, WAMP_UBJSON};
+
+    #[test]
+    fn handshake() {
+        let serializers = Serializers::default();
+        let parse = |request| {
+            parse_handshake(&serializers, request)
+                .map(|(serializer, length)| (serializer.protocol().to_string(), length))
+        };
+        assert_eq!(parse([0x7F, 0xF1, 0, 0]), Ok((WAMP_JSON.to_string(), MAX_LENGTH)));
+        assert_eq!(parse([0x7F, 0x02, 0, 0]), Ok((WAMP_MSGPACK.to_string(), 512)));
+        assert_eq!(parse([0x7F, 0x33, 0, 0]), Ok((WAMP_CBOR.to_string(), 4096)));
+        assert_eq!(handshake_reply(3, 4096), [0x7F, 0x33, 0, 0]);
+        assert_eq!(parse([0x7F, 0x04, 0, 0]), Ok((WAMP_UBJSON.to_string(), 512)));
+        assert_eq!(parse([0x7F, 0x15, 0, 0]), Err(1));
+        assert_eq!(parse([0x7F, 0x11, 0, 1]), Err(3));
+        assert_eq!(handshake_reply(2, 1 << 20), [0x7F, 0xB2, 0, 0]);
+        assert_eq!(handshake_reply(1, MAX_LENGTH), [0x7F, 0xF1, 0, 0]);
+        assert_eq!(handshake_error(1), [0x7F, 0x10, 0, 0]);
+        assert_eq!(length_exponent(100), 0);
+        assert_eq!(length_exponent(4096), 3);
+        assert_eq!(length_exponent(5000), 3);
+        assert_eq!(length_exponent(8191), 3);
+        assert_eq!(length_exponent(MAX_LENGTH), 15);
+        assert_eq!(length_exponent(1 << 24), 15);
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(frame_header(0, 0x012345), [0, 0x01, 0x23, 0x45]);
+        assert_eq!(parse_frame_header([0, 0x01, 0x23, 0x45]), Some((0, 0x012345)));
+        assert_eq!(parse_frame_header([2, 0, 0, 0]), Some((2, 0)));
+        assert_eq!(parse_frame_header([3, 0, 0, 0]), None);
+        assert_eq!(parse_frame_header([0x10, 0, 0, 0]), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn killed_session() {
+        use super::{run_timers, Event, RawSender, FRAME_MESSAGE};
+        use crate::messages::{ErrorDetails, Message, Reason};
+        use crate::router::handshake::CLOSE_TIMEOUT;
+        use crate::router::sender::Sender;
+        use crate::router::{ConnectionState, RouterConfig, RouterCore};
+        use std::collections::HashMap;
+        use std::io::Read;
+        use std::os::unix::net::UnixStream;
+        use std::sync::{mpsc, Arc, Mutex};
+        use std::thread;
+        use std::time::Duration;
+
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        let (events, receiver) = mpsc::channel();
+        let (timers, timer_receiver) = mpsc::channel();
+        let timer_events = events.clone();
+        thread::spawn(move || run_timers(timer_receiver, timer_events));
+        let sender = RawSender {
+            stream: Arc::new(Mutex::new(Box::new(stream))),
+            events,
+            timers,
+            max_length: MAX_LENGTH,
+        };
+        let mut core = RouterCore {
+            node: 1,
+            config: Arc::new(RouterConfig { close_timeout: 50, ..RouterConfig::default() }),
+            realms: HashMap::new(),
+            connections: Default::default(),
+            senders: Default::default(),
+            invocations: Default::default(),
+            leases: HashMap::new(),
+        };
+        core.add_connection(1, 1);
+        core.set_protocol(1, WAMP_JSON.to_string());
+        core.set_state(1, ConnectionState::Connected);
+        core.senders.lock().unwrap().insert(1, Sender::RawSocket(sender));
+
+        core.kill_session(1, Reason::Killed, None, 2);
+        let mut header = [0; 4];
+        peer.read_exact(&mut header).unwrap();
+        let (frame_type, length) = parse_frame_header(header).unwrap();
+        assert_eq!(frame_type, FRAME_MESSAGE);
+        let mut payload = vec![0; length as usize];
+        peer.read_exact(&mut payload).unwrap();
+        let serializer = Serializers::default().get(WAMP_JSON).unwrap();
+        assert_eq!(serializer.deserialize(&payload).unwrap(), Message::Goodbye(ErrorDetails::new(), Reason::Killed));
+        assert_eq!(core.state_of(1), Some(ConnectionState::ShuttingDown));
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(Event::Timeout(token)) => assert_eq!(token, CLOSE_TIMEOUT),
+            _ => panic!("the close timeout did not fire"),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn oversized_message() {
+        use super::{Event, RawSender};
+        use std::os::unix::net::UnixStream;
+        use std::sync::{mpsc, Arc, Mutex};
+        use ws::{CloseCode, Message as WSMessage};
+
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        let (events, receiver) = mpsc::channel();
+        let (timers, _timer_receiver) = mpsc::channel();
+        let sender = RawSender {
+            stream: Arc::new(Mutex::new(Box::new(stream))),
+            events,
+            timers,
+            max_length: 4,
+        };
+        sender.send(WSMessage::Text("[3,{},\"wamp.close.killed\"]".to_string())).unwrap();
+        match receiver.try_recv() {
+            Ok(Event::Close(code)) => assert_eq!(code, CloseCode::Size),
+            _ => panic!("the connection was not closed"),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Lets sessions outlive their connection.
+//!
+//! If resumption is configured, the `WELCOME` of a session carries a `resume_token`.  When the
+//! connection of the session is lost, the session is detached: it keeps its ID, subscriptions
+//! and registrations, and the messages for it are buffered.  A client that connects to any node
+//! of the cluster within the grace period can present the session ID and the token as
+//! `resume_session` and `resume_token` in its `HELLO` to take the session over.  The router
+//! answers with a `WELCOME` that has `resumed` set and a new token, followed by the buffered
+//! messages.  Sessions that are not resumed in time leave their realm.
+//!
+//! Sessions are also detached when their node shuts down, and when their node stops renewing
+//! its lease in the replicated state, for example because it crashed.  Messages for detached
+//! sessions are collected on each node and buffered in the replicated state in batches.
+//!
+//! Only the SHA-256 hash of a token is replicated to the other nodes.
+use super::{ConnectionHandler, ConnectionState, RouterInfo};
+
+use crate::messages::{RouterRoles, WelcomeDetails};
+use crate::router::auth::AuthIdentity;
+use crate::utils::now_millis;
+use crate::ID;
+use rand::{thread_rng, Rng};
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest;
+use std::thread;
+use std::time::Duration;
+use ws::CloseCode;
+
+/// How often the messages for detached sessions are buffered, in milliseconds.
+const BUFFER_INTERVAL: u64 = 100;
+/// How often the lease of a node is renewed and sessions are detached or expired, in
+/// milliseconds.
+const LEASE_INTERVAL: u64 = 1000;
+/// How long a node is considered alive after it renewed its lease, in milliseconds.
+const LEASE_DURATION: u64 = 10 * LEASE_INTERVAL;
+
+/// Creates a new random resume token.
+pub fn new_token() -> String {
+    base64::encode_config(&thread_rng().gen::<[u8; 32]>(), base64::URL_SAFE_NO_PAD)
+}
+
+/// The hash of a token that is kept in the replicated state.
+pub fn token_hash(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+/// Whether a token matches the hash of the token that was issued.
+pub fn verify_token(token: &str, hash: &str) -> bool {
+    verify_slices_are_equal(token_hash(token).as_bytes(), hash.as_bytes()).is_ok()
+}
+
+/// Keeps the sessions of the cluster resumable while the node runs.
+pub fn maintain(router: &RouterInfo) {
+    let mut elapsed = 0;
+    loop {
+        thread::sleep(Duration::from_millis(BUFFER_INTERVAL));
+        router.buffer_messages();
+        elapsed += BUFFER_INTERVAL;
+        if elapsed >= LEASE_INTERVAL {
+            elapsed = 0;
+            router.renew_lease(now_millis() + LEASE_DURATION);
+            detach_orphans(router);
+            expire_sessions(router);
+        }
+    }
+}
+
+/// Detaches the sessions whose connection is gone without the node noticing: the sessions of
+/// nodes whose lease ran out, and the sessions this node had before it was restarted.
+pub fn detach_orphans(router: &RouterInfo) {
+    let grace_period = match router.config.resumption {
+        Some(ref resumption) => resumption.grace_period,
+        None => return,
+    };
+    let now = now_millis();
+    let leases = router.leases();
+    let orphans = router.connections()
+        .lock().unwrap()
+        .values()
+        .filter_map(|connection| {
+            let connection = connection.lock().unwrap();
+            if connection.state != ConnectionState::Connected || connection.session.is_none() {
+                return None;
+            }
+            let gone = if connection.node == router.ids.node() {
+                !router.senders.lock().unwrap().contains_key(&connection.id)
+            } else {
+                leases.get(&connection.node).map_or(false, |until| *until <= now)
+            };
+            if gone {
+                Some((connection.id, connection.generation))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    for (connection_id, generation) in orphans {
+        log::warn!("The node of session {} is gone, detaching the session", connection_id);
+        router.detach_session(connection_id, generation, now + grace_period);
+    }
+}
+
+/// Ends the detached sessions of the cluster whose grace period is over.
+pub fn expire_sessions(router: &RouterInfo) {
+    let now = now_millis();
+    let expired = router.connections()
+        .lock().unwrap()
+        .values()
+        .filter_map(|connection| {
+            let connection = connection.lock().unwrap();
+            match (&connection.state, connection.detached_until) {
+                (ConnectionState::Detached, Some(until)) if until <= now => Some(connection.id),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    for connection_id in expired {
+        router.expire_session(connection_id);
+    }
+}
+
+impl ConnectionHandler {
+    /// Keeps the session of this connection, which was lost, for the grace period.
+    pub fn detach(&self) {
+        let grace_period = match self.router.config.resumption {
+            Some(ref resumption) => resumption.grace_period,
+            None => return,
+        };
+        log::debug!("Detaching session {} for {} milliseconds", self.info_id, grace_period);
+        self.cancel_invocations();
+        self.router.detach_session(self.info_id, self.generation, now_millis() + grace_period);
+    }
+
+    /// Takes over a session of a realm if the token resumes it.  Returns whether the session
+    /// was resumed, otherwise the client is authenticated as a new session.
+    pub fn resume(&mut self, realm: &str, session_id: ID, token: &str) -> bool {
+        let session = match self.router.connection(session_id) {
+            Ok(info) => {
+                let info = info.lock().unwrap();
+                let valid = match info.resume_token {
+                    Some(ref hash) => verify_token(token, hash),
+                    None => false,
+                };
+                let resumable = match info.state {
+                    ConnectionState::Detached | ConnectionState::Connected => true,
+                    _ => false,
+                };
+                if !valid || !resumable || info.realm.as_ref().map(String::as_str) != Some(realm) {
+                    return false;
+                }
+                match info.session {
+                    Some(ref session) => session.clone(),
+                    None => return false,
+                }
+            }
+            Err(_) => return false,
+        };
+        let identity = AuthIdentity {
+            authid: session.authid,
+            authrole: session.authrole,
+            authmethod: session.authmethod,
+            authprovider: session.authprovider,
+            authextra: None,
+        };
+        let token = new_token();
+        let mut details = identity.welcome_details(WelcomeDetails::new(RouterRoles::new()));
+        details.resume_token = Some(token.clone());
+        details.resumed = true;
+
+        let connection_id = self.info_id;
+        {
+            let mut senders = self.router.senders.lock().unwrap();
+            let sender = match senders.remove(&connection_id) {
+                Some(sender) => sender,
+                None => return false,
+            };
+            if let Some(previous) = senders.insert(session_id, sender) {
+                log::debug!("Closing the previous connection of session {}", session_id);
+                previous.close(CloseCode::Away).ok();
+            }
+        }
+        self.router.resume_session(connection_id, session_id, token_hash(&token), details);
+        if self.router.connection(connection_id).is_ok() {
+            log::warn!("Connection {} could not resume session {}", connection_id, session_id);
+            let mut senders = self.router.senders.lock().unwrap();
+            if let Some(sender) = senders.remove(&session_id) {
+                senders.insert(connection_id, sender);
+            }
+            return false;
+        }
+        self.info_id = session_id;
+        self.generation = match self.router.connection(session_id) {
+            Ok(info) => info.lock().unwrap().generation,
+            Err(_) => self.generation,
+        };
+        self.realm = self.router.config.realm_or_default(realm);
+        self.identity = Some(identity);
+        self.subscriptions = Some(self.router.subscriptions(realm.to_string()));
+        self.durable = Some(self.router.durable_subscriptions(realm.to_string()));
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{new_token, token_hash, verify_token};
+
+    #[test]
+    fn tokens() {
+        let token = new_token();
+        assert_eq!(token.len(), 43);
+        assert_ne!(token, new_token());
+        let hash = token_hash(&token);
+        assert!(verify_token(&token, &hash));
+        assert!(!verify_token(&new_token(), &hash));
+        assert!(!verify_token(&token, &token));
+    }
+}
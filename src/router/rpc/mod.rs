@@ -0,0 +1,196 @@
+//! Contains the dealer, which routes invocations to the sessions that registered a procedure.
+//!
+//! Registrations are part of the replicated router state.  Invocations the router sends on
+//! behalf of a connection are tracked on the node of that connection.  The result is passed to
+//! the connection by waking its handler with the `INVOCATION_RESULT` timeout.  A `CALL` of a
+//! session is such an invocation, whose result the handler passes on as `RESULT` or `ERROR`.
+use super::ConnectionHandler;
+
+use crate::messages::{CallOptions, ErrorType, Message, Reason, RegisterOptions, ResultDetails, URI,
+                      YieldOptions};
+use crate::{Dict, Error, ErrorKind, List, MatchingPolicy, WampResult, ID};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use ws::util::Token;
+use crate::router::sender::Sender;
+
+/// Wakes a handler whose invocation has a result.
+pub const INVOCATION_RESULT: Token = Token(1);
+/// Wakes a handler whose invocation did not return in time.
+pub const INVOCATION_TIMEOUT: Token = Token(2);
+
+/// A procedure a session registered.
+#[derive(Debug, Clone, Copy)]
+pub struct Registration {
+    pub id: ID,
+    pub callee: u64,
+}
+
+/// The outcome of an invocation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InvocationResult {
+    Yield(Option<List>, Option<Dict>),
+    Error(Reason, Option<List>, Option<Dict>),
+}
+
+/// An invocation that was sent on behalf of a connection of this node.
+#[derive(Debug)]
+pub struct PendingInvocation {
+    pub caller: u64,
+    pub callee: u64,
+    pub result: Option<InvocationResult>,
+}
+
+/// Stores the result of an invocation if its caller is connected to this node and wakes the
+/// caller.  Returns whether the invocation was found.
+pub fn deliver_result(
+    invocations: &Mutex<HashMap<ID, PendingInvocation>>,
+    senders: &Mutex<HashMap<u64, Sender>>,
+    callee: u64,
+    invocation_id: ID,
+    result: InvocationResult,
+) -> bool {
+    let mut invocations = invocations.lock().unwrap();
+    let invocation = match invocations.get_mut(&invocation_id) {
+        Some(invocation) => invocation,
+        None => return false,
+    };
+    if invocation.callee != callee {
+        log::warn!("Connection {} answered invocation {} of another callee", callee, invocation_id);
+        return true;
+    }
+    invocation.result = Some(result);
+    if let Some(sender) = senders.lock().unwrap().get(&invocation.caller) {
+        sender.timeout(0, INVOCATION_RESULT).ok();
+    }
+    true
+}
+
+/// Fails the invocations of this node that wait for a callee that left.
+pub fn cancel_callee(
+    invocations: &Mutex<HashMap<ID, PendingInvocation>>,
+    senders: &Mutex<HashMap<u64, Sender>>,
+    callee: u64,
+) {
+    let pending = invocations.lock().unwrap()
+        .iter()
+        .filter(|(_, invocation)| invocation.callee == callee && invocation.result.is_none())
+        .map(|(invocation_id, _)| *invocation_id)
+        .collect::<Vec<_>>();
+    for invocation_id in pending {
+        let result = InvocationResult::Error(Reason::Cancelled, None, None);
+        deliver_result(invocations, senders, callee, invocation_id, result);
+    }
+}
+
+impl ConnectionHandler {
+    pub fn handle_register(&mut self, request_id: u64, options: RegisterOptions, procedure: URI) -> WampResult<()> {
+        log::debug!(
+            "Responding to register message (conn: {}, id: {}, procedure: {})",
+            self.info_id, request_id, procedure.uri
+        );
+        if options.pattern_match != MatchingPolicy::Strict {
+            return Err(Error::new(ErrorKind::ErrorReason(
+                ErrorType::Register,
+                request_id,
+                Reason::OptionNotAllowed,
+            )));
+        }
+        let registration_id = self.router.ids.router();
+        self.router.add_registration(self.info_id, request_id, procedure, registration_id);
+        Ok(())
+    }
+
+    pub fn handle_unregister(&mut self, request_id: u64, registration_id: u64) {
+        self.router.remove_registration(self.info_id, registration_id, request_id);
+    }
+
+    pub fn handle_call(
+        &mut self,
+        request_id: u64,
+        _options: CallOptions,
+        procedure: URI,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+    ) -> WampResult<()> {
+        log::debug!(
+            "Responding to call message (conn: {}, id: {}, procedure: {})",
+            self.info_id, request_id, procedure.uri
+        );
+        let error = |reason| Error::new(ErrorKind::ErrorReason(ErrorType::Call, request_id, reason));
+        let meta_result = self.meta_call(
+            &procedure.uri,
+            args.as_ref().map_or(&[][..], |args| &args[..]),
+            kwargs.as_ref().unwrap_or(&Dict::new()),
+        );
+        if let Some(result) = meta_result {
+            let args = result.map_err(error)?;
+            self.send_message(Message::Result(request_id, ResultDetails::new(), Some(args), None));
+            return Ok(());
+        }
+        let realm = match self.realm {
+            Some(ref realm) => realm.name.clone(),
+            None => return Err(error(Reason::NoSuchRealm)),
+        };
+        let registration = self.router.registration(realm, procedure.uri)
+            .ok_or_else(|| error(Reason::NoSuchProcedure))?;
+        let invocation_id = self.router.invoke(self.info_id, registration, args, kwargs);
+        self.calls.insert(invocation_id, request_id);
+        Ok(())
+    }
+
+    /// Answers the calls of this connection whose results arrived once the handler is woken up.
+    pub fn handle_call_results(&mut self) -> WampResult<()> {
+        let router = &self.router;
+        let results = self.calls.iter()
+            .filter_map(|(invocation_id, request_id)| {
+                router.take_invocation_result(*invocation_id)
+                    .map(|result| (*invocation_id, *request_id, result))
+            })
+            .collect::<Vec<_>>();
+        for (invocation_id, request_id, result) in results {
+            self.calls.remove(&invocation_id);
+            let message = match result {
+                InvocationResult::Yield(args, kwargs) => {
+                    Message::Result(request_id, ResultDetails::new(), args, kwargs)
+                }
+                InvocationResult::Error(reason, args, kwargs) => {
+                    Message::Error(ErrorType::Call, request_id, HashMap::new(), reason, args, kwargs)
+                }
+            };
+            self.send_message(message);
+        }
+        Ok(())
+    }
+
+    pub fn handle_yield(
+        &mut self,
+        invocation_id: ID,
+        _options: YieldOptions,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+    ) {
+        self.router.invocation_result(self.info_id, invocation_id, InvocationResult::Yield(args, kwargs));
+    }
+
+    pub fn handle_invocation_error(
+        &mut self,
+        invocation_id: ID,
+        reason: Reason,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+    ) {
+        self.router.invocation_result(
+            self.info_id,
+            invocation_id,
+            InvocationResult::Error(reason, args, kwargs),
+        );
+    }
+
+    /// Sends an invocation of the given registration on behalf of this connection.  The handler is
+    /// woken with `INVOCATION_RESULT` when the result arrives.
+    pub fn invoke(&self, registration: Registration, args: List) -> ID {
+        self.router.invoke(self.info_id, registration, Some(args), None)
+    }
+}
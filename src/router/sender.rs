@@ -0,0 +1,114 @@
+//! Sends to the peer of a connection, whichever transport it uses.
+use crate::router::batching::{Batch, FLUSH};
+use crate::router::rawsocket::RawSender;
+use crate::router::serializer::Serializer;
+use std::sync::{Arc, Mutex};
+use ws::util::Token;
+use ws::{CloseCode, Message as WSMessage, Result as WSResult};
+
+/// The sending half of a WebSocket or RawSocket connection.
+#[derive(Debug, Clone)]
+pub enum Sender {
+    WebSocket(ws::Sender),
+    /// A WebSocket connection with a batched sub-protocol, the batch of its serializer, and
+    /// the milliseconds its events wait for other events.
+    Batched(ws::Sender, Arc<Mutex<Batch>>, u64),
+    RawSocket(RawSender),
+}
+
+impl Sender {
+    pub fn send(&self, message: WSMessage) -> WSResult<()> {
+        match *self {
+            Sender::WebSocket(ref sender) => sender.send(message),
+            Sender::Batched(ref sender, ref batch, _) => {
+                let mut batch = batch.lock().unwrap();
+                batch.push(message);
+                match batch.take() {
+                    Some(frame) => sender.send(frame),
+                    None => Ok(()),
+                }
+            }
+            Sender::RawSocket(ref sender) => sender.send(message),
+        }
+    }
+
+    /// Sends an event, which may wait for other events on a batched connection.
+    pub fn send_event(&self, message: WSMessage) -> WSResult<()> {
+        match *self {
+            Sender::Batched(ref sender, ref batch, window) => {
+                let mut batch = batch.lock().unwrap();
+                let first = batch.push(message);
+                if batch.is_full() {
+                    match batch.take() {
+                        Some(frame) => sender.send(frame),
+                        None => Ok(()),
+                    }
+                } else if first {
+                    sender.timeout(window, FLUSH)
+                } else {
+                    Ok(())
+                }
+            }
+            _ => self.send(message),
+        }
+    }
+
+    /// Sends the events that wait on a batched connection.
+    pub fn flush(&self) -> WSResult<()> {
+        match *self {
+            Sender::Batched(ref sender, ref batch, _) => match batch.lock().unwrap().take() {
+                Some(frame) => sender.send(frame),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Turns the sender of a WebSocket connection into one for a batched sub-protocol, whose
+    /// serializer builds the frames.
+    pub fn batched(self, serializer: Arc<dyn Serializer>, window: u64) -> Sender {
+        match self {
+            Sender::WebSocket(sender) => {
+                Sender::Batched(sender, Arc::new(Mutex::new(Batch::new(serializer))), window)
+            }
+            sender => sender,
+        }
+    }
+
+    pub fn close(&self, code: CloseCode) -> WSResult<()> {
+        self.flush().ok();
+        match *self {
+            Sender::WebSocket(ref sender) | Sender::Batched(ref sender, _, _) => sender.close(code),
+            Sender::RawSocket(ref sender) => sender.close(code),
+        }
+    }
+
+    /// Wakes the handler of the connection with the given token after the given milliseconds.
+    pub fn timeout(&self, ms: u64, token: Token) -> WSResult<()> {
+        match *self {
+            Sender::WebSocket(ref sender) | Sender::Batched(ref sender, _, _) => sender.timeout(ms, token),
+            Sender::RawSocket(ref sender) => sender.timeout(ms, token),
+        }
+    }
+
+    pub fn ping(&self, data: Vec<u8>) -> WSResult<()> {
+        match *self {
+            Sender::WebSocket(ref sender) | Sender::Batched(ref sender, _, _) => sender.ping(data),
+            Sender::RawSocket(ref sender) => sender.ping(data),
+        }
+    }
+
+    /// Shuts down the WebSocket server, or closes a RawSocket connection.
+    pub fn shutdown(&self) -> WSResult<()> {
+        match *self {
+            Sender::WebSocket(ref sender) | Sender::Batched(ref sender, _, _) => sender.shutdown(),
+            Sender::RawSocket(ref sender) => sender.close(CloseCode::Away),
+        }
+    }
+}
+
+impl From<ws::Sender> for Sender {
+    fn from(sender: ws::Sender) -> Sender {
+        Sender::WebSocket(sender)
+    }
+}
@@ -0,0 +1,391 @@
+//! Contains the `Serializer` trait that encodes and decodes the messages of a WAMP
+//! sub-protocol, and the registry of the serializers a router offers.
+//!
+//! The router picks the serializer of a connection by the sub-protocol the client negotiates,
+//! or by the serializer ID of its RawSocket handshake.  Embedders of the router can add their
+//! own codecs with `RouterConfig::register_serializer`.
+use super::{WAMP_CBOR, WAMP_JSON, WAMP_JSON_BATCHED, WAMP_MSGPACK, WAMP_MSGPACK_BATCHED, WAMP_UBJSON};
+
+use crate::messages::{ubjson, with_bytes_encoding, BytesEncoding, Message};
+use crate::router::batching;
+use crate::utils::StructMapWriter;
+use crate::{Error, ErrorKind, WampResult};
+use rmp_serde::{Deserializer as RMPDeserializer, Serializer as RMPSerializer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Encodes and decodes the messages of a WAMP sub-protocol.
+pub trait Serializer: Send + Sync {
+    /// The name of the WebSocket sub-protocol, such as `wamp.2.json`.
+    fn protocol(&self) -> &str;
+
+    /// Whether messages are sent in binary frames.  Text serializers have to produce UTF-8.
+    fn binary(&self) -> bool;
+
+    /// The serializer ID RawSocket clients announce in their handshake, if they can use this
+    /// serializer.
+    fn rawsocket_id(&self) -> Option<u8> {
+        None
+    }
+
+    /// Whether several messages share a frame.  Batched serializers split frames with `split`
+    /// and build them with `join`, and the router puts events that are sent in quick
+    /// succession into one frame.
+    fn batched(&self) -> bool {
+        false
+    }
+
+    /// How `Value::Bytes` is encoded.  By default, binary serializers use their binary type and
+    /// text serializers base64 encoded strings.  Binary serializers without a binary type, such
+    /// as UBJSON, return `BytesEncoding::Base64`.
+    fn bytes_encoding(&self) -> BytesEncoding {
+        if self.binary() {
+            BytesEncoding::Binary
+        } else {
+            BytesEncoding::Base64
+        }
+    }
+
+    /// Encodes a message.  `Value::Bytes` are serialized with the `bytes_encoding` of the
+    /// serializer when the message is encoded through `encode`.
+    fn serialize(&self, message: &Message) -> WampResult<Vec<u8>>;
+
+    /// Encodes a message with the `bytes_encoding` of the serializer.  The router encodes every
+    /// outgoing message with this, so implementations only provide `serialize`.
+    fn encode(&self, message: &Message) -> WampResult<Vec<u8>> {
+        with_bytes_encoding(self.bytes_encoding(), || self.serialize(message))
+    }
+
+    fn deserialize(&self, payload: &[u8]) -> WampResult<Message>;
+
+    /// Splits a frame into the payloads of its messages.
+    fn split<'a>(&self, frame: &'a [u8]) -> WampResult<Vec<&'a [u8]>> {
+        Ok(vec![frame])
+    }
+
+    /// Puts the payloads of several messages into one frame.  By default, text payloads are
+    /// each terminated by `\x1e` like in `wamp.2.json.batched`, and binary payloads are each
+    /// prefixed by their length like in `wamp.2.msgpack.batched`.
+    fn join(&self, payloads: Vec<Vec<u8>>) -> Vec<u8> {
+        batching::join(self.binary(), payloads)
+    }
+}
+
+/// The serializers a router offers, by the name of their sub-protocol.
+#[derive(Clone)]
+pub struct Serializers {
+    serializers: HashMap<String, Arc<dyn Serializer>>,
+}
+
+impl Serializers {
+    /// Adds a serializer.  A serializer with the same sub-protocol is replaced.
+    pub fn register(&mut self, serializer: Arc<dyn Serializer>) {
+        self.serializers.insert(serializer.protocol().to_string(), serializer);
+    }
+
+    pub fn get(&self, protocol: &str) -> Option<Arc<dyn Serializer>> {
+        self.serializers.get(protocol).cloned()
+    }
+
+    /// Returns the serializer with the given RawSocket serializer ID.
+    pub fn by_rawsocket_id(&self, id: u8) -> Option<Arc<dyn Serializer>> {
+        self.serializers.values().find(|serializer| serializer.rawsocket_id() == Some(id)).cloned()
+    }
+
+    /// The names of the sub-protocols, in alphabetical order.
+    pub fn protocols(&self) -> Vec<&str> {
+        let mut protocols = self.serializers.keys().map(String::as_str).collect::<Vec<_>>();
+        protocols.sort();
+        protocols
+    }
+}
+
+impl Default for Serializers {
+    fn default() -> Serializers {
+        let mut serializers = Serializers { serializers: HashMap::new() };
+        serializers.register(Arc::new(Json { protocol: WAMP_JSON, batched: false }));
+        serializers.register(Arc::new(Json { protocol: WAMP_JSON_BATCHED, batched: true }));
+        serializers.register(Arc::new(MsgPack { protocol: WAMP_MSGPACK, batched: false }));
+        serializers.register(Arc::new(MsgPack { protocol: WAMP_MSGPACK_BATCHED, batched: true }));
+        serializers.register(Arc::new(Cbor));
+        serializers.register(Arc::new(Ubjson));
+        serializers
+    }
+}
+
+impl fmt::Debug for Serializers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.protocols()).finish()
+    }
+}
+
+struct Json {
+    protocol: &'static str,
+    batched: bool,
+}
+
+impl Serializer for Json {
+    fn protocol(&self) -> &str {
+        self.protocol
+    }
+
+    fn binary(&self) -> bool {
+        false
+    }
+
+    fn rawsocket_id(&self) -> Option<u8> {
+        if self.batched { None } else { Some(1) }
+    }
+
+    fn batched(&self) -> bool {
+        self.batched
+    }
+
+    fn serialize(&self, message: &Message) -> WampResult<Vec<u8>> {
+        serde_json::to_vec(message).map_err(|e| Error::new(ErrorKind::JSONError(e)))
+    }
+
+    fn deserialize(&self, payload: &[u8]) -> WampResult<Message> {
+        serde_json::from_slice(payload).map_err(|e| Error::new(ErrorKind::JSONError(e)))
+    }
+
+    fn split<'a>(&self, frame: &'a [u8]) -> WampResult<Vec<&'a [u8]>> {
+        if self.batched {
+            Ok(batching::split_json(frame))
+        } else {
+            Ok(vec![frame])
+        }
+    }
+}
+
+struct MsgPack {
+    protocol: &'static str,
+    batched: bool,
+}
+
+impl Serializer for MsgPack {
+    fn protocol(&self) -> &str {
+        self.protocol
+    }
+
+    fn binary(&self) -> bool {
+        true
+    }
+
+    fn rawsocket_id(&self) -> Option<u8> {
+        if self.batched { None } else { Some(2) }
+    }
+
+    fn batched(&self) -> bool {
+        self.batched
+    }
+
+    fn serialize(&self, message: &Message) -> WampResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        match message.serialize(&mut RMPSerializer::with(&mut buf, StructMapWriter)) {
+            Ok(()) => Ok(buf),
+            Err(e) => {
+                log::error!("Could not encode {:?} as MsgPack: {}", message, e);
+                Err(Error::new(ErrorKind::MalformedData))
+            }
+        }
+    }
+
+    fn deserialize(&self, payload: &[u8]) -> WampResult<Message> {
+        let mut de = RMPDeserializer::new(payload);
+        Deserialize::deserialize(&mut de).map_err(|e| Error::new(ErrorKind::MsgPackError(e)))
+    }
+
+    fn split<'a>(&self, frame: &'a [u8]) -> WampResult<Vec<&'a [u8]>> {
+        if self.batched {
+            batching::split_msgpack(frame)
+        } else {
+            Ok(vec![frame])
+        }
+    }
+}
+
+struct Cbor;
+
+impl Serializer for Cbor {
+    fn protocol(&self) -> &str {
+        WAMP_CBOR
+    }
+
+    fn binary(&self) -> bool {
+        true
+    }
+
+    fn rawsocket_id(&self) -> Option<u8> {
+        Some(3)
+    }
+
+    fn serialize(&self, message: &Message) -> WampResult<Vec<u8>> {
+        serde_cbor::to_vec(message).map_err(|e| Error::new(ErrorKind::CBORError(e)))
+    }
+
+    fn deserialize(&self, payload: &[u8]) -> WampResult<Message> {
+        serde_cbor::from_slice(payload).map_err(|e| Error::new(ErrorKind::CBORError(e)))
+    }
+}
+
+struct Ubjson;
+
+impl Serializer for Ubjson {
+    fn protocol(&self) -> &str {
+        WAMP_UBJSON
+    }
+
+    fn binary(&self) -> bool {
+        true
+    }
+
+    fn rawsocket_id(&self) -> Option<u8> {
+        Some(4)
+    }
+
+    fn bytes_encoding(&self) -> BytesEncoding {
+        BytesEncoding::Base64
+    }
+
+    fn serialize(&self, message: &Message) -> WampResult<Vec<u8>> {
+        ubjson::to_vec(message).map_err(|e| Error::new(ErrorKind::UBJSONError(e)))
+    }
+
+    fn deserialize(&self, payload: &[u8]) -> WampResult<Message> {
+        ubjson::from_slice(payload).map_err(|e| Error::new(ErrorKind::UBJSONError(e)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Serializer, Serializers};
+    use crate::messages::{Message, PublishOptions, Value, URI};
+    use crate::{Error, ErrorKind, WampResult, ID};
+    use std::sync::Arc;
+
+    /// Encodes messages as the decimal ID of their request, which is enough for `SUBSCRIBED`.
+    struct Ids;
+
+    impl Serializer for Ids {
+        fn protocol(&self) -> &str {
+            "wamp.2.ids"
+        }
+
+        fn binary(&self) -> bool {
+            false
+        }
+
+        fn serialize(&self, message: &Message) -> WampResult<Vec<u8>> {
+            match *message {
+                Message::Subscribed(request_id, _) => Ok(request_id.to_string().into_bytes()),
+                _ => Ok(Vec::new()),
+            }
+        }
+
+        fn deserialize(&self, payload: &[u8]) -> WampResult<Message> {
+            let id = String::from_utf8_lossy(payload).parse::<ID>().unwrap_or_default();
+            Ok(Message::Subscribed(id, 0))
+        }
+    }
+
+    /// Encodes messages with CBOR under another sub-protocol, like a codec of an embedder.
+    struct CustomCbor;
+
+    impl Serializer for CustomCbor {
+        fn protocol(&self) -> &str {
+            "wamp.2.cbor.custom"
+        }
+
+        fn binary(&self) -> bool {
+            true
+        }
+
+        fn serialize(&self, message: &Message) -> WampResult<Vec<u8>> {
+            serde_cbor::to_vec(message).map_err(|e| Error::new(ErrorKind::CBORError(e)))
+        }
+
+        fn deserialize(&self, payload: &[u8]) -> WampResult<Message> {
+            serde_cbor::from_slice(payload).map_err(|e| Error::new(ErrorKind::CBORError(e)))
+        }
+    }
+
+    fn publish_bytes() -> Message {
+        Message::Publish(
+            1,
+            PublishOptions::new(false),
+            URI::new("t"),
+            Some(vec![Value::Bytes(vec![1, 2])]),
+            None,
+        )
+    }
+
+    #[test]
+    fn registry() {
+        let mut serializers = Serializers::default();
+        assert_eq!(
+            serializers.protocols(),
+            vec!["wamp.2.cbor", "wamp.2.json", "wamp.2.json.batched", "wamp.2.msgpack",
+                 "wamp.2.msgpack.batched", "wamp.2.ubjson"]
+        );
+        assert_eq!(serializers.by_rawsocket_id(2).unwrap().protocol(), "wamp.2.msgpack");
+        assert!(serializers.by_rawsocket_id(5).is_none());
+        assert!(serializers.get("wamp.2.ids").is_none());
+
+        serializers.register(Arc::new(Ids));
+        let ids = serializers.get("wamp.2.ids").unwrap();
+        assert_eq!(ids.serialize(&Message::Subscribed(42, 7)).unwrap(), b"42");
+        assert_eq!(ids.deserialize(b"42").unwrap(), Message::Subscribed(42, 0));
+    }
+
+    #[test]
+    fn binary() {
+        let serializers = Serializers::default();
+        let message = publish_bytes();
+        let msgpack = serializers.get("wamp.2.msgpack").unwrap();
+        assert_eq!(
+            msgpack.encode(&message).unwrap(),
+            [0x95, 0x10, 0x01, 0x80, 0xA1, b't', 0x91, 0xC4, 0x02, 0x01, 0x02]
+        );
+        let cbor = serializers.get("wamp.2.cbor").unwrap();
+        assert_eq!(
+            cbor.encode(&message).unwrap(),
+            [0x85, 0x10, 0x01, 0xA0, 0x61, b't', 0x81, 0x42, 0x01, 0x02]
+        );
+        let json = serializers.get("wamp.2.json").unwrap();
+        assert_eq!(json.encode(&message).unwrap(), &b"[16,1,{},\"t\",[\"\\u0000AQI=\"]]"[..]);
+        let ubjson = serializers.get("wamp.2.ubjson").unwrap();
+        let payload = ubjson.encode(&message).unwrap();
+        assert!(payload.windows(8).any(|window| window == b"SU\x05\x00AQI="));
+        for protocol in serializers.protocols() {
+            let serializer = serializers.get(protocol).unwrap();
+            let payload = serializer.encode(&message).unwrap();
+            assert_eq!(serializer.deserialize(&payload).unwrap(), message, "{}", protocol);
+        }
+    }
+
+    #[test]
+    fn custom_binary() {
+        let mut serializers = Serializers::default();
+        serializers.register(Arc::new(CustomCbor));
+        let custom = serializers.get("wamp.2.cbor.custom").unwrap();
+        let payload = custom.encode(&publish_bytes()).unwrap();
+        assert_eq!(payload, [0x85, 0x10, 0x01, 0xA0, 0x61, b't', 0x81, 0x42, 0x01, 0x02]);
+        assert_eq!(custom.deserialize(&payload).unwrap(), publish_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let serializers = Serializers::default();
+        let message = Message::Subscribed(58944, 48975938);
+        for protocol in serializers.protocols() {
+            let serializer = serializers.get(protocol).unwrap();
+            let payload = serializer.encode(&message).unwrap();
+            assert_eq!(serializer.deserialize(&payload).unwrap(), message, "{}", protocol);
+        }
+        let json = serializers.get("wamp.2.json.batched").unwrap();
+        assert_eq!(json.split(b"[35,1]\x1e[35,2]\x1e").unwrap(), vec![&b"[35,1]"[..], &b"[35,2]"[..]]);
+    }
+}
@@ -0,0 +1,71 @@
+//! Contains the TLS support of the WebSocket transport, which requires the `ssl` feature.
+use crate::router::auth::TransportDetails;
+use crate::router::config::TlsConfig;
+use openssl::error::ErrorStack;
+use openssl::nid::Nid;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::X509Ref;
+use std::net::TcpStream;
+use ws::{Error as WSError, ErrorKind as WSErrorKind, Result as WSResult};
+
+/// Creates the acceptor for TLS connections from the configured certificate and key.
+pub fn acceptor(config: &TlsConfig) -> Result<SslAcceptor, ErrorStack> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_private_key_file(&config.key, SslFiletype::PEM)?;
+    acceptor.set_certificate_chain_file(&config.certificate)?;
+    acceptor.check_private_key()?;
+    if let Some(ref client_ca) = config.client_ca {
+        acceptor.set_ca_file(client_ca)?;
+        let mut mode = SslVerifyMode::PEER;
+        if config.require_client_certificate {
+            mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+        }
+        acceptor.set_verify(mode);
+    }
+    Ok(acceptor.build())
+}
+
+/// Returns the subject common names and the subject alternative names of a certificate.
+fn names(certificate: &X509Ref) -> Vec<String> {
+    let mut names = certificate.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().as_utf8().ok())
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    if let Some(alt_names) = certificate.subject_alt_names() {
+        names.extend(alt_names.iter().filter_map(|name| {
+            name.dnsname().or_else(|| name.email()).or_else(|| name.uri()).map(str::to_string)
+        }));
+    }
+    names
+}
+
+/// Performs the TLS handshake and records the properties of the connection that can be used
+/// for authentication.
+pub fn accept(
+    acceptor: &SslAcceptor,
+    sock: TcpStream,
+    transport: &mut TransportDetails,
+) -> WSResult<SslStream<TcpStream>> {
+    let stream = acceptor.accept(sock).map_err(|e| {
+        WSError::new(WSErrorKind::Internal, format!("TLS handshake failed: {}", e))
+    })?;
+    // tls-unique is not defined for TLS 1.3.  It is the first Finished message of the
+    // handshake, which the client sends in a full handshake and the router in an abbreviated
+    // one that resumes a TLS session.
+    let ssl = stream.ssl();
+    if ssl.version_str() != "TLSv1.3" {
+        let mut finished = [0u8; 64];
+        let len = if ssl.session_reused() {
+            ssl.finished(&mut finished)
+        } else {
+            ssl.peer_finished(&mut finished)
+        };
+        transport.tls_unique = Some(finished[..len.min(finished.len())].to_vec());
+    }
+    // The certificate has been verified against the client CA during the handshake
+    if let Some(certificate) = ssl.peer_certificate() {
+        transport.client_names = names(&certificate);
+    }
+    Ok(stream)
+}
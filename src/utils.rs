@@ -2,6 +2,15 @@ use rmp::encode::{write_map_len, write_str, ValueWriteError};
 use rmp::Marker;
 use rmp_serde::encode::VariantWriter;
 use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The milliseconds since the epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or(0)
+}
 
 pub struct StructMapWriter;
 